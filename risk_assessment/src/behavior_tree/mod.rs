@@ -0,0 +1,3 @@
+pub mod node;
+pub mod tree;
+pub mod xml;
\ No newline at end of file
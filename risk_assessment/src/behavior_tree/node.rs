@@ -0,0 +1,100 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+impl std::fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NodeStatus::Success => write!(f, "success"),
+            NodeStatus::Failure => write!(f, "failure"),
+            NodeStatus::Running => write!(f, "running"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BtNodeType {
+    // Ticks the children in order, fails as soon as one of them fails
+    Sequence,
+    // Ticks the children in order, succeeds as soon as one of them succeeds
+    Fallback,
+    // Ticks all children, succeeds when at least this many of them have succeeded
+    Parallel(usize),
+    // Succeeds if the predicate holds in the current state, fails otherwise
+    Condition(String),
+    // Executes the operation of the model with the given name
+    Action(String),
+}
+
+// Nodes remember their status once they have succeeded or failed, so that
+// finished children are not ticked again until the tree is reset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BtNode {
+    pub name: String,
+    pub node_type: BtNodeType,
+    pub children: Vec<BtNode>,
+    pub status: Option<NodeStatus>,
+}
+
+impl BtNode {
+    fn new(name: &str, node_type: BtNodeType, children: Vec<BtNode>) -> BtNode {
+        BtNode {
+            name: name.to_string(),
+            node_type,
+            children,
+            status: None,
+        }
+    }
+
+    pub fn sequence(name: &str, children: Vec<BtNode>) -> BtNode {
+        BtNode::new(name, BtNodeType::Sequence, children)
+    }
+
+    pub fn fallback(name: &str, children: Vec<BtNode>) -> BtNode {
+        BtNode::new(name, BtNodeType::Fallback, children)
+    }
+
+    pub fn parallel(name: &str, success_threshold: usize, children: Vec<BtNode>) -> BtNode {
+        BtNode::new(name, BtNodeType::Parallel(success_threshold), children)
+    }
+
+    pub fn condition(name: &str, predicate: &str) -> BtNode {
+        BtNode::new(name, BtNodeType::Condition(predicate.to_string()), vec![])
+    }
+
+    pub fn action(name: &str, operation: &str) -> BtNode {
+        BtNode::new(name, BtNodeType::Action(operation.to_string()), vec![])
+    }
+
+    pub fn reset(&mut self) {
+        self.status = None;
+        self.children.iter_mut().for_each(|child| child.reset());
+    }
+
+    // All operations referenced by the action leaves in this subtree
+    pub fn operations(&self) -> Vec<String> {
+        let mut operations = match &self.node_type {
+            BtNodeType::Action(operation) => vec![operation.clone()],
+            _ => vec![],
+        };
+        for child in &self.children {
+            operations.extend(child.operations());
+        }
+        operations
+    }
+
+    // All predicates used by the condition leaves in this subtree
+    pub fn predicates(&self) -> Vec<String> {
+        let mut predicates = match &self.node_type {
+            BtNodeType::Condition(predicate) => vec![predicate.clone()],
+            _ => vec![],
+        };
+        for child in &self.children {
+            predicates.extend(child.predicates());
+        }
+        predicates
+    }
+}
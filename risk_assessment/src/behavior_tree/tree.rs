@@ -0,0 +1,326 @@
+use crate::*;
+use micro_sp::*;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Duration};
+
+pub struct BehaviorTree {
+    pub name: String,
+    pub root: BtNode,
+    operations: HashMap<String, Operation>,
+    conditions: HashMap<String, Transition>,
+    executing: HashSet<String>,
+}
+
+impl BehaviorTree {
+    // Fails if an action leaf refers to an operation that is not in the model,
+    // or if a condition doesn't parse against the state
+    pub fn new(name: &str, root: BtNode, model: &Model, state: &State) -> Result<BehaviorTree, String> {
+        let mut operations = HashMap::new();
+        let mut unknown = vec![];
+        for op_name in root.operations() {
+            match model.operations.iter().find(|o| o.name == op_name) {
+                Some(o) => {
+                    operations.insert(op_name, o.clone());
+                }
+                None => unknown.push(op_name),
+            }
+        }
+        if !unknown.is_empty() {
+            return Err(format!(
+                "Behavior tree '{}' refers to operations that are not in model '{}': {}.",
+                name,
+                model.name,
+                unknown.join(", ")
+            ));
+        }
+
+        // The parser panics on an invalid predicate, i.e. one from an imported XML
+        let invalid: Vec<String> = root
+            .predicates()
+            .iter()
+            .filter_map(|predicate| parse_goal(predicate, state).err())
+            .collect();
+        if !invalid.is_empty() {
+            return Err(format!(
+                "Behavior tree '{}' has invalid conditions: {}",
+                name,
+                invalid.join(" ")
+            ));
+        }
+
+        let mut conditions = HashMap::new();
+        for predicate in root.predicates() {
+            let condition = Transition::parse(
+                &format!("condition_{}", predicate),
+                &predicate,
+                "true",
+                Vec::<&str>::new(),
+                Vec::<&str>::new(),
+                state,
+            );
+            conditions.insert(predicate, condition);
+        }
+
+        Ok(BehaviorTree {
+            name: name.to_string(),
+            root,
+            operations,
+            conditions,
+            executing: HashSet::new(),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.root.reset();
+        self.executing.clear();
+    }
+
+    // Ticks the tree once and returns the status of the root and the updated state
+    pub fn tick(&mut self, state: &State) -> (NodeStatus, State) {
+        let BehaviorTree {
+            root,
+            operations,
+            conditions,
+            executing,
+            ..
+        } = self;
+        tick_node(root, state.clone(), operations, conditions, executing)
+    }
+}
+
+fn tick_node(
+    node: &mut BtNode,
+    state: State,
+    operations: &HashMap<String, Operation>,
+    conditions: &HashMap<String, Transition>,
+    executing: &mut HashSet<String>,
+) -> (NodeStatus, State) {
+    match node.status {
+        Some(NodeStatus::Success) | Some(NodeStatus::Failure) => {
+            return (node.status.unwrap(), state)
+        }
+        _ => (),
+    }
+
+    let mut state = state;
+    let status = match &node.node_type {
+        BtNodeType::Sequence => {
+            let mut status = NodeStatus::Success;
+            for child in node.children.iter_mut() {
+                let (child_status, new_state) =
+                    tick_node(child, state, operations, conditions, executing);
+                state = new_state;
+                if child_status != NodeStatus::Success {
+                    status = child_status;
+                    break;
+                }
+            }
+            status
+        }
+        BtNodeType::Fallback => {
+            let mut status = NodeStatus::Failure;
+            for child in node.children.iter_mut() {
+                let (child_status, new_state) =
+                    tick_node(child, state, operations, conditions, executing);
+                state = new_state;
+                if child_status != NodeStatus::Failure {
+                    status = child_status;
+                    break;
+                }
+            }
+            status
+        }
+        BtNodeType::Parallel(success_threshold) => {
+            let mut successes = 0;
+            let mut failures = 0;
+            for child in node.children.iter_mut() {
+                let (child_status, new_state) =
+                    tick_node(child, state, operations, conditions, executing);
+                state = new_state;
+                match child_status {
+                    NodeStatus::Success => successes += 1,
+                    NodeStatus::Failure => failures += 1,
+                    NodeStatus::Running => (),
+                }
+            }
+            if successes >= *success_threshold {
+                NodeStatus::Success
+            } else if failures > node.children.len().saturating_sub(*success_threshold) {
+                NodeStatus::Failure
+            } else {
+                NodeStatus::Running
+            }
+        }
+        BtNodeType::Condition(predicate) => match conditions.get(predicate) {
+            Some(condition) if condition.clone().eval_planning(&state) => NodeStatus::Success,
+            _ => NodeStatus::Failure,
+        },
+        BtNodeType::Action(op_name) => match operations.get(op_name) {
+            Some(operation) => {
+                let (status, new_state) = tick_operation(operation, &state, executing);
+                state = new_state;
+                status
+            }
+            None => NodeStatus::Failure,
+        },
+    };
+
+    node.status = Some(status);
+    (status, state)
+}
+
+// Same protocol as the operation runner: start when the precondition holds,
+// succeed on the postcondition and fail on the fail transition
+fn tick_operation(
+    operation: &Operation,
+    state: &State,
+    executing: &mut HashSet<String>,
+) -> (NodeStatus, State) {
+    if executing.contains(&operation.name) {
+        if let Some(complete) = operation
+            .postconditions
+            .iter()
+            .find(|t| t.clone().eval_running(state))
+        {
+            executing.remove(&operation.name);
+            return (NodeStatus::Success, complete.clone().take_running(state));
+        }
+        if let Some(fail) = operation
+            .fail_transitions
+            .iter()
+            .find(|t| t.clone().eval_running(state))
+        {
+            executing.remove(&operation.name);
+            return (NodeStatus::Failure, fail.clone().take_running(state));
+        }
+        (NodeStatus::Running, state.clone())
+    } else {
+        match operation
+            .preconditions
+            .iter()
+            .find(|t| t.clone().eval_running(state))
+        {
+            Some(start) => {
+                executing.insert(operation.name.clone());
+                (NodeStatus::Running, start.clone().take_running(state))
+            }
+            None => (NodeStatus::Failure, state.clone()),
+        }
+    }
+}
+
+// Ticks the tree against the state manager until the root succeeds or fails
+pub async fn behavior_tree_ticker(
+    tree: &mut BehaviorTree,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<NodeStatus, Box<dyn Error>> {
+    let mut interval = interval(Duration::from_millis(ticker_rate));
+    log_info!("behavior_tree", "Ticking '{}'.", tree.name);
+    loop {
+        let (response_tx, response_rx) = oneshot::channel();
+        command_sender.send(Command::GetState(response_tx)).await?;
+        let state = response_rx.await?;
+
+        let (status, new_state) = tree.tick(&state);
+
+        let modified_state = state.get_diff_partial_state(&new_state);
+        command_sender
+            .send(Command::SetPartialState(modified_state))
+            .await?;
+
+        if status != NodeStatus::Running {
+            log_info!("behavior_tree", "Tree '{}' finished with {}.", tree.name, status);
+            return Ok(status);
+        }
+        interval.tick().await;
+    }
+}
+
+#[cfg(test)]
+fn emulate_gantry(state: &State, success: bool) -> State {
+    if state.get_or_default_bool("test", "gantry_request_trigger") {
+        state
+            .update("gantry_request_trigger", false.to_spvalue())
+            .update(
+                "gantry_request_state",
+                match success {
+                    true => ServiceRequestState::Succeeded.to_string(),
+                    false => ServiceRequestState::Failed.to_string(),
+                }
+                .to_spvalue(),
+            )
+    } else {
+        state.clone()
+    }
+}
+
+#[test]
+fn test_behavior_tree_sequence() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    let root = BtNode::sequence(
+        "prepare_gantry",
+        vec![
+            BtNode::condition("not_calibrated", "var:gantry_calibrated_estimated == UNKNOWN"),
+            BtNode::action("unlock", "op_gantry_unlock"),
+            BtNode::action("calibrate", "op_gantry_calibrate"),
+        ],
+    );
+    let mut tree = BehaviorTree::new("prepare_gantry", root, &model, &state).unwrap();
+
+    let mut state = state;
+    let mut status = NodeStatus::Running;
+    for _ in 0..10 {
+        let (new_status, new_state) = tree.tick(&state);
+        status = new_status;
+        state = emulate_gantry(&new_state, true);
+        if status != NodeStatus::Running {
+            break;
+        }
+    }
+    assert_eq!(status, NodeStatus::Success);
+    assert_eq!(state.get_value("gantry_calibrated_estimated"), true.to_spvalue());
+    assert_eq!(state.get_value("gantry_locked_estimated"), false.to_spvalue());
+}
+
+#[test]
+fn test_behavior_tree_fallback() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    // The lock fails, the recovery branch unlocks instead
+    let root = BtNode::fallback(
+        "lock_or_unlock",
+        vec![
+            BtNode::action("lock", "op_gantry_lock"),
+            BtNode::action("unlock", "op_gantry_unlock"),
+        ],
+    );
+    let mut tree = BehaviorTree::new("lock_or_unlock", root, &model, &state).unwrap();
+
+    let mut state = state;
+    let mut status = NodeStatus::Running;
+    let mut attempts = 0;
+    for _ in 0..10 {
+        let (new_status, new_state) = tree.tick(&state);
+        status = new_status;
+        attempts += 1;
+        state = emulate_gantry(&new_state, attempts > 1);
+        if status != NodeStatus::Running {
+            break;
+        }
+    }
+    assert_eq!(status, NodeStatus::Success);
+    assert_eq!(state.get_value("gantry_locked_estimated"), false.to_spvalue());
+
+    let root = BtNode::action("teleport", "op_robot_teleport_to_a");
+    assert!(BehaviorTree::new("teleport", root, &model, &state).is_err());
+    let root = BtNode::condition("typo", "var:gantry_locked_estimated ==");
+    assert!(BehaviorTree::new("typo", root, &model, &state).is_err());
+}
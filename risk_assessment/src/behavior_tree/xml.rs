@@ -0,0 +1,279 @@
+use crate::*;
+use micro_sp::*;
+
+// BehaviorTree.CPP (v4) / Groot XML format.
+// Action leaves use the operation name as their ID, conditions use the
+// "Predicate" ID with the predicate as a port. Risk annotations are stored
+// as ports on the actions, with ';' separated values, one per failure mode.
+// A ';' inside a value is written as '\;' and a '\' as '\\':
+//
+// <Action ID="op_gantry_lock" name="lock"
+//         failure_causes="generic_failure;violation"
+//         severity="3;6" occurrence="3;2" detection="2;3"
+//         effects="...;..." mitigations="...;..."/>
+
+static CONDITION_ID: &'static str = "Predicate";
+static RISK_PORTS: [&'static str; 6] = [
+    "failure_causes",
+    "severity",
+    "occurrence",
+    "detection",
+    "effects",
+    "mitigations",
+];
+
+fn escape(value: &str) -> String {
+    value
+        .replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+fn node_to_xml(node: &BtNode, fmea: &FmeaTable, depth: usize, xml: &mut String) {
+    let indent = "  ".repeat(depth);
+    let children = |xml: &mut String| {
+        for child in &node.children {
+            node_to_xml(child, fmea, depth + 1, xml);
+        }
+    };
+    match &node.node_type {
+        BtNodeType::Sequence => {
+            xml.push_str(&format!("{}<Sequence name=\"{}\">\n", indent, escape(&node.name)));
+            children(xml);
+            xml.push_str(&format!("{}</Sequence>\n", indent));
+        }
+        BtNodeType::Fallback => {
+            xml.push_str(&format!("{}<Fallback name=\"{}\">\n", indent, escape(&node.name)));
+            children(xml);
+            xml.push_str(&format!("{}</Fallback>\n", indent));
+        }
+        BtNodeType::Parallel(success_count) => {
+            xml.push_str(&format!(
+                "{}<Parallel name=\"{}\" success_count=\"{}\" failure_count=\"{}\">\n",
+                indent,
+                escape(&node.name),
+                success_count,
+                node.children.len().saturating_sub(*success_count) + 1
+            ));
+            children(xml);
+            xml.push_str(&format!("{}</Parallel>\n", indent));
+        }
+        BtNodeType::Condition(predicate) => {
+            xml.push_str(&format!(
+                "{}<Condition ID=\"{}\" name=\"{}\" predicate=\"{}\"/>\n",
+                indent,
+                CONDITION_ID,
+                escape(&node.name),
+                escape(predicate)
+            ));
+        }
+        BtNodeType::Action(operation) => {
+            let mut risk = String::new();
+            if let Some(op) = fmea.get_operation(operation) {
+                for port in RISK_PORTS {
+                    risk.push_str(&format!(
+                        " {}=\"{}\"",
+                        port,
+                        escape(&risk_port_value(&op.failure_modes, port))
+                    ));
+                }
+            }
+            xml.push_str(&format!(
+                "{}<Action ID=\"{}\" name=\"{}\"{}/>\n",
+                indent,
+                escape(operation),
+                escape(&node.name),
+                risk
+            ));
+        }
+    }
+}
+
+fn risk_port_value(failure_modes: &[FailureMode], port: &str) -> String {
+    failure_modes
+        .iter()
+        .map(|fm| match port {
+            "failure_causes" => fm.cause.clone(),
+            "severity" => fm.severity.to_string(),
+            "occurrence" => fm.occurrence.to_string(),
+            "detection" => fm.detection.to_string(),
+            "effects" => fm.effect.clone(),
+            _ => fm.mitigation.clone(),
+        })
+        .map(|value| value.replace('\\', "\\\\").replace(';', "\\;"))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+// Exports the tree together with the risk annotations of its operations.
+// The TreeNodesModel section declares the operations so that Groot can edit them.
+pub fn behavior_tree_to_xml(name: &str, root: &BtNode, fmea: &FmeaTable) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<root BTCPP_format=\"4\" main_tree_to_execute=\"{}\">\n",
+        escape(name)
+    );
+    xml.push_str(&format!("  <BehaviorTree ID=\"{}\">\n", escape(name)));
+    node_to_xml(root, fmea, 2, &mut xml);
+    xml.push_str("  </BehaviorTree>\n");
+
+    xml.push_str("  <TreeNodesModel>\n");
+    xml.push_str(&format!(
+        "    <Condition ID=\"{}\">\n      <input_port name=\"predicate\"/>\n    </Condition>\n",
+        CONDITION_ID
+    ));
+    let mut operations = root.operations();
+    operations.sort();
+    operations.dedup();
+    for operation in operations {
+        xml.push_str(&format!("    <Action ID=\"{}\">\n", escape(&operation)));
+        for port in RISK_PORTS {
+            xml.push_str(&format!("      <input_port name=\"{}\"/>\n", port));
+        }
+        xml.push_str("    </Action>\n");
+    }
+    xml.push_str("  </TreeNodesModel>\n");
+    xml.push_str("</root>\n");
+    xml
+}
+
+fn split_port(node: &roxmltree::Node, port: &str) -> Vec<String> {
+    let value = match node.attribute(port) {
+        Some(value) if !value.is_empty() => value,
+        _ => return vec![],
+    };
+    let mut values = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => values.last_mut().unwrap().extend(chars.next()),
+            ';' => values.push(String::new()),
+            c => values.last_mut().unwrap().push(c),
+        }
+    }
+    values
+}
+
+fn parse_score(node: &roxmltree::Node, port: &str, index: usize) -> Result<u8, String> {
+    let values = split_port(node, port);
+    let value = values.get(index).ok_or(format!(
+        "Action '{}' has fewer '{}' values than failure causes.",
+        node.attribute("ID").unwrap_or(""),
+        port
+    ))?;
+    match value.trim().parse::<u8>() {
+        Ok(score) if (MIN_SCORE..=MAX_SCORE).contains(&score) => Ok(score),
+        _ => Err(format!(
+            "Action '{}' has invalid {} '{}', expected {}..{}.",
+            node.attribute("ID").unwrap_or(""),
+            port,
+            value,
+            MIN_SCORE,
+            MAX_SCORE
+        )),
+    }
+}
+
+fn node_from_xml(node: &roxmltree::Node, fmea: &mut FmeaTable) -> Result<BtNode, String> {
+    let tag = node.tag_name().name();
+    let name = node.attribute("name").unwrap_or(tag);
+    match tag {
+        "Sequence" | "SequenceWithMemory" => Ok(BtNode::sequence(name, element_children(node, fmea)?)),
+        "Fallback" => Ok(BtNode::fallback(name, element_children(node, fmea)?)),
+        // Reactive nodes tick their conditions again while a child is running,
+        // the sequences and fallbacks of the runner don't
+        "ReactiveSequence" | "ReactiveFallback" => Err(format!(
+            "Unsupported reactive node '{}' ({}), use Sequence or Fallback.",
+            name, tag
+        )),
+        "Parallel" => {
+            let children = element_children(node, fmea)?;
+            let success_count = node
+                .attribute("success_count")
+                .or(node.attribute("success_threshold"))
+                .map(|count| count.parse::<i64>())
+                .unwrap_or(Ok(-1))
+                .map_err(|e| format!("Parallel '{}' has an invalid success_count: {}.", name, e))?;
+            // BehaviorTree.CPP uses -1 for "all children"
+            let success_count = match success_count {
+                n if n < 0 => children.len(),
+                n => n as usize,
+            };
+            Ok(BtNode::parallel(name, success_count, children))
+        }
+        "Condition" | "Predicate" => match node.attribute("predicate") {
+            Some(predicate) => Ok(BtNode::condition(name, predicate)),
+            None => Err(format!("Condition '{}' has no predicate.", name)),
+        },
+        // Groot2 also writes actions with the ID as the tag, i.e. <op_gantry_lock/>
+        _ if tag == "Action" || !node.children().any(|child| child.is_element()) => {
+            let operation = match tag {
+                "Action" => node
+                    .attribute("ID")
+                    .ok_or(format!("Action '{}' has no ID.", name))?,
+                _ => tag,
+            };
+            let causes = split_port(node, "failure_causes");
+            let effects = split_port(node, "effects");
+            let mitigations = split_port(node, "mitigations");
+            let mut failure_modes = vec![];
+            for (i, cause) in causes.iter().enumerate() {
+                failure_modes.push(FailureMode::try_new(
+                    cause,
+                    parse_score(node, "severity", i)?,
+                    parse_score(node, "occurrence", i)?,
+                    parse_score(node, "detection", i)?,
+                    effects.get(i).map(|e| e.as_str()).unwrap_or(""),
+                    mitigations.get(i).map(|m| m.as_str()).unwrap_or(""),
+                )?);
+            }
+            if !failure_modes.is_empty() && fmea.get_operation(operation).is_none() {
+                fmea.add(operation, failure_modes);
+            }
+            Ok(BtNode::action(node.attribute("name").unwrap_or(operation), operation))
+        }
+        _ => Err(format!(
+            "Unsupported node '{}' at position {}.",
+            tag,
+            node.range().start
+        )),
+    }
+}
+
+fn element_children(node: &roxmltree::Node, fmea: &mut FmeaTable) -> Result<Vec<BtNode>, String> {
+    node.children()
+        .filter(|child| child.is_element())
+        .map(|child| node_from_xml(&child, fmea))
+        .collect()
+}
+
+// Loads the main tree and the risk annotations of its actions. All operations
+// must exist in the model, the unknown ones are reported together.
+pub fn behavior_tree_from_xml(
+    xml: &str,
+    model: &Model,
+    state: &State,
+) -> Result<(BehaviorTree, FmeaTable), String> {
+    let document = roxmltree::Document::parse(xml).map_err(|e| format!("Invalid XML: {}.", e))?;
+    let root = document.root_element();
+    let trees: Vec<roxmltree::Node> = root
+        .children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "BehaviorTree")
+        .collect();
+    let tree = match root.attribute("main_tree_to_execute") {
+        Some(main) => trees.iter().find(|t| t.attribute("ID") == Some(main)),
+        None => trees.first(),
+    }
+    .ok_or("No main BehaviorTree found.".to_string())?;
+    let name = tree.attribute("ID").unwrap_or(&model.name);
+
+    let mut fmea = FmeaTable::new(&model.name);
+    let top: Vec<roxmltree::Node> = tree.children().filter(|n| n.is_element()).collect();
+    let bt_root = match top.as_slice() {
+        [single] => node_from_xml(single, &mut fmea)?,
+        _ => return Err(format!("BehaviorTree '{}' must have exactly one root node.", name)),
+    };
+
+    let behavior_tree = BehaviorTree::new(name, bt_root, model, state)?;
+    Ok((behavior_tree, fmea))
+}
@@ -0,0 +1,102 @@
+use micro_sp::*;
+use std::collections::HashMap;
+
+// Identifies the emulation settings that apply to a command. Moves can also
+// be emulated per position, the key is then i.e. "gantry_move_pipe_blue_box".
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EmulationKey {
+    pub device: String,
+    pub command: String,
+    pub position: Option<String>,
+}
+
+impl EmulationKey {
+    pub fn new(device: &str, command: &str, position: Option<&str>) -> EmulationKey {
+        EmulationKey {
+            device: device.to_string(),
+            command: command.to_string(),
+            position: position.map(|p| p.to_string()),
+        }
+    }
+
+    // Prefixes of the emulation variables, from the most to the least specific
+    pub fn prefixes(&self) -> Vec<String> {
+        let mut prefixes = vec![];
+        if let Some(position) = &self.position {
+            prefixes.push(format!("{}_{}_{}", self.device, self.command, position));
+        }
+        prefixes.push(format!("{}_{}", self.device, self.command));
+        prefixes.push(self.device.clone());
+        prefixes
+    }
+}
+
+impl std::fmt::Display for EmulationKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.prefixes()[0])
+    }
+}
+
+// Only moves are emulated per position
+pub fn emulation_key_for_command(device: &str, command: &str, position: &str) -> EmulationKey {
+    match command {
+        "move" => EmulationKey::new(device, command, Some(position)),
+        _ => EmulationKey::new(device, command, None),
+    }
+}
+
+// The emulation keys of the commands that the operations issue, found by
+// taking their start transitions and looking at the device command variables
+pub fn operation_emulation_keys(
+    model: &Model,
+    devices: &[String],
+    state: &State,
+) -> HashMap<String, EmulationKey> {
+    let target = "operation_emulation_keys";
+    let mut keys = HashMap::new();
+    for o in &model.operations {
+        for device in devices {
+            let trigger = format!("{}_request_trigger", device);
+            let idle = state.update(&trigger, false.to_spvalue());
+            for t in &o.preconditions {
+                let started = t.clone().take_planning(&idle);
+                if started.get_or_default_bool(target, &trigger) {
+                    let command =
+                        started.get_or_default_string(target, &format!("{}_command_command", device));
+                    let position = match command.as_str() {
+                        "move" => started
+                            .get_or_default_string(target, &format!("{}_position_command", device)),
+                        _ => String::new(),
+                    };
+                    keys.insert(
+                        o.name.clone(),
+                        emulation_key_for_command(device, &command, &position),
+                    );
+                }
+            }
+        }
+    }
+    keys
+}
+
+#[test]
+fn test_operation_emulation_keys() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let devices = vec!["gantry".to_string(), "robot".to_string(), "scanner".to_string()];
+    let keys = operation_emulation_keys(&model, &devices, &state);
+
+    assert_eq!(
+        keys.get("op_gantry_move_to_home").map(|k| k.to_string()),
+        Some("gantry_move_home".to_string())
+    );
+    assert_eq!(
+        keys.get("op_robot_mount_gripper_tool").map(|k| k.to_string()),
+        Some("robot_mount".to_string())
+    );
+    assert_eq!(
+        keys.get("op_scan_pipe_blue_box").map(|k| k.to_string()),
+        Some("scanner_scan".to_string())
+    );
+}
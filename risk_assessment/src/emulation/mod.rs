@@ -0,0 +1,2 @@
+pub mod key;
+pub mod profile;
\ No newline at end of file
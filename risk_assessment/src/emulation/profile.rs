@@ -0,0 +1,393 @@
+use crate::*;
+use micro_sp::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+// Emulation profiles describe how the emulated devices behave, per device and
+// per command, so that scenarios can be switched without recompiling:
+//
+// name = "worn_gripper"
+//
+// [devices.robot]
+// failure_probability = 5
+//
+// [devices.robot.commands.pick]
+// execution_time = { distribution = "log_normal", milliseconds = 3000, deviation = 1000 }
+// failure_probability = 40
+// failure_causes = { mis_grip = 3, dropped_item = 1 }
+//
+// [devices.gantry.commands.move.positions.pipe_blue_box]
+// failure_probability = 20
+//
+// [devices.gantry.commands.calibrate]
+// failure_causes_from_fmea = true
+// correlations = { detected_drift = { collision = 300 } }
+//
+// With failure_causes_from_fmea the causes are weighted with their FMEA occurrence
+// ratings, taken from the operations that issue the command. A correlation makes
+// a cause more (or less) likely, in percent, right after the device failed with
+// another cause.
+//
+// Device settings go to the {device}_emulate_* variables, command settings to
+// the {device}_{command}_emulate_* variables and position settings to the
+// {device}_move_{position}_emulate_* variables. The client tickers use the
+// most specific ones, see EmulationKey.
+
+// The milliseconds are the mean of the distribution, the deviation is the standard
+// deviation for normal and log_normal, and the maximum jitter for fixed_jitter.
+// A device that responds after_timeout takes the milliseconds on top of the request timeout.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ExecutionTime {
+    // none, exact, random, normal, log_normal, exponential, fixed_jitter, never_responds, after_timeout
+    pub distribution: String,
+    #[serde(default)]
+    pub milliseconds: i64,
+    #[serde(default)]
+    pub deviation: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct EmulationSettings {
+    pub execution_time: Option<ExecutionTime>,
+    pub failure_probability: Option<i64>,              // percentage 0..100
+    pub failure_causes: Option<BTreeMap<String, u32>>, // cause -> weight
+    pub silent_failure_probability: Option<i64>,       // percentage 0..100
+    pub failure_causes_from_fmea: Option<bool>,
+    pub correlations: Option<BTreeMap<String, BTreeMap<String, u32>>>, // after -> cause -> percentage
+    // Keys that are none of the above, i.e. misspelled ones, they are an error when
+    // the profile is applied. Flattened structs can't deny unknown fields.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct CommandProfile {
+    #[serde(flatten)]
+    pub settings: EmulationSettings,
+    #[serde(default)]
+    pub positions: BTreeMap<String, EmulationSettings>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct DeviceProfile {
+    #[serde(flatten)]
+    pub defaults: EmulationSettings,
+    #[serde(default)]
+    pub commands: BTreeMap<String, CommandProfile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct EmulationProfile {
+    pub name: String,
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceProfile>,
+}
+
+fn set(state: State, name: &str, value: SPValue) -> State {
+    match state.state.contains_key(name) {
+        true => state.update(name, value),
+        false => {
+            let var = match value {
+                SPValue::Array(_, _) => av!(name),
+                _ => iv!(name),
+            };
+            state.add(assign!(var, value))
+        }
+    }
+}
+
+// Maps a percentage to the DONT_EMULATE / ALWAYS / RANDOM modes of the Emulation message
+fn probability_mode(name: &str, percentage: i64) -> Result<(i64, i64), String> {
+    match percentage {
+        0 => Ok((0, 0)),
+        100 => Ok((1, 100)),
+        p if (1..100).contains(&p) => Ok((2, p)),
+        p => Err(format!("Invalid {} {}, expected a percentage 0..100.", name, p)),
+    }
+}
+
+// Highest occurrence rating of every cause over the operations that issue the command
+fn fmea_failure_causes(
+    prefix: &str,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+) -> Result<BTreeMap<String, u32>, String> {
+    let mut causes: BTreeMap<String, u32> = BTreeMap::new();
+    for (operation, key) in keys {
+        if !key.prefixes().contains(&prefix.to_string()) {
+            continue;
+        }
+        if let Some(risk) = fmea.get_operation(operation) {
+            for fm in &risk.failure_modes {
+                let weight = causes.entry(fm.cause.clone()).or_default();
+                *weight = (*weight).max(fm.occurrence as u32);
+            }
+        }
+    }
+    match causes.is_empty() {
+        true => Err(format!("No FMEA failure modes found for {}.", prefix)),
+        false => Ok(causes),
+    }
+}
+
+impl EmulationSettings {
+    // Only the settings that are given are written to the state
+    fn apply(
+        &self,
+        prefix: &str,
+        state: &State,
+        fmea: &FmeaTable,
+        keys: &HashMap<String, EmulationKey>,
+    ) -> Result<State, String> {
+        if !self.unknown.is_empty() {
+            return Err(format!(
+                "Unknown emulation settings for {}: {}.",
+                prefix,
+                self.unknown.keys().cloned().collect::<Vec<String>>().join(", ")
+            ));
+        }
+        let mut state = state.clone();
+        if let Some(execution_time) = &self.execution_time {
+            let mode = match execution_time.distribution.as_str() {
+                "none" => 0,
+                "exact" => 1,
+                "random" => 2,
+                "normal" => 3,
+                "log_normal" => 4,
+                "exponential" => 5,
+                "fixed_jitter" => 6,
+                "never_responds" => 7,
+                "after_timeout" => 8,
+                other => return Err(format!("Unknown execution time distribution '{}' for {}.", other, prefix)),
+            };
+            state = set(state, &format!("{prefix}_emulate_execution_time"), mode.to_spvalue());
+            if execution_time.milliseconds < 0 || execution_time.deviation < 0 {
+                return Err(format!("Negative execution time for {}.", prefix));
+            }
+            state = set(state, &format!("{prefix}_emulated_execution_time"), execution_time.milliseconds.to_spvalue());
+            state = set(state, &format!("{prefix}_emulated_execution_time_deviation"), execution_time.deviation.to_spvalue());
+        }
+        if let Some(probability) = self.failure_probability {
+            let (mode, rate) = probability_mode("failure probability", probability)?;
+            state = set(state, &format!("{prefix}_emulate_failure_rate"), mode.to_spvalue());
+            state = set(state, &format!("{prefix}_emulated_failure_rate"), rate.to_spvalue());
+        }
+        if let Some(probability) = self.silent_failure_probability {
+            let (mode, rate) = probability_mode("silent failure probability", probability)?;
+            state = set(state, &format!("{prefix}_emulate_silent_failure"), mode.to_spvalue());
+            state = set(state, &format!("{prefix}_emulated_silent_failure_rate"), rate.to_spvalue());
+        }
+        let causes = match (&self.failure_causes, self.failure_causes_from_fmea) {
+            (Some(_), Some(true)) => {
+                return Err(format!(
+                    "Both failure_causes and failure_causes_from_fmea are set for {}.",
+                    prefix
+                ))
+            }
+            (_, Some(true)) => Some(fmea_failure_causes(prefix, fmea, keys)?),
+            (causes, _) => causes.clone(),
+        };
+        if let Some(causes) = &causes {
+            if causes.values().all(|weight| *weight == 0) {
+                return Err(format!("Failure causes for {} need at least one positive weight.", prefix));
+            }
+            let mode: i64 = match causes.len() {
+                1 => 1,
+                _ => 2,
+            };
+            state = set(state, &format!("{prefix}_emulate_failure_cause"), mode.to_spvalue());
+            state = set(
+                state,
+                &format!("{prefix}_emulated_failure_cause"),
+                SPValue::Array(SPValueType::String, causes.keys().map(|c| c.to_spvalue()).collect()),
+            );
+            state = set(
+                state,
+                &format!("{prefix}_emulated_failure_cause_weights"),
+                SPValue::Array(SPValueType::Int64, causes.values().map(|w| (*w as i64).to_spvalue()).collect()),
+            );
+        }
+        if let Some(correlations) = &self.correlations {
+            let (mut after, mut cause, mut factor) = (vec![], vec![], vec![]);
+            for (previous, correlated) in correlations {
+                for (correlated_cause, percentage) in correlated {
+                    if let Some(causes) = &causes {
+                        if !causes.contains_key(correlated_cause) {
+                            return Err(format!(
+                                "Correlated failure cause '{}' for {} is not one of its failure causes.",
+                                correlated_cause, prefix
+                            ));
+                        }
+                    }
+                    after.push(previous.to_spvalue());
+                    cause.push(correlated_cause.to_spvalue());
+                    factor.push((*percentage as i64).to_spvalue());
+                }
+            }
+            state = set(state, &format!("{prefix}_emulated_correlation_after"), SPValue::Array(SPValueType::String, after));
+            state = set(state, &format!("{prefix}_emulated_correlation_cause"), SPValue::Array(SPValueType::String, cause));
+            state = set(state, &format!("{prefix}_emulated_correlation_factor"), SPValue::Array(SPValueType::Int64, factor));
+        }
+        Ok(state)
+    }
+}
+
+impl EmulationProfile {
+    pub fn from_toml(toml: &str) -> Result<EmulationProfile, String> {
+        toml::from_str(toml).map_err(|e| format!("Invalid emulation profile: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<EmulationProfile, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read emulation profile '{}': {}.", path, e))?;
+        EmulationProfile::from_toml(&toml)
+    }
+
+    // Pushes the profile into the state, devices must have emulation variables.
+    // The FMEA and the emulation keys of the operations are only used for
+    // settings with failure_causes_from_fmea, see operation_emulation_keys.
+    pub fn apply(
+        &self,
+        state: &State,
+        fmea: &FmeaTable,
+        keys: &HashMap<String, EmulationKey>,
+    ) -> Result<State, String> {
+        let mut state = state.clone();
+        for (device, profile) in &self.devices {
+            if !state.state.contains_key(&format!("{}_emulate_failure_rate", device)) {
+                return Err(format!(
+                    "Emulation profile '{}' refers to unknown device '{}'.",
+                    self.name, device
+                ));
+            }
+            state = profile.defaults.apply(device, &state, fmea, keys)?;
+            for (command, command_profile) in &profile.commands {
+                if !device_commands(device).contains(&command.as_str()) {
+                    return Err(format!(
+                        "Emulation profile '{}' refers to unknown command '{}' of '{}', expected one of: {}.",
+                        self.name,
+                        command,
+                        device,
+                        device_commands(device).join(", ")
+                    ));
+                }
+                let key = EmulationKey::new(device, command, None);
+                state = command_profile.settings.apply(&key.to_string(), &state, fmea, keys)?;
+                if !command_profile.positions.is_empty() && command != "move" {
+                    return Err(format!(
+                        "Emulation profile '{}' sets positions for '{}', only moves are emulated per position.",
+                        self.name, key
+                    ));
+                }
+                for (position, settings) in &command_profile.positions {
+                    let key = EmulationKey::new(device, command, Some(position));
+                    state = settings.apply(&key.to_string(), &state, fmea, keys)?;
+                }
+            }
+        }
+        Ok(state)
+    }
+}
+
+// Replaces the emulation of a running state with the profile applied to the initial
+// state, or with the emulation of the initial state if there is no profile. Emulation
+// variables of an earlier profile that the new one doesn't set become UNKNOWN.
+pub fn switch_emulation_profile(
+    initial_state: &State,
+    state: &State,
+    profile: Option<&EmulationProfile>,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+) -> Result<State, String> {
+    let emulation = match profile {
+        Some(profile) => profile.apply(initial_state, fmea, keys)?,
+        None => initial_state.clone(),
+    };
+    let mut new_state = state.clone();
+    for variable in state.state.keys().filter(|v| v.contains("_emulate")) {
+        let value = match emulation.state.get(variable) {
+            Some(assignment) => assignment.val.clone(),
+            None => SPValue::UNKNOWN,
+        };
+        new_state = new_state.update(variable, value);
+    }
+    for (variable, assignment) in &emulation.state {
+        if variable.contains("_emulate") && !new_state.state.contains_key(variable) {
+            new_state = new_state.add(assignment.clone());
+        }
+    }
+    Ok(new_state)
+}
+
+#[test]
+fn test_emulation_profile() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, fmea) = crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let keys = operation_emulation_keys(&model, &vec!["gantry".to_string(), "robot".to_string()], &state);
+    let profile = EmulationProfile::from_toml(
+        r#"
+        name = "worn_gripper"
+
+        [devices.robot]
+        failure_probability = 5
+
+        [devices.robot.commands.pick]
+        execution_time = { distribution = "log_normal", milliseconds = 3000, deviation = 1000 }
+        failure_probability = 100
+        failure_causes = { mis_grip = 3, dropped_item = 1 }
+
+        [devices.gantry.commands.move.positions.pipe_blue_box]
+        failure_probability = 20
+
+        [devices.gantry.commands.calibrate]
+        failure_causes_from_fmea = true
+        correlations = { detected_drift = { collision = 300 } }
+        "#,
+    )
+    .unwrap();
+    let state = profile.apply(&state, &fmea, &keys).unwrap();
+
+    assert_eq!(state.get_value("robot_emulate_failure_rate"), 2.to_spvalue());
+    assert_eq!(state.get_value("robot_emulated_failure_rate"), 5.to_spvalue());
+    assert_eq!(state.get_value("robot_pick_emulate_failure_rate"), 1.to_spvalue());
+    assert_eq!(state.get_value("robot_pick_emulate_execution_time"), 4.to_spvalue());
+    assert_eq!(state.get_value("robot_pick_emulated_execution_time_deviation"), 1000.to_spvalue());
+    assert_eq!(state.get_value("robot_pick_emulate_failure_cause"), 2.to_spvalue());
+    assert_eq!(
+        state.get_value("robot_pick_emulated_failure_cause_weights"),
+        SPValue::Array(SPValueType::Int64, vec![1.to_spvalue(), 3.to_spvalue()])
+    );
+
+    assert_eq!(state.get_value("gantry_move_pipe_blue_box_emulated_failure_rate"), 20.to_spvalue());
+    let key = emulation_key_for_command("gantry", "move", "pipe_blue_box");
+    assert_eq!(key.prefixes()[0], "gantry_move_pipe_blue_box");
+
+    match state.get_value("gantry_calibrate_emulated_failure_cause") {
+        SPValue::Array(_, causes) => assert!(causes.contains(&"detected_drift".to_spvalue())),
+        other => panic!("Expected failure causes, got {:?}.", other),
+    }
+    assert_eq!(
+        state.get_value("gantry_calibrate_emulated_correlation_factor"),
+        SPValue::Array(SPValueType::Int64, vec![300.to_spvalue()])
+    );
+
+    let profile = EmulationProfile::from_toml("name = \"typo\"\n[devices.robto]\nfailure_probability = 5").unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+    let profile = EmulationProfile::from_toml("name = \"typo\"\n[devices.robot]\nfailure_probabilty = 40").unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+    let profile =
+        EmulationProfile::from_toml("name = \"typo\"\n[devices.robot.commands.mvoe]\nfailure_probability = 40").unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+    let profile = EmulationProfile::from_toml(
+        "name = \"typo\"\n[devices.gantry.commands.move.positions.home]\nsilent_failure_probabilty = 40",
+    )
+    .unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+    let profile = EmulationProfile::from_toml("name = \"bad\"\n[devices.robot]\nfailure_probability = 150").unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+    let profile = EmulationProfile::from_toml(
+        "name = \"bad\"\n[devices.robot]\nfailure_causes = { mis_grip = 1 }\ncorrelations = { mis_grip = { collision = 200 } }",
+    )
+    .unwrap();
+    assert!(profile.apply(&state, &fmea, &keys).is_err());
+}
@@ -0,0 +1,93 @@
+use futures::{Stream, StreamExt};
+use crate::interfaces::msgs::TriggerCameraSystem;
+use crate::*;
+
+pub async fn spawn_camera_system_emulator_server(
+    transport: impl Transport,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = transport.serve::<TriggerCameraSystem::Service>("/camera_system_emulator_service")?;
+
+    tokio::task::spawn(async move {
+        let result = camera_system_emlator_server(service, device_seed(seed, "camera_system")).await;
+        match result {
+            Ok(()) => log_info!("camera_system_emulator", "Service call succeeded."),
+            Err(e) => log_error!("camera_system_emulator", "Service call failed with: {}.", e),
+        };
+    });
+    Ok(())
+}
+
+async fn camera_system_emlator_server(
+    mut service: impl Stream<Item = TransportRequest<TriggerCameraSystem::Service>> + Unpin,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log_info!("camera_system_emulator", "Spawned.");
+    let mut sampler = RequestSampler::new("camera_system_emulator", seed);
+    loop {
+        match service.next().await {
+            Some(request) => {
+                // a device that never responds drops the request
+                let SampledRequest { mut fail, cause, .. } =
+                    match sampler.sample(&request.message.emulated_response).await {
+                        Some(sampled) => sampled,
+                        None => continue,
+                    };
+
+                match request.message.command.as_str() {
+                    "update" => log_info!(
+                        "camera_system_emulator",
+                        "Got request to update the position of {}.",
+                        request.message.blue_box
+                    ),
+                    _ => {
+                        log_warn!("camera_system_emulator", "Unknown command");
+                        fail = true;
+                    },
+                };
+
+                let success_info = match request.message.command.as_str() {
+                    "update" => format!("Succeeded to update the position of {}.",
+                        request.message.blue_box
+                    ),
+                    _ => "Failed, unknown command".to_string()
+                };
+
+                let failure_info = match request.message.command.as_str() {
+                    "update" => format!("Failed to update the position of {} due to {}.",
+                        request.message.blue_box, cause
+                    ),
+                    _ => "Failed, unknown command".to_string()
+                };
+
+                sampler.record(fail, &cause);
+
+                if !fail {
+                    let response = TriggerCameraSystem::Response {
+                        success: true,
+                        failure_cause: "".to_string(),
+                        info: success_info.clone(),
+                    };
+                    log_info!("camera_system_emulator", "{}", success_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("camera_system_emulator", "Could not send service response: {}", e);
+                    }
+                    continue;
+                } else {
+                    let response = TriggerCameraSystem::Response {
+                        success: false,
+                        failure_cause: cause,
+                        info: failure_info.clone(),
+                    };
+                    log_error!("camera_system_emulator", "{}", failure_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("camera_system_emulator", "Could not send service response: {}", e);
+                    }
+                    continue;
+                }
+            }
+
+            None => (),
+        }
+    }
+}
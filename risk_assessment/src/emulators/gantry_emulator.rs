@@ -1,87 +1,76 @@
 use futures::{Stream, StreamExt};
-use r2r::risk_assessment_msgs::srv::TriggerGantry;
-use rand::prelude::SliceRandom;
-use r2r::QosProfile;
-use r2r::ServiceRequest;
-use rand::Rng;
+use crate::interfaces::msgs::TriggerGantry;
 use std::sync::{Arc, Mutex};
+use crate::*;
 
 pub async fn spawn_gantry_emulator_server(
-    arc_node: Arc<Mutex<r2r::Node>>,
+    transport: impl Transport,
+    world: Arc<Mutex<World>>,
+    seed: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let service = arc_node
-        .lock()
-        .unwrap()
-        .create_service::<TriggerGantry::Service>(
-            "/gantry_emulator_service",
-            QosProfile::default(),
-        )?;
+    let service = transport.serve::<TriggerGantry::Service>("/gantry_emulator_service")?;
 
     tokio::task::spawn(async move {
-        let result = gantry_emlator_server(service).await;
+        let result = gantry_emlator_server(service, world, device_seed(seed, "gantry")).await;
         match result {
-            Ok(()) => r2r::log_info!("gantry_emulator", "Service call succeeded."),
-            Err(e) => r2r::log_error!("gantry_emulator", "Service call failed with: {}.", e),
+            Ok(()) => log_info!("gantry_emulator", "Service call succeeded."),
+            Err(e) => log_error!("gantry_emulator", "Service call failed with: {}.", e),
         };
     });
     Ok(())
 }
 
 async fn gantry_emlator_server(
-    mut service: impl Stream<Item = ServiceRequest<TriggerGantry::Service>> + Unpin,
+    mut service: impl Stream<Item = TransportRequest<TriggerGantry::Service>> + Unpin,
+    world: Arc<Mutex<World>>,
+    seed: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    r2r::log_info!("gantry_emulator", "Spawned.");
+    log_info!("gantry_emulator", "Spawned.");
+    let mut sampler = RequestSampler::new("gantry_emulator", seed);
     loop {
         match service.next().await {
             Some(request) => {
-                // emulate request execution time
-                let delay: u64 = match request.message.emulated_response.emulate_execution_time {
-                    0 => 0,
-                    1 => request.message.emulated_response.emulated_execution_time as u64,
-                    2 => {
-                        let mut rng = rand::thread_rng();
-                        rng.gen_range(0..request.message.emulated_response.emulated_execution_time) as u64
-                    },
-                    _ => 0
-                };
-                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
-
-                // emulate failure rate
-                let mut fail = match request.message.emulated_response.emulate_failure_rate {
-                    0 => false,
-                    1 => true,
-                    2 => rand::thread_rng().gen_range(0..=100) <= request.message.emulated_response.emulated_failure_rate as u64,
-                    _ => false
-                };
-
-                // emulate failure cause
-                let cause = match request.message.emulated_response.emulate_failure_cause {
-                    0 => "generic_failure".to_string(),
-                    1 => request.message.emulated_response.emulated_failure_cause[0].to_string(),
-                    2 => request.message.emulated_response.emulated_failure_cause
-                    .choose(&mut rand::thread_rng())
-                    .unwrap()
-                    .to_string(),
-                    _ => "generic_failure".to_string(),
-                };
+                // a device that never responds drops the request
+                let SampledRequest { mut fail, mut cause, silent } =
+                    match sampler.sample(&request.message.emulated_response).await {
+                        Some(sampled) => sampled,
+                        None => continue,
+                    };
 
                 match request.message.command.as_str() {
-                    "move" => r2r::log_info!(
+                    "move" => log_info!(
                         "gantry_emulator",
                         "Got request to move to {}.",
                         request.message.position
                     ),
                     "calibrate" => {
-                        r2r::log_info!("gantry_emulator", "Got request to calibrate.")
+                        log_info!("gantry_emulator", "Got request to calibrate.")
                     }
-                    "lock" => r2r::log_info!("gantry_emulator", "Got request to lock."),
-                    "unlock" => r2r::log_info!("gantry_emulator", "Got request to unlock."),
+                    "lock" => log_info!("gantry_emulator", "Got request to lock."),
+                    "unlock" => log_info!("gantry_emulator", "Got request to unlock."),
                     _ => {
-                        r2r::log_warn!("gantry_emulator", "Unknown command");
+                        log_warn!("gantry_emulator", "Unknown command");
                         fail = true;
                     },
                 };
 
+                // apply the command to the ground truth, impossible commands fail with their own cause
+                if !fail {
+                    let mut world = world.lock().unwrap();
+                    let mut next = world.clone();
+                    match next.gantry_command(&request.message.command, &request.message.position) {
+                        Err(impossible) => {
+                            fail = true;
+                            cause = impossible;
+                        }
+                        Ok(()) => {
+                            if !(silent && world.silent_failure("gantry", &request.message.command, &request.message.position)) {
+                                *world = next;
+                            }
+                        }
+                    }
+                }
+
                 let success_info = match request.message.command.as_str() {
                     "move" => format!("Succeeded to move to {}.",
                         request.message.position
@@ -102,16 +91,18 @@ async fn gantry_emlator_server(
                     _ => "Failed, unknown command".to_string()
                 };
 
+                sampler.record(fail, &cause);
+
                 if !fail {
                     let response = TriggerGantry::Response {
                         success: true,
                         failure_cause: "".to_string(),
                         info: success_info.clone(),
                     };
-                    r2r::log_info!("gantry_emulator", "{}", success_info);
-                    request
-                        .respond(response)
-                        .expect("Could not send service response.");
+                    log_info!("gantry_emulator", "{}", success_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("gantry_emulator", "Could not send service response: {}", e);
+                    }
                     continue;
                 } else {
                     let response = TriggerGantry::Response {
@@ -119,10 +110,10 @@ async fn gantry_emlator_server(
                         failure_cause: cause,
                         info: failure_info.clone(),
                     };
-                    r2r::log_error!("gantry_emulator", "{}", failure_info);
-                    request
-                        .respond(response)
-                        .expect("Could not send service response.");
+                    log_error!("gantry_emulator", "{}", failure_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("gantry_emulator", "Could not send service response: {}", e);
+                    }
                     continue;
                 }
             }
@@ -1,2 +1,7 @@
+pub mod camera_system_emulator;
 pub mod gantry_emulator;
-pub mod robot_emulator;
\ No newline at end of file
+pub mod robot_emulator;
+pub mod sampling;
+pub mod scanner_emulator;
+pub mod seed;
+pub mod world;
\ No newline at end of file
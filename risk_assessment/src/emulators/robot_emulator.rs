@@ -1,95 +1,83 @@
 use futures::{Stream, StreamExt};
-use r2r::risk_assessment_msgs::srv::TriggerRobot;
-use rand::prelude::SliceRandom;
-use r2r::QosProfile;
-use r2r::ServiceRequest;
-use rand::Rng;
+use crate::interfaces::msgs::TriggerRobot;
 use std::sync::{Arc, Mutex};
+use crate::*;
 
 pub async fn spawn_robot_emulator_server(
-    arc_node: Arc<Mutex<r2r::Node>>,
+    transport: impl Transport,
+    world: Arc<Mutex<World>>,
+    seed: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let service = arc_node
-        .lock()
-        .unwrap()
-        .create_service::<TriggerRobot::Service>(
-            "/robot_emulator_service",
-            QosProfile::default(),
-        )?;
+    let service = transport.serve::<TriggerRobot::Service>("/robot_emulator_service")?;
 
     tokio::task::spawn(async move {
-        let result = robot_emlator_server(service).await;
+        let result = robot_emlator_server(service, world, device_seed(seed, "robot")).await;
         match result {
-            Ok(()) => r2r::log_info!("robot_emulator", "Service call succeeded."),
-            Err(e) => r2r::log_error!("robot_emulator", "Service call failed with: {}.", e),
+            Ok(()) => log_info!("robot_emulator", "Service call succeeded."),
+            Err(e) => log_error!("robot_emulator", "Service call failed with: {}.", e),
         };
     });
     Ok(())
 }
 
 async fn robot_emlator_server(
-    mut service: impl Stream<Item = ServiceRequest<TriggerRobot::Service>> + Unpin,
+    mut service: impl Stream<Item = TransportRequest<TriggerRobot::Service>> + Unpin,
+    world: Arc<Mutex<World>>,
+    seed: u64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    r2r::log_info!("robot_emulator", "Spawned.");
+    log_info!("robot_emulator", "Spawned.");
+    let mut sampler = RequestSampler::new("robot_emulator", seed);
     loop {
         match service.next().await {
             Some(request) => {
-                // emulate request execution time
-                let delay: u64 = match request.message.emulated_response.emulate_execution_time {
-                    0 => 0,
-                    1 => request.message.emulated_response.emulated_execution_time as u64,
-                    2 => {
-                        let mut rng = rand::thread_rng();
-                        rng.gen_range(0..request.message.emulated_response.emulated_execution_time) as u64
-                    },
-                    _ => 0
-                };
-                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
-
-                // emulate failure rate
-                let mut fail = match request.message.emulated_response.emulate_failure_rate {
-                    0 => false,
-                    1 => true,
-                    2 => rand::thread_rng().gen_range(0..=100) <= request.message.emulated_response.emulated_failure_rate as u64,
-                    _ => false
-                };
-
-                // emulate failure cause
-                let cause = match request.message.emulated_response.emulate_failure_cause {
-                    0 => "generic_failure".to_string(),
-                    1 => request.message.emulated_response.emulated_failure_cause[0].to_string(),
-                    2 => request.message.emulated_response.emulated_failure_cause
-                    .choose(&mut rand::thread_rng())
-                    .unwrap()
-                    .to_string(),
-                    _ => "generic_failure".to_string(),
-                };
+                // a device that never responds drops the request
+                let SampledRequest { mut fail, mut cause, silent } =
+                    match sampler.sample(&request.message.emulated_response).await {
+                        Some(sampled) => sampled,
+                        None => continue,
+                    };
 
                 let mut checked_mounted_tool = "UNKNOWN".to_string();
+
                 match request.message.command.as_str() {
-                    "move" => r2r::log_info!(
+                    "move" => log_info!(
                         "robot_emulator",
                         "Got request to move to {}.",
                         request.message.position
                     ),
                     "pick" => {
-                        r2r::log_info!("robot_emulator", "Got request to pick.")
+                        log_info!("robot_emulator", "Got request to pick.")
                     }
-                    "place" => r2r::log_info!("robot_emulator", "Got request to place."),
-                    "mount" => r2r::log_info!("robot_emulator", "Got request to mount."),
-                    "unmount" => r2r::log_info!("robot_emulator", "Got request to unmount."),
+                    "place" => log_info!("robot_emulator", "Got request to place."),
+                    "mount" => log_info!("robot_emulator", "Got request to mount."),
+                    "unmount" => log_info!("robot_emulator", "Got request to unmount."),
                     "check_mounted_tool" => {
-                        checked_mounted_tool = vec!("gripper_tool", "suction_tool", "none").choose(&mut rand::thread_rng())
-                        .unwrap()
-                        .to_string();
-                        r2r::log_info!("robot_emulator", "Got request to check_mounted_tool.")
+                        checked_mounted_tool = world.lock().unwrap().robot_mounted.clone();
+                        log_info!("robot_emulator", "Got request to check_mounted_tool.")
                     },
                     _ => {
-                        r2r::log_warn!("robot_emulator", "Unknown command");
+                        log_warn!("robot_emulator", "Unknown command");
                         fail = true;
                     },
                 };
 
+                // apply the command to the ground truth, impossible commands fail with their own cause
+                if !fail {
+                    let mut world = world.lock().unwrap();
+                    let mut next = world.clone();
+                    match next.robot_command(&request.message.command, &request.message.position) {
+                        Err(impossible) => {
+                            fail = true;
+                            cause = impossible;
+                        }
+                        Ok(()) => {
+                            if !(silent && world.silent_failure("robot", &request.message.command, &request.message.position)) {
+                                *world = next;
+                            }
+                        }
+                    }
+                }
+
                 let success_info = match request.message.command.as_str() {
                     "move" => format!("Succeeded to move to {}.",
                         request.message.position
@@ -114,6 +102,8 @@ async fn robot_emlator_server(
                     _ => "Failed, unknown command".to_string()
                 };
 
+                sampler.record(fail, &cause);
+
                 if !fail {
                     let response = TriggerRobot::Response {
                         success: true,
@@ -121,10 +111,10 @@ async fn robot_emlator_server(
                         info: success_info.clone(),
                         checked_mounted_tool
                     };
-                    r2r::log_info!("robot_emulator", "{}", success_info);
-                    request
-                        .respond(response)
-                        .expect("Could not send service response.");
+                    log_info!("robot_emulator", "{}", success_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("robot_emulator", "Could not send service response: {}", e);
+                    }
                     continue;
                 } else {
                     let response = TriggerRobot::Response {
@@ -133,10 +123,10 @@ async fn robot_emlator_server(
                         info: failure_info.clone(),
                         checked_mounted_tool
                     };
-                    r2r::log_error!("robot_emulator", "{}", failure_info);
-                    request
-                        .respond(response)
-                        .expect("Could not send service response.");
+                    log_error!("robot_emulator", "{}", failure_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("robot_emulator", "Could not send service response: {}", e);
+                    }
                     continue;
                 }
             }
@@ -0,0 +1,315 @@
+use crate::*;
+use crate::interfaces::msgs::Emulation;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use rand_distr::{Exp, LogNormal, Normal};
+
+// Weights of the failure causes, uniform if no weights are given. Causes that are
+// correlated with the previous failure of the device get their weight multiplied.
+fn failure_cause_weights(emulation: &Emulation, previous_cause: &Option<String>) -> Result<Vec<f64>, String> {
+    let causes = &emulation.emulated_failure_cause;
+    let mut weights: Vec<f64> = match emulation.emulated_failure_cause_weights.len() {
+        0 => vec![1.0; causes.len()],
+        n if n == causes.len() => emulation
+            .emulated_failure_cause_weights
+            .iter()
+            .map(|w| *w as f64)
+            .collect(),
+        n => {
+            return Err(format!(
+                "Got {} failure cause weights for {} failure causes.",
+                n,
+                causes.len()
+            ))
+        }
+    };
+    let correlations = &emulation.emulated_correlation_after;
+    if correlations.len() != emulation.emulated_correlation_cause.len()
+        || correlations.len() != emulation.emulated_correlation_factor.len()
+    {
+        return Err("Failure correlations need an 'after', a 'cause' and a 'factor' each.".to_string());
+    }
+    if let Some(previous_cause) = previous_cause {
+        for (i, after) in correlations.iter().enumerate() {
+            if after == previous_cause {
+                let cause = &emulation.emulated_correlation_cause[i];
+                match causes.iter().position(|c| c == cause) {
+                    Some(index) => {
+                        weights[index] *= emulation.emulated_correlation_factor[i] as f64 / 100.0
+                    }
+                    None => {
+                        return Err(format!(
+                            "Correlated failure cause '{}' is not one of the failure causes.",
+                            cause
+                        ))
+                    }
+                }
+            }
+        }
+    }
+    Ok(weights)
+}
+
+fn percentage(name: &str, value: i32) -> Result<f64, String> {
+    match value {
+        v if (0..=100).contains(&v) => Ok(v as f64 / 100.0),
+        v => Err(format!("Invalid {} {}, expected a percentage 0..100.", name, v)),
+    }
+}
+
+// Samples whether the request fails and why, returns None if it succeeds.
+// An invalid emulation is an error instead of a panic, so that the emulator
+// can report it and keep serving requests.
+pub fn sample_failure(
+    emulation: &Emulation,
+    previous_cause: &Option<String>,
+    rng: &mut impl Rng,
+) -> Result<Option<String>, String> {
+    check_execution_time(emulation)?;
+    let causes = &emulation.emulated_failure_cause;
+    let weights = match emulation.emulate_failure_cause {
+        0 => vec![],
+        1 | 2 if causes.is_empty() => {
+            return Err("Failure cause emulation is enabled, but no causes are given.".to_string())
+        }
+        1 => vec![],
+        2 => failure_cause_weights(emulation, previous_cause)?,
+        mode => return Err(format!("Unknown failure cause emulation mode {}.", mode)),
+    };
+
+    // A correlated cause is more likely, the other causes keep their probability
+    let mut probability = match emulation.emulate_failure_rate {
+        0 => 0.0,
+        1 => 1.0,
+        2 => percentage("failure rate", emulation.emulated_failure_rate)?,
+        mode => return Err(format!("Unknown failure rate emulation mode {}.", mode)),
+    };
+    if emulation.emulate_failure_cause == 2 && emulation.emulate_failure_rate == 2 {
+        let uncorrelated = failure_cause_weights(emulation, &None)?.iter().sum::<f64>();
+        if uncorrelated > 0.0 {
+            probability = (probability * weights.iter().sum::<f64>() / uncorrelated).min(1.0);
+        }
+    }
+    if !rng.gen_bool(probability) {
+        return Ok(None);
+    }
+
+    let cause = match emulation.emulate_failure_cause {
+        0 => "generic_failure".to_string(),
+        1 => causes[0].clone(),
+        _ => {
+            let index = WeightedIndex::new(&weights)
+                .map_err(|e| format!("Invalid failure cause weights: {}.", e))?;
+            causes[index.sample(rng)].clone()
+        }
+    };
+    Ok(Some(cause))
+}
+
+// Samples whether the request succeeds but has no effect
+pub fn sample_silent_failure(emulation: &Emulation, rng: &mut impl Rng) -> Result<bool, String> {
+    match emulation.emulate_silent_failure {
+        0 => Ok(false),
+        1 => Ok(true),
+        2 => Ok(rng.gen_bool(percentage("silent failure rate", emulation.emulated_silent_failure_rate)?)),
+        mode => Err(format!("Unknown silent failure emulation mode {}.", mode)),
+    }
+}
+
+fn check_execution_time(emulation: &Emulation) -> Result<(), String> {
+    if emulation.emulate_execution_time > 8 {
+        return Err(format!(
+            "Unknown execution time emulation mode {}.",
+            emulation.emulate_execution_time
+        ));
+    }
+    if emulation.emulated_execution_time < 0 || emulation.emulated_execution_time_deviation < 0 {
+        return Err(format!(
+            "Invalid execution time {} ms with deviation {} ms, expected positive values.",
+            emulation.emulated_execution_time, emulation.emulated_execution_time_deviation
+        ));
+    }
+    Ok(())
+}
+
+// Execution time in milliseconds, None if the device never responds. The mean is
+// "emulated_execution_time" and the spread "emulated_execution_time_deviation".
+pub fn sample_execution_time(emulation: &Emulation, rng: &mut impl Rng) -> Result<Option<u64>, String> {
+    check_execution_time(emulation)?;
+    let mean = emulation.emulated_execution_time as f64;
+    let deviation = emulation.emulated_execution_time_deviation as f64;
+    let milliseconds = match emulation.emulate_execution_time {
+        0 => 0.0,
+        1 => mean,
+        2 if mean == 0.0 => 0.0,
+        2 => rng.gen_range(0.0..mean),
+        3 => Normal::new(mean, deviation)
+            .map_err(|e| format!("Invalid normal execution time: {}.", e))?
+            .sample(rng),
+        4 if mean == 0.0 => 0.0,
+        4 => {
+            // mean and deviation of the log-normal itself, not of its logarithm
+            let sigma = (1.0 + (deviation / mean).powi(2)).ln().sqrt();
+            LogNormal::new(mean.ln() - sigma.powi(2) / 2.0, sigma)
+                .map_err(|e| format!("Invalid log-normal execution time: {}.", e))?
+                .sample(rng)
+        }
+        5 if mean == 0.0 => 0.0,
+        5 => Exp::new(1.0 / mean)
+            .map_err(|e| format!("Invalid exponential execution time: {}.", e))?
+            .sample(rng),
+        6 => mean + rng.gen_range(0.0..=deviation),
+        7 => return Ok(None),
+        8 => emulation.request_timeout.max(0) as f64 + mean,
+        mode => return Err(format!("Unknown execution time emulation mode {}.", mode)),
+    };
+    Ok(Some(milliseconds.max(0.0).round() as u64))
+}
+
+// What an emulator samples for a request, before it looks at the command
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledRequest {
+    pub fail: bool,
+    pub cause: String,
+    pub silent: bool,
+}
+
+// Samples the requests of one emulated device. Every request gets its own random
+// source from the device seed and its sequence number, and correlated failure
+// causes depend on the previous failure of the device.
+pub struct RequestSampler {
+    emulator: String,
+    seed: u64,
+    sequence: u64,
+    previous_cause: Option<String>,
+}
+
+impl RequestSampler {
+    pub fn new(emulator: &str, seed: u64) -> RequestSampler {
+        RequestSampler {
+            emulator: emulator.to_string(),
+            seed,
+            sequence: 0,
+            previous_cause: None,
+        }
+    }
+
+    // Waits for the emulated execution time, then samples the failure, its cause and
+    // whether the request fails silently. Returns None for a device that never responds,
+    // the request is then dropped. An invalid emulation is reported as a failure.
+    pub async fn sample(&mut self, emulation: &Emulation) -> Option<SampledRequest> {
+        let mut rng = request_rng(self.seed, self.sequence);
+        self.sequence += 1;
+
+        let delay = match sample_execution_time(emulation, &mut rng) {
+            Ok(Some(delay)) => delay,
+            Ok(None) => {
+                log_warn!(&self.emulator, "Emulating a device that never responds.");
+                return None;
+            }
+            Err(e) => {
+                log_error!(&self.emulator, "Invalid emulation: {}", e);
+                0
+            }
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+
+        let (fail, cause) = match sample_failure(emulation, &self.previous_cause, &mut rng) {
+            Ok(Some(cause)) => (true, cause),
+            Ok(None) => (false, "generic_failure".to_string()),
+            Err(e) => {
+                log_error!(&self.emulator, "Invalid emulation: {}", e);
+                (true, "invalid_emulation".to_string())
+            }
+        };
+
+        let (fail, cause, silent) = match sample_silent_failure(emulation, &mut rng) {
+            Ok(silent) => (fail, cause, silent),
+            Err(e) => {
+                log_error!(&self.emulator, "Invalid emulation: {}", e);
+                (true, "invalid_emulation".to_string(), false)
+            }
+        };
+        Some(SampledRequest { fail, cause, silent })
+    }
+
+    // The outcome of the request, once the command was applied
+    pub fn record(&mut self, fail: bool, cause: &str) {
+        self.previous_cause = match fail {
+            true => Some(cause.to_string()),
+            false => None,
+        };
+    }
+}
+
+#[test]
+fn test_sample_failure() {
+    let mut rng = rand::thread_rng();
+    let mut emulation = Emulation::default();
+    emulation.emulate_failure_rate = 1;
+    emulation.emulate_failure_cause = 1;
+    assert!(sample_failure(&emulation, &None, &mut rng).is_err());
+
+    emulation.emulate_failure_cause = 2;
+    emulation.emulated_failure_cause = vec!["detected_drift".to_string(), "collision".to_string()];
+    emulation.emulated_failure_cause_weights = vec![1, 0];
+    assert_eq!(
+        sample_failure(&emulation, &None, &mut rng),
+        Ok(Some("detected_drift".to_string()))
+    );
+
+    // After a drift, a collision is the only possible cause
+    emulation.emulated_failure_cause_weights = vec![0, 1];
+    emulation.emulated_correlation_after = vec!["detected_drift".to_string()];
+    emulation.emulated_correlation_cause = vec!["collision".to_string()];
+    emulation.emulated_correlation_factor = vec![300];
+    assert_eq!(
+        sample_failure(&emulation, &Some("detected_drift".to_string()), &mut rng),
+        Ok(Some("collision".to_string()))
+    );
+
+    emulation.emulated_failure_cause_weights = vec![1];
+    assert!(sample_failure(&emulation, &None, &mut rng).is_err());
+}
+
+#[test]
+fn test_sample_execution_time() {
+    let mut rng = rand::thread_rng();
+    let mut emulation = Emulation::default();
+    emulation.emulated_execution_time = 1000;
+    emulation.emulated_execution_time_deviation = 200;
+    for mode in 0..=6 {
+        emulation.emulate_execution_time = mode;
+        assert!(sample_execution_time(&emulation, &mut rng).unwrap().is_some());
+    }
+
+    emulation.emulate_execution_time = 6;
+    let delay = sample_execution_time(&emulation, &mut rng).unwrap().unwrap();
+    assert!((1000..=1200).contains(&delay));
+
+    emulation.emulate_execution_time = 7;
+    assert_eq!(sample_execution_time(&emulation, &mut rng), Ok(None));
+
+    emulation.emulate_execution_time = 8;
+    emulation.request_timeout = 5000;
+    assert_eq!(sample_execution_time(&emulation, &mut rng), Ok(Some(6000)));
+
+    emulation.emulated_execution_time_deviation = -1;
+    assert!(sample_execution_time(&emulation, &mut rng).is_err());
+}
+
+#[test]
+fn test_sample_silent_failure() {
+    let mut rng = rand::thread_rng();
+    let mut emulation = Emulation::default();
+    emulation.emulate_silent_failure = 2;
+    emulation.emulated_silent_failure_rate = 0;
+    assert!((0..1000).all(|_| sample_silent_failure(&emulation, &mut rng) == Ok(false)));
+    emulation.emulated_silent_failure_rate = 100;
+    assert!((0..1000).all(|_| sample_silent_failure(&emulation, &mut rng) == Ok(true)));
+
+    emulation.emulated_silent_failure_rate = -1;
+    assert!(sample_silent_failure(&emulation, &mut rng).is_err());
+    emulation.emulate_silent_failure = 3;
+    assert!(sample_silent_failure(&emulation, &mut rng).is_err());
+}
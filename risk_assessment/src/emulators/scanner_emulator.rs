@@ -0,0 +1,104 @@
+use futures::{Stream, StreamExt};
+use crate::interfaces::msgs::TriggerScanner;
+use std::sync::{Arc, Mutex};
+use crate::*;
+
+pub async fn spawn_scanner_emulator_server(
+    transport: impl Transport,
+    world: Arc<Mutex<World>>,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service = transport.serve::<TriggerScanner::Service>("/scanner_emulator_service")?;
+
+    tokio::task::spawn(async move {
+        let result = scanner_emlator_server(service, world, device_seed(seed, "scanner")).await;
+        match result {
+            Ok(()) => log_info!("scanner_emulator", "Service call succeeded."),
+            Err(e) => log_error!("scanner_emulator", "Service call failed with: {}.", e),
+        };
+    });
+    Ok(())
+}
+
+async fn scanner_emlator_server(
+    mut service: impl Stream<Item = TransportRequest<TriggerScanner::Service>> + Unpin,
+    world: Arc<Mutex<World>>,
+    seed: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    log_info!("scanner_emulator", "Spawned.");
+    let mut sampler = RequestSampler::new("scanner_emulator", seed);
+    loop {
+        match service.next().await {
+            Some(request) => {
+                // a device that never responds drops the request
+                let SampledRequest { mut fail, cause, silent } =
+                    match sampler.sample(&request.message.emulated_response).await {
+                        Some(sampled) => sampled,
+                        None => continue,
+                    };
+
+                match request.message.command.as_str() {
+                    "scan" => log_info!(
+                        "scanner_emulator",
+                        "Got request to scan {}.",
+                        request.message.item
+                    ),
+                    _ => {
+                        log_warn!("scanner_emulator", "Unknown command");
+                        fail = true;
+                    },
+                };
+
+                let success_info = match request.message.command.as_str() {
+                    "scan" => format!("Succeeded to scan {}.", request.message.item),
+                    _ => "Failed, unknown command".to_string()
+                };
+
+                let failure_info = match request.message.command.as_str() {
+                    "scan" => format!("Failed to scan {} due to {}.",
+                        request.message.item, cause
+                    ),
+                    _ => "Failed, unknown command".to_string()
+                };
+
+                sampler.record(fail, &cause);
+
+                if !fail {
+                    // a silent failure reports the item without looking for it
+                    let scanned_item = {
+                        let mut world = world.lock().unwrap();
+                        match silent && world.silent_failure("scanner", "scan", &request.message.item) {
+                            true => request.message.item.clone(),
+                            false => world.scan(&request.message.item),
+                        }
+                    };
+                    let response = TriggerScanner::Response {
+                        success: true,
+                        failure_cause: "".to_string(),
+                        info: success_info.clone(),
+                        scanned_item,
+                    };
+                    log_info!("scanner_emulator", "{}", success_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("scanner_emulator", "Could not send service response: {}", e);
+                    }
+                    continue;
+                } else {
+                    let response = TriggerScanner::Response {
+                        success: false,
+                        failure_cause: cause,
+                        info: failure_info.clone(),
+                        scanned_item: "UNKNOWN".to_string(),
+                    };
+                    log_error!("scanner_emulator", "{}", failure_info);
+                    if let Err(e) = request.respond(response) {
+                        log_warn!("scanner_emulator", "Could not send service response: {}", e);
+                    }
+                    continue;
+                }
+            }
+
+            None => (),
+        }
+    }
+}
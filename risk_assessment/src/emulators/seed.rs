@@ -0,0 +1,50 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// A run is reproduced by starting it with the same seed, i.e. EMULATION_SEED=42.
+// Every device gets its own seed derived from the global one, and every request
+// of a device gets its own random source derived from the device seed and the
+// sequence number of the request. The responses of a device therefore only
+// depend on the seed and on the order of the requests it gets.
+
+// Random if EMULATION_SEED is not set, so that it can be logged and reported
+pub fn emulation_seed() -> Result<u64, String> {
+    match std::env::var("EMULATION_SEED") {
+        Ok(seed) => seed
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid EMULATION_SEED '{}': {}.", seed, e)),
+        Err(_) => Ok(rand::thread_rng().gen()),
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a of the device name, the std hasher is not stable between releases
+pub fn device_seed(seed: u64, device: &str) -> u64 {
+    let hash = device.bytes().fold(0xcbf29ce484222325, |hash: u64, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    splitmix64(seed ^ hash)
+}
+
+pub fn request_rng(device_seed: u64, sequence: u64) -> StdRng {
+    StdRng::seed_from_u64(splitmix64(device_seed ^ splitmix64(sequence)))
+}
+
+#[test]
+fn test_request_rng_is_reproducible() {
+    assert_ne!(device_seed(42, "gantry"), device_seed(42, "robot"));
+    assert_ne!(device_seed(42, "gantry"), device_seed(43, "gantry"));
+
+    let seed = device_seed(42, "gantry");
+    let first: Vec<u64> = (0..10).map(|n| request_rng(seed, n).gen()).collect();
+    let again: Vec<u64> = (0..10).map(|n| request_rng(seed, n).gen()).collect();
+    assert_eq!(first, again);
+    assert_ne!(first[0], first[1]);
+}
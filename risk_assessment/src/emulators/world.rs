@@ -0,0 +1,277 @@
+use micro_sp::*;
+use crate::*;
+use rand::prelude::SliceRandom;
+use std::collections::HashMap;
+
+// A command that was reported as successful, but didn't have its effect.
+// It is detected once a check reveals the true value of the affected variable,
+// while that value still differs from the expected one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SilentFailure {
+    pub device: String,
+    pub command: String,
+    pub variable: String,
+    pub expected: SPValue, // the value that the command should have set
+    pub detected: bool,
+}
+
+// Hidden ground truth of the cell, shared by the emulators. The runner never
+// reads it directly, it can only estimate it through the device responses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct World {
+    pub gantry_position: String,
+    pub gantry_locked: bool,
+    pub gantry_calibrated: bool,
+    pub robot_position: String,
+    pub robot_mounted: String,
+    pub robot_holding: String,
+    pub item_positions: HashMap<String, String>,
+    pub silent_failures: Vec<SilentFailure>,
+}
+
+// Pipes can only be picked with the gripper, plates with the suction tool
+fn tool_for_item(item: &str) -> &'static str {
+    match item {
+        "pipe" => "gripper_tool",
+        "plate" => "suction_tool",
+        _ => "none",
+    }
+}
+
+impl World {
+    // The mounted tool is not known at startup, like on the real cell,
+    // but it is the same for the same emulation seed
+    pub fn new(seed: u64) -> World {
+        World {
+            gantry_position: "home".to_string(),
+            gantry_locked: false,
+            gantry_calibrated: false,
+            robot_position: "a".to_string(),
+            robot_mounted: vec!["gripper_tool", "suction_tool", "none"]
+                .choose(&mut request_rng(device_seed(seed, "world"), 0))
+                .unwrap()
+                .to_string(),
+            robot_holding: "none".to_string(),
+            item_positions: HashMap::from([
+                ("pipe".to_string(), "pipe_blue_box".to_string()),
+                ("plate".to_string(), "plate_blue_box".to_string()),
+            ]),
+            silent_failures: vec![],
+        }
+    }
+
+    // Applies the command if it is physically possible, returns the cause otherwise
+    pub fn gantry_command(&mut self, command: &str, position: &str) -> Result<(), String> {
+        match command {
+            "move" => {
+                if self.gantry_locked {
+                    return Err("gantry_locked".to_string());
+                }
+                if !self.gantry_calibrated {
+                    return Err("not_calibrated".to_string());
+                }
+                self.gantry_position = position.to_string();
+            }
+            "calibrate" => {
+                if self.gantry_locked {
+                    return Err("gantry_locked".to_string());
+                }
+                self.gantry_calibrated = true;
+            }
+            "lock" => self.gantry_locked = true,
+            "unlock" => self.gantry_locked = false,
+            _ => return Err("unknown_command".to_string()),
+        }
+        Ok(())
+    }
+
+    // Applies the command if it is physically possible, returns the cause otherwise
+    pub fn robot_command(&mut self, command: &str, position: &str) -> Result<(), String> {
+        match command {
+            "move" => {
+                if !self.gantry_locked {
+                    return Err("gantry_not_locked".to_string());
+                }
+                self.robot_position = position.to_string();
+            }
+            "mount" => {
+                if self.robot_mounted != "none" {
+                    return Err("tool_already_mounted".to_string());
+                }
+                match self.robot_position.strip_suffix("_rack") {
+                    Some(tool) => self.robot_mounted = tool.to_string(),
+                    None => return Err("not_at_tool_rack".to_string()),
+                }
+            }
+            "unmount" => {
+                if self.robot_mounted == "none" {
+                    return Err("no_tool_mounted".to_string());
+                }
+                if self.robot_holding != "none" {
+                    return Err("holding_item".to_string());
+                }
+                if self.robot_position != format!("{}_rack", self.robot_mounted) {
+                    return Err("not_at_tool_rack".to_string());
+                }
+                self.robot_mounted = "none".to_string();
+            }
+            "pick" => {
+                if self.robot_holding != "none" {
+                    return Err("already_holding".to_string());
+                }
+                let mut items: Vec<&String> = self
+                    .item_positions
+                    .iter()
+                    .filter(|(_, pos)| **pos == self.robot_position)
+                    .map(|(item, _)| item)
+                    .collect();
+                items.sort();
+                let item = match items.first() {
+                    Some(_) => items
+                        .iter()
+                        .find(|item| tool_for_item(item) == self.robot_mounted)
+                        .ok_or("wrong_tool".to_string())?
+                        .to_string(),
+                    None => return Err("no_item_at_position".to_string()),
+                };
+                self.item_positions.insert(item.clone(), "robot".to_string());
+                self.robot_holding = item;
+            }
+            "place" => {
+                if self.robot_holding == "none" {
+                    return Err("not_holding".to_string());
+                }
+                self.item_positions
+                    .insert(self.robot_holding.clone(), self.robot_position.clone());
+                self.robot_holding = "none".to_string();
+            }
+            "check_mounted_tool" => self.reveal("robot_mounted_estimated"),
+            _ => return Err("unknown_command".to_string()),
+        }
+        Ok(())
+    }
+
+    // The scanner only finds the item if it is where the robot is
+    pub fn scan(&mut self, item: &str) -> String {
+        self.reveal(&format!("{}_position_estimated", item));
+        match self.item_positions.get(item) {
+            Some(pos) if *pos == self.robot_position => item.to_string(),
+            _ => "none".to_string(),
+        }
+    }
+
+    // Records a silent failure of the command, returns false for
+    // commands that have no effect on the world and can't fail silently
+    pub fn silent_failure(&mut self, device: &str, command: &str, argument: &str) -> bool {
+        let variable = match (device, command) {
+            ("gantry", "move") => "gantry_position_estimated".to_string(),
+            ("gantry", "calibrate") => "gantry_calibrated_estimated".to_string(),
+            ("gantry", "lock") | ("gantry", "unlock") => "gantry_locked_estimated".to_string(),
+            ("robot", "move") => "robot_position_estimated".to_string(),
+            ("robot", "mount") | ("robot", "unmount") => "robot_mounted_estimated".to_string(),
+            ("robot", "pick") | ("robot", "place") => "robot_holding_estimated".to_string(),
+            ("scanner", "scan") => format!("{}_position_estimated", argument),
+            _ => return false,
+        };
+        // the scanner reports the item where the robot is
+        let mut next = self.clone();
+        let expected = match device {
+            "gantry" => next.gantry_command(command, argument).map(|_| next.truth(&variable)),
+            "robot" => next.robot_command(command, argument).map(|_| next.truth(&variable)),
+            _ => Ok(self.robot_position.to_spvalue()),
+        }
+        .unwrap_or_else(|_| self.truth(&variable));
+        self.silent_failures.push(SilentFailure {
+            device: device.to_string(),
+            command: command.to_string(),
+            variable,
+            expected,
+            detected: false,
+        });
+        true
+    }
+
+    // Only the silent failures that are still outstanding are detected
+    fn reveal(&mut self, variable: &str) {
+        let truth = self.truth(variable);
+        self.silent_failures
+            .iter_mut()
+            .filter(|sf| sf.variable == variable && sf.expected != truth)
+            .for_each(|sf| sf.detected = true);
+    }
+
+    fn truth(&self, variable: &str) -> SPValue {
+        self.ground_truth()
+            .into_iter()
+            .find(|(name, _)| name == variable)
+            .map(|(_, value)| value)
+            .unwrap_or(SPValue::UNKNOWN)
+    }
+
+    // The true values of the variables that the model estimates
+    pub fn ground_truth(&self) -> Vec<(String, SPValue)> {
+        let mut truth = vec![
+            ("gantry_position_estimated".to_string(), self.gantry_position.to_spvalue()),
+            ("gantry_locked_estimated".to_string(), self.gantry_locked.to_spvalue()),
+            ("gantry_calibrated_estimated".to_string(), self.gantry_calibrated.to_spvalue()),
+            ("robot_position_estimated".to_string(), self.robot_position.to_spvalue()),
+            ("robot_mounted_estimated".to_string(), self.robot_mounted.to_spvalue()),
+            ("robot_holding_estimated".to_string(), self.robot_holding.to_spvalue()),
+        ];
+        let mut items: Vec<(&String, &String)> = self.item_positions.iter().collect();
+        items.sort();
+        for (item, pos) in items {
+            truth.push((format!("{}_position_estimated", item), pos.to_spvalue()));
+        }
+        truth
+    }
+}
+
+#[test]
+fn test_world_rejects_impossible_commands() {
+    let mut world = World::new(0);
+    world.robot_mounted = "suction_tool".to_string();
+
+    assert_eq!(world.gantry_command("move", "pipe_blue_box"), Err("not_calibrated".to_string()));
+    assert_eq!(world.gantry_command("calibrate", ""), Ok(()));
+    assert_eq!(world.gantry_command("lock", ""), Ok(()));
+    assert_eq!(world.gantry_command("move", "pipe_blue_box"), Err("gantry_locked".to_string()));
+    assert_eq!(world.gantry_position, "home");
+
+    assert_eq!(world.robot_command("move", "pipe_blue_box"), Ok(()));
+    assert_eq!(world.scan("pipe"), "pipe");
+    assert_eq!(world.robot_command("pick", ""), Err("wrong_tool".to_string()));
+    assert_eq!(world.robot_command("mount", ""), Err("tool_already_mounted".to_string()));
+
+    assert_eq!(world.robot_command("move", "suction_tool_rack"), Ok(()));
+    assert_eq!(world.robot_command("unmount", ""), Ok(()));
+    assert_eq!(world.robot_command("move", "gripper_tool_rack"), Ok(()));
+    assert_eq!(world.robot_command("mount", ""), Ok(()));
+    assert_eq!(world.robot_command("move", "pipe_blue_box"), Ok(()));
+    assert_eq!(world.robot_command("pick", ""), Ok(()));
+    assert_eq!(world.robot_holding, "pipe");
+    assert_eq!(world.robot_command("move", "plate_pipe_box"), Ok(()));
+    assert_eq!(world.robot_command("place", ""), Ok(()));
+    assert_eq!(world.item_positions.get("pipe"), Some(&"plate_pipe_box".to_string()));
+}
+
+#[test]
+fn test_world_silent_failures() {
+    let mut world = World::new(0);
+    world.robot_mounted = "none".to_string();
+    world.robot_position = "gripper_tool_rack".to_string();
+    assert!(world.silent_failure("robot", "mount", ""));
+    assert!(world.silent_failure("gantry", "lock", ""));
+    assert!(!world.silent_failure("robot", "check_mounted_tool", ""));
+
+    assert_eq!(world.robot_command("check_mounted_tool", ""), Ok(()));
+    let detected: Vec<bool> = world.silent_failures.iter().map(|sf| sf.detected).collect();
+    assert_eq!(detected, vec![true, false]);
+
+    // The retried mount succeeds, so the silent failure before it is not outstanding
+    assert!(world.silent_failure("robot", "mount", ""));
+    assert_eq!(world.robot_command("mount", ""), Ok(()));
+    assert_eq!(world.robot_command("check_mounted_tool", ""), Ok(()));
+    let detected: Vec<bool> = world.silent_failures.iter().map(|sf| sf.detected).collect();
+    assert_eq!(detected, vec![true, false, false]);
+}
@@ -0,0 +1,57 @@
+use crate::*;
+use micro_sp::*;
+use crate::interfaces::msgs::{Emulation, TriggerCameraSystem};
+use tokio::sync::mpsc;
+
+pub struct CameraSystemClient {
+    pub prefix: String,
+}
+
+impl CameraSystemClient {
+    pub fn new(prefix: &str) -> CameraSystemClient {
+        CameraSystemClient {
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl DeviceClient for CameraSystemClient {
+    type Service = TriggerCameraSystem::Service;
+
+    fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn describe(&self, state: &State, target: &str) -> String {
+        format!(
+            "{} of '{}'",
+            self.command(state, target),
+            state.get_or_default_string(target, &format!("{}_update_command", self.prefix))
+        )
+    }
+
+    fn request(&self, state: &State, target: &str, emulated_response: Emulation) -> TriggerCameraSystem::Request {
+        TriggerCameraSystem::Request {
+            command: self.command(state, target),
+            blue_box: state.get_or_default_string(target, &format!("{}_update_command", self.prefix)),
+            emulated_response,
+        }
+    }
+
+    fn outcome(response: &TriggerCameraSystem::Response) -> (bool, String, String) {
+        (response.success, response.failure_cause.clone(), response.info.clone())
+    }
+
+    // The blue box positions are updated by the operation that requested the update
+    fn map_response(&self, state: &State, _target: &str, _response: &TriggerCameraSystem::Response) -> State {
+        state.clone()
+    }
+}
+
+pub async fn camera_system_client_ticker(
+    transport: impl Transport,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    device_client_ticker(CameraSystemClient::new("camera_system"), transport, ticker_rate, command_sender).await
+}
@@ -0,0 +1,228 @@
+use crate::*;
+use micro_sp::*;
+use crate::interfaces::msgs::Emulation;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Duration};
+
+pub type RequestOf<D> = <<D as DeviceClient>::Service as ServiceType>::Request;
+pub type ResponseOf<D> = <<D as DeviceClient>::Service as ServiceType>::Response;
+
+// A device that is driven with the request trigger protocol:
+//
+// {prefix}_request_trigger: set by an operation to issue {prefix}_command_command
+// {prefix}_request_state: initial -> succeeded / failed, reset by the operation
+// {prefix}_failure_cause: cause of the last failed request
+// {prefix}_info: info of the last response, or why there was none
+// {prefix}_fail_counter_{cause}: failures per cause, counted if the variable exists
+// {prefix}_total_fail_counter, {prefix}_subsequent_fail_counter
+// {prefix}_request_timeout: milliseconds to wait for the response, 0 waits forever
+//
+// A device only has to say how a request is built from the state, and how
+// the response is mapped back to the state, see device_client_ticker.
+pub trait DeviceClient {
+    type Service: ServiceType + Send + 'static;
+
+    // Prefix of the device variables, i.e. "gantry" for gantry_request_trigger
+    fn prefix(&self) -> &str;
+
+    fn service_name(&self) -> String {
+        format!("/{}_emulator_service", self.prefix())
+    }
+
+    fn command(&self, state: &State, target: &str) -> String {
+        state.get_or_default_string(target, &format!("{}_command_command", self.prefix()))
+    }
+
+    // Moves are emulated per position, see emulation_key_for_command
+    fn emulation_key(&self, state: &State, target: &str) -> EmulationKey {
+        EmulationKey::new(self.prefix(), &self.command(state, target), None)
+    }
+
+    // I.e. "move to 'home'", for the logs
+    fn describe(&self, state: &State, target: &str) -> String {
+        self.command(state, target)
+    }
+
+    fn request(&self, state: &State, target: &str, emulated_response: Emulation) -> RequestOf<Self>;
+
+    // Whether the request succeeded, the failure cause if it didn't, and the info of the device
+    fn outcome(response: &ResponseOf<Self>) -> (bool, String, String);
+
+    // Updates the estimated and measured variables of the device with the response,
+    // the protocol variables are handled by the ticker
+    fn map_response(&self, state: &State, target: &str, response: &ResponseOf<Self>) -> State;
+}
+
+pub enum RequestOutcome<R> {
+    Response(R),
+    // No response within the request timeout
+    Timeout,
+    // The request couldn't be sent or the response couldn't be received
+    Error(String),
+}
+
+// Applies the outcome of a request to the protocol variables and the variables of the device
+pub fn apply_request_outcome<D: DeviceClient>(
+    device: &D,
+    state: &State,
+    target: &str,
+    outcome: RequestOutcome<ResponseOf<D>>,
+) -> State {
+    let prefix = device.prefix();
+    let var = |name: &str| format!("{}_{}", prefix, name);
+    let description = device.describe(state, target);
+    let total_fail_counter = state.get_or_default_i64(target, &var("total_fail_counter"));
+    let subsequent_fail_counter = state.get_or_default_i64(target, &var("subsequent_fail_counter"));
+
+    let (state, failure_cause, info) = match outcome {
+        RequestOutcome::Response(response) => {
+            let (success, cause, info) = D::outcome(&response);
+            let state = device
+                .map_response(state, target, &response)
+                .update(&var("info"), info.to_spvalue());
+            if success {
+                log_info!(target, "Requested {} succeeded.", description);
+                return state
+                    .update(&var("request_state"), ServiceRequestState::Succeeded.to_string().to_spvalue())
+                    .update(&var("subsequent_fail_counter"), 0.to_spvalue());
+            }
+            log_error!(target, "Requested {} failed with {}: {}", description, cause, info);
+            // A device that doesn't say why it failed fails generically
+            match cause.is_empty() {
+                true => (state, GENERIC_FAILURE.to_string(), info),
+                false => (state, cause, info),
+            }
+        }
+        RequestOutcome::Timeout => {
+            let info = format!(
+                "No response within {} ms.",
+                state.get_or_default_i64(target, &var("request_timeout"))
+            );
+            log_error!(target, "Requested {} failed: {}", description, info);
+            (state.clone(), TIMEOUT_FAILURE_CAUSE.to_string(), info)
+        }
+        RequestOutcome::Error(e) => {
+            log_error!(target, "Request failed with: {}.", e);
+            (state.clone(), GENERIC_FAILURE.to_string(), e)
+        }
+    };
+
+    let cause_counter = var(&format!("fail_counter_{}", failure_cause));
+    let state = match state.state.contains_key(&cause_counter) {
+        true => {
+            let count = state.get_or_default_i64(target, &cause_counter);
+            state.update(&cause_counter, (count + 1).to_spvalue())
+        }
+        false => state,
+    };
+    state
+        .update(&var("request_state"), ServiceRequestState::Failed.to_string().to_spvalue())
+        .update(&var("failure_cause"), failure_cause.to_spvalue())
+        .update(&var("info"), info.to_spvalue())
+        .update(&var("subsequent_fail_counter"), (subsequent_fail_counter + 1).to_spvalue())
+        .update(&var("total_fail_counter"), (total_fail_counter + 1).to_spvalue())
+}
+
+pub async fn device_client_ticker<D: DeviceClient, T: Transport>(
+    device: D,
+    transport: T,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    RequestOf<D>: Send + 'static,
+    ResponseOf<D>: Send + 'static,
+{
+    let prefix = device.prefix().to_string();
+    let client = transport.client::<D::Service>(&device.service_name())?;
+
+    let mut interval = interval(Duration::from_millis(ticker_rate));
+
+    let target = format!("{}_interface", prefix);
+    let target = target.as_str();
+    log_warn!(target, "Waiting for the server...");
+    client.wait_for_server().await?;
+    log_info!(target, "Server available.");
+
+    log_info!(target, "Spawned.");
+
+    loop {
+        let (response_tx, response_rx) = oneshot::channel();
+        command_sender.send(Command::GetState(response_tx)).await?;
+        let state = response_rx.await?;
+
+        let request_trigger =
+            state.get_or_default_bool(target, &format!("{}_request_trigger", prefix));
+        let request_state =
+            state.get_or_default_string(target, &format!("{}_request_state", prefix));
+
+        if request_trigger {
+            let mut new_state =
+                state.update(&format!("{}_request_trigger", prefix), false.to_spvalue());
+            if request_state == ServiceRequestState::Initial.to_string() {
+                log_info!(target, "Requesting to {}.", device.describe(&state, target));
+                let emulation_key = device.emulation_key(&state, target);
+                let request_timeout =
+                    state.get_or_default_i64(target, &format!("{}_request_timeout", prefix));
+
+                // An invalid emulation fails the request without sending it
+                let outcome = match emulation_for_command(&state, target, &emulation_key) {
+                    Ok(emulated_response) => {
+                        let request = device.request(&state, target, emulated_response);
+                        match request_with_timeout(client.request(&request), request_timeout).await {
+                            Some(Ok(response)) => RequestOutcome::Response(response),
+                            Some(Err(e)) => RequestOutcome::Error(e),
+                            None => RequestOutcome::Timeout,
+                        }
+                    }
+                    Err(e) => RequestOutcome::Error(format!("Invalid emulation: {}", e)),
+                };
+                new_state = apply_request_outcome(&device, &new_state, target, outcome);
+            }
+
+            let modified_state = state.get_diff_partial_state(&new_state);
+            command_sender
+                .send(Command::SetPartialState(modified_state))
+                .await?;
+        }
+
+        interval.tick().await;
+    }
+}
+
+#[test]
+fn test_apply_request_outcome() {
+    use crate::interfaces::msgs::TriggerGantry;
+
+    let state = crate::models::bt_test_endre::state::state()
+        .update("gantry_command_command", "move".to_spvalue())
+        .update("gantry_position_command", "home".to_spvalue());
+    let target = "test_apply_request_outcome";
+
+    let response = TriggerGantry::Response {
+        success: true,
+        failure_cause: "".to_string(),
+        info: "".to_string(),
+    };
+    let new_state = apply_request_outcome(&GantryClient::new("gantry"), &state, target, RequestOutcome::Response(response));
+    assert_eq!(new_state.get_value("gantry_request_state"), "succeeded".to_spvalue());
+    assert_eq!(new_state.get_value("gantry_position_estimated"), "home".to_spvalue());
+
+    let new_state = apply_request_outcome(&GantryClient::new("gantry"), &state, target, RequestOutcome::Timeout);
+    assert_eq!(new_state.get_value("gantry_request_state"), "failed".to_spvalue());
+    assert_eq!(new_state.get_value("gantry_failure_cause"), "timeout".to_spvalue());
+    assert_eq!(new_state.get_value("gantry_total_fail_counter"), 1.to_spvalue());
+    assert_eq!(new_state.get_value("gantry_subsequent_fail_counter"), 1.to_spvalue());
+    assert_eq!(new_state.get_value("gantry_fail_counter_timeout"), 1.to_spvalue());
+
+    let response = TriggerGantry::Response {
+        success: false,
+        failure_cause: "collision".to_string(),
+        info: "Failed to move to home due to collision.".to_string(),
+    };
+    let new_state = apply_request_outcome(&GantryClient::new("gantry"), &new_state, target, RequestOutcome::Response(response));
+    assert_eq!(new_state.get_value("gantry_failure_cause"), "collision".to_spvalue());
+    assert_eq!(new_state.get_value("gantry_info"), "Failed to move to home due to collision.".to_spvalue());
+    assert_eq!(new_state.get_value("gantry_fail_counter_collision"), 1.to_spvalue());
+    assert_eq!(new_state.get_value("gantry_total_fail_counter"), 2.to_spvalue());
+}
@@ -0,0 +1,81 @@
+use crate::*;
+use micro_sp::*;
+use crate::interfaces::msgs::Emulation;
+
+// The most specific variable that exists, i.e. {device}_{command}_{position}_*
+// before {device}_{command}_* before {device}_*. Variables can't be removed from
+// the state, so a variable that is UNKNOWN counts as not set.
+fn emulation_variable(state: &State, key: &EmulationKey, field: &str) -> String {
+    key.prefixes()
+        .iter()
+        .map(|prefix| format!("{prefix}_{field}"))
+        .find(|variable| match state.state.get(variable) {
+            Some(assignment) => assignment.val != SPValue::UNKNOWN,
+            None => false,
+        })
+        .unwrap_or(format!("{}_{}", key.device, field))
+}
+
+// Values that don't fit the field of the message are an invalid emulation, they would
+// wrap around otherwise, i.e. a weight of -1 would dominate all other causes
+fn field<T: TryFrom<i64>>(state: &State, target: &str, variable: &str) -> Result<T, String> {
+    let value = state.get_or_default_i64(target, variable);
+    T::try_from(value).map_err(|_| format!("Invalid {} {}, out of range.", variable, value))
+}
+
+fn array_of_u32(state: &State, variable: &str) -> Result<Vec<u32>, String> {
+    match state.get_value(variable) {
+        SPValue::Array(_, values) => values
+            .iter()
+            .filter_map(|value| match value {
+                SPValue::Int64(value) => Some(
+                    u32::try_from(*value)
+                        .map_err(|_| format!("Invalid {} {}, out of range.", variable, value)),
+                ),
+                _ => None,
+            })
+            .collect(),
+        _ => Ok(vec![]),
+    }
+}
+
+// The emulation parameters that are sent with the command that is being issued,
+// an error if one of them is out of range
+pub fn emulation_for_command(state: &State, target: &str, key: &EmulationKey) -> Result<Emulation, String> {
+    let var = |field: &str| emulation_variable(state, key, field);
+    Ok(Emulation {
+        emulate_execution_time: field(state, target, &var("emulate_execution_time"))?,
+        emulated_execution_time: field(state, target, &var("emulated_execution_time"))?,
+        emulated_execution_time_deviation: field(state, target, &var("emulated_execution_time_deviation"))?,
+        request_timeout: field(state, target, &format!("{}_request_timeout", key.device))?,
+        emulate_failure_rate: field(state, target, &var("emulate_failure_rate"))?,
+        emulated_failure_rate: field(state, target, &var("emulated_failure_rate"))?,
+        emulate_failure_cause: field(state, target, &var("emulate_failure_cause"))?,
+        emulated_failure_cause: state
+            .get_or_default_array_of_strings(target, &var("emulated_failure_cause")),
+        emulated_failure_cause_weights: array_of_u32(state, &var("emulated_failure_cause_weights"))?,
+        emulated_correlation_after: state
+            .get_or_default_array_of_strings(target, &var("emulated_correlation_after")),
+        emulated_correlation_cause: state
+            .get_or_default_array_of_strings(target, &var("emulated_correlation_cause")),
+        emulated_correlation_factor: array_of_u32(state, &var("emulated_correlation_factor"))?,
+        emulate_silent_failure: field(state, target, &var("emulate_silent_failure"))?,
+        emulated_silent_failure_rate: field(state, target, &var("emulated_silent_failure_rate"))?,
+    })
+}
+
+#[test]
+fn test_emulation_for_command_out_of_range() {
+    let state = crate::models::bt_test_endre::state::state();
+    let target = "test_emulation_for_command_out_of_range";
+    let key = EmulationKey::new("gantry", "calibrate", None);
+    assert!(emulation_for_command(&state, target, &key).is_ok());
+
+    let weights = state.update(
+        "gantry_emulated_failure_cause_weights",
+        SPValue::Array(SPValueType::Int64, vec![SPValue::Int64(1), SPValue::Int64(-1)]),
+    );
+    assert!(emulation_for_command(&weights, target, &key).is_err());
+    let mode = state.update("gantry_emulate_failure_rate", 258.to_spvalue());
+    assert!(emulation_for_command(&mode, target, &key).is_err());
+}
@@ -0,0 +1,188 @@
+use crate::*;
+use futures::{FutureExt, StreamExt};
+use micro_sp::*;
+use r2r::risk_assessment_msgs::action::ExecuteGoal;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+// Executes goals as the ROS 2 action /risk_assessment_runner/execute_goal.
+// A goal is submitted like with the submit_goal service, the feedback follows the plan
+// until it is completed or failed, and the result carries the FMEA report of the goal.
+// Goals are executed one at a time, a new goal is rejected while one is executing
+// and while a campaign or scenario run is active.
+pub async fn spawn_goal_action_server(
+    arc_node: Arc<Mutex<r2r::Node>>,
+    name: &str,
+    fmea: &FmeaTable,
+    devices: Vec<String>,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let server = arc_node
+        .lock()
+        .unwrap()
+        .create_action_server::<ExecuteGoal::Action>(&format!("/{}/execute_goal", NODE_ID))?;
+
+    let name = name.to_string();
+    let fmea = fmea.clone();
+    tokio::task::spawn(async move {
+        match goal_action_server(server, &name, &fmea, &devices, ticker_rate, command_sender, recorder_sender, run_active).await {
+            Ok(()) => r2r::log_info!(NODE_ID, "Goal action server succeeded."),
+            Err(e) => r2r::log_error!(NODE_ID, "Goal action server failed with: {}.", e),
+        };
+    });
+    Ok(())
+}
+
+async fn goal_action_server(
+    mut server: impl futures::Stream<Item = r2r::ActionServerGoalRequest<ExecuteGoal::Action>> + Unpin,
+    name: &str,
+    fmea: &FmeaTable,
+    devices: &[String],
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    r2r::log_info!(NODE_ID, "Goal action server spawned.");
+    // Every goal runs in its own task, so that the server keeps answering new goals
+    let mut executing: Option<(String, tokio::task::JoinHandle<()>)> = None;
+    loop {
+        match server.next().await {
+            Some(request) => {
+                let goal = request.goal.goal.clone();
+                if run_active.load(Ordering::SeqCst) {
+                    r2r::log_warn!(NODE_ID, "Rejected goal '{}', {}", goal, RUN_ACTIVE_INFO);
+                    request.reject().map_err(|e| e.to_string())?;
+                    continue;
+                }
+                if let Some((executing_goal, handle)) = &executing {
+                    if !handle.is_finished() {
+                        r2r::log_warn!(NODE_ID, "Rejected goal '{}', goal '{}' is still executing.", goal, executing_goal);
+                        request.reject().map_err(|e| e.to_string())?;
+                        continue;
+                    }
+                }
+                let state = get_state(&command_sender).await?;
+                if let Err(e) = parse_goal(&goal, &state) {
+                    r2r::log_warn!(NODE_ID, "Rejected goal: {}", e);
+                    request.reject().map_err(|e| e.to_string())?;
+                    continue;
+                }
+
+                let (name, fmea, devices) = (name.to_string(), fmea.clone(), devices.to_vec());
+                let (command_sender, recorder_sender) = (command_sender.clone(), recorder_sender.clone());
+                let goal_clone = goal.clone();
+                let handle = tokio::task::spawn(async move {
+                    match execute_goal(request, &name, &fmea, &devices, ticker_rate, &command_sender, &recorder_sender).await {
+                        Ok(()) => (),
+                        Err(e) => r2r::log_error!(NODE_ID, "Goal '{}' failed with: {}.", goal_clone, e),
+                    }
+                });
+                executing = Some((goal, handle));
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+async fn execute_goal(
+    request: r2r::ActionServerGoalRequest<ExecuteGoal::Action>,
+    name: &str,
+    fmea: &FmeaTable,
+    devices: &[String],
+    ticker_rate: u64,
+    command_sender: &mpsc::Sender<Command>,
+    recorder_sender: &mpsc::Sender<RecorderCommand>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = format!("{}_goal_action", name);
+    let target = target.as_str();
+    let goal = request.goal.goal.clone();
+    let goal = goal.as_str();
+    let (mut goal_handle, mut cancel_requests) = request.accept().map_err(|e| e.to_string())?;
+    r2r::log_warn!(NODE_ID, "Executing goal '{}'.", goal);
+    let log_at_start = get_log(recorder_sender).await?;
+    let state = get_state(command_sender).await?;
+
+    // Failed device requests since the goal was accepted, each one consumes a retry
+    let fail_counter = |state: &State, device: &str| {
+        state.get_or_default_i64(target, &format!("{}_total_fail_counter", device))
+    };
+    let fail_counters_at_start: HashMap<&String, i64> =
+        devices.iter().map(|d| (d, fail_counter(&state, d))).collect();
+    let mut last_fail_counters = fail_counters_at_start.clone();
+    let mut failure_cause = "".to_string();
+    let mut retries = 0;
+    let mut last_feedback = None;
+
+    // The feedback follows the sampled states, a cancel request ends the goal
+    let mut observe = |state: &State| {
+        retries = 0;
+        for device in devices {
+            let counter = fail_counter(state, device);
+            if counter > last_fail_counters[device] {
+                failure_cause = state.get_or_default_string(target, &format!("{}_failure_cause", device));
+            }
+            last_fail_counters.insert(device, counter);
+            retries = retries + counter - fail_counters_at_start[device];
+        }
+
+        let plan = plan_of(name, state);
+        let current_step = state.get_or_default_i64(target, &format!("{}_plan_current_step", name));
+        let feedback = ExecuteGoal::Feedback {
+            current_operation: plan.get(current_step as usize).cloned().unwrap_or_default(),
+            plan,
+            current_step,
+            retries,
+            failure_cause: failure_cause.clone(),
+        };
+        // Only changes are sent as feedback
+        if last_feedback.as_ref() != Some(&feedback) {
+            if let Err(e) = goal_handle.publish_feedback(feedback.clone()) {
+                r2r::log_warn!(NODE_ID, "Can't publish the feedback of goal '{}': {}.", goal, e);
+            }
+            last_feedback = Some(feedback);
+        }
+
+        match cancel_requests.next().now_or_never() {
+            Some(Some(cancel_request)) => {
+                r2r::log_warn!(NODE_ID, "Cancelling goal '{}'.", goal);
+                cancel_request.accept();
+                false
+            }
+            _ => true,
+        }
+    };
+    let outcome = run_goal(name, goal, None, ticker_rate, command_sender, &mut observe).await?;
+
+    let state = get_state(command_sender).await?;
+    let log = get_log(recorder_sender).await?.since(&log_at_start);
+    let info = format!("Goal '{}' {} after {} retries.", goal, outcome, retries);
+    r2r::log_warn!(NODE_ID, "{}", info);
+    let result = goal_result(name, &state, fmea, &log, &info);
+    match outcome {
+        RunOutcome::Completed => goal_handle.succeed(result),
+        RunOutcome::Cancelled => goal_handle.cancel(result),
+        _ => goal_handle.abort(result),
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// The risk summary only has the failure modes of the operations that ran for the goal
+pub fn goal_result(name: &str, state: &State, fmea: &FmeaTable, log: &RiskLog, info: &str) -> ExecuteGoal::Result {
+    let plan_state = state.get_or_default_string(&format!("{}_goal_action", name), &format!("{}_plan_state", name));
+    let mut report = FmeaReport::new(fmea, log);
+    report.rows.retain(|row| row.executions > 0 || row.observed_failures > 0);
+    ExecuteGoal::Result {
+        success: PlanState::from_str(&plan_state) == PlanState::Completed,
+        plan_state,
+        plan: plan_of(name, state),
+        info: info.to_string(),
+        risk_summary: report.to_json().to_string(),
+    }
+}
@@ -1,7 +1,16 @@
 // pub mod ticker;
-// pub mod scanner_client_ticker;
 // pub mod gripper_client_ticker;
+pub mod camera_system_client_ticker;
+pub mod device_client;
+pub mod emulation;
 pub mod gantry_client_ticker;
+#[cfg(feature = "ros")]
+pub mod goal_action;
+pub mod msgs;
 pub mod robot_client_ticker;
+pub mod runner_services;
+pub mod scanner_client_ticker;
+pub mod timeout;
+pub mod transport;
 // pub mod set_state_server;
 // pub mod state_publisher;
\ No newline at end of file
@@ -0,0 +1,76 @@
+// The messages of the device services. With the ros feature these are the ones
+// generated from risk_assessment_msgs, without it plain types with the same fields,
+// so that the client tickers and the emulators also run over the ChannelTransport.
+#[cfg(feature = "ros")]
+pub use r2r::risk_assessment_msgs::msg::Emulation;
+#[cfg(feature = "ros")]
+pub use r2r::risk_assessment_msgs::srv::{TriggerCameraSystem, TriggerGantry, TriggerRobot, TriggerScanner};
+
+#[cfg(not(feature = "ros"))]
+pub use self::plain::*;
+
+#[cfg(not(feature = "ros"))]
+#[allow(non_snake_case)]
+mod plain {
+    // See risk_assessment_msgs/msg/Emulation.msg for the values
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct Emulation {
+        pub emulate_execution_time: u8,
+        pub emulated_execution_time: i32,
+        pub emulated_execution_time_deviation: i32,
+        pub request_timeout: i32,
+        pub emulate_failure_rate: u8,
+        pub emulated_failure_rate: i32,
+        pub emulate_failure_cause: u8,
+        pub emulated_failure_cause: Vec<String>,
+        pub emulated_failure_cause_weights: Vec<u32>,
+        pub emulated_correlation_after: Vec<String>,
+        pub emulated_correlation_cause: Vec<String>,
+        pub emulated_correlation_factor: Vec<u32>,
+        pub emulate_silent_failure: u8,
+        pub emulated_silent_failure_rate: i32,
+    }
+
+    // A service laid out like the generated ones, i.e. TriggerGantry::Request
+    macro_rules! service {
+        ($service:ident { $($request:ident: $request_type:ty),* } { $($response:ident: $response_type:ty),* }) => {
+            pub mod $service {
+                use super::Emulation;
+
+                #[derive(Debug, Clone, Default, PartialEq)]
+                pub struct Request {
+                    $(pub $request: $request_type,)*
+                }
+
+                #[derive(Debug, Clone, Default, PartialEq)]
+                pub struct Response {
+                    $(pub $response: $response_type,)*
+                }
+
+                pub struct Service;
+
+                impl crate::ServiceType for Service {
+                    type Request = Request;
+                    type Response = Response;
+                }
+            }
+        };
+    }
+
+    service!(
+        TriggerGantry { command: String, speed: f32, position: String, emulated_response: Emulation }
+        { success: bool, failure_cause: String, info: String }
+    );
+    service!(
+        TriggerRobot { command: String, speed: f32, position: String, emulated_response: Emulation }
+        { success: bool, failure_cause: String, info: String, checked_mounted_tool: String }
+    );
+    service!(
+        TriggerScanner { command: String, item: String, emulated_response: Emulation }
+        { success: bool, failure_cause: String, info: String, scanned_item: String }
+    );
+    service!(
+        TriggerCameraSystem { command: String, blue_box: String, emulated_response: Emulation }
+        { success: bool, failure_cause: String, info: String }
+    );
+}
@@ -0,0 +1,411 @@
+use crate::*;
+use micro_sp::*;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+// Only the services need ROS 2, the state functions are also used by the campaigns
+// and the scenarios
+#[cfg(feature = "ros")]
+use futures::{Stream, StreamExt};
+#[cfg(feature = "ros")]
+use r2r::risk_assessment_msgs::srv::{
+    CancelGoal, GetPlanStatus, GetVariables, SetVariables, SubmitGoal,
+};
+#[cfg(feature = "ros")]
+use r2r::{QosProfile, ServiceRequest};
+#[cfg(feature = "ros")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "ros")]
+use std::sync::{Arc, Mutex};
+
+// Runner side services to drive the runner from outside, i.e. by operators or test harnesses:
+//
+// /risk_assessment_runner/get_variables
+// /risk_assessment_runner/set_variables
+// /risk_assessment_runner/submit_goal
+// /risk_assessment_runner/cancel_goal
+// /risk_assessment_runner/get_plan_status
+//
+// Every call is turned into commands to the state manager. Goals and variables are
+// rejected while a campaign or scenario run is active, it submits and cancels its own
+// goals and sets its own variables.
+#[cfg(feature = "ros")]
+pub async fn spawn_runner_services(
+    arc_node: Arc<Mutex<r2r::Node>>,
+    name: &str,
+    command_sender: mpsc::Sender<Command>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let service_name = |service: &str| format!("/{}/{}", NODE_ID, service);
+    let (get_variables, set_variables, submit_goal, cancel_goal, get_plan_status) = {
+        let mut node = arc_node.lock().unwrap();
+        (
+            node.create_service::<GetVariables::Service>(&service_name("get_variables"), QosProfile::default())?,
+            node.create_service::<SetVariables::Service>(&service_name("set_variables"), QosProfile::default())?,
+            node.create_service::<SubmitGoal::Service>(&service_name("submit_goal"), QosProfile::default())?,
+            node.create_service::<CancelGoal::Service>(&service_name("cancel_goal"), QosProfile::default())?,
+            node.create_service::<GetPlanStatus::Service>(&service_name("get_plan_status"), QosProfile::default())?,
+        )
+    };
+
+    let tx = command_sender.clone();
+    tokio::task::spawn(async move {
+        log_service_result("get_variables", get_variables_server(get_variables, tx).await)
+    });
+    let (name_clone, tx, run_active_clone) = (name.to_string(), command_sender.clone(), run_active.clone());
+    tokio::task::spawn(async move {
+        log_service_result("set_variables", set_variables_server(set_variables, &name_clone, tx, run_active_clone).await)
+    });
+    let (name_clone, tx, run_active_clone) = (name.to_string(), command_sender.clone(), run_active.clone());
+    tokio::task::spawn(async move {
+        log_service_result("submit_goal", submit_goal_server(submit_goal, &name_clone, tx, run_active_clone).await)
+    });
+    let (name_clone, tx, run_active_clone) = (name.to_string(), command_sender.clone(), run_active.clone());
+    tokio::task::spawn(async move {
+        log_service_result("cancel_goal", cancel_goal_server(cancel_goal, &name_clone, tx, run_active_clone).await)
+    });
+    let (name_clone, tx) = (name.to_string(), command_sender.clone());
+    tokio::task::spawn(async move {
+        log_service_result("get_plan_status", get_plan_status_server(get_plan_status, &name_clone, tx).await)
+    });
+    Ok(())
+}
+
+#[cfg(feature = "ros")]
+pub static RUN_ACTIVE_INFO: &str = "a campaign or scenario run is active.";
+
+#[cfg(feature = "ros")]
+fn log_service_result(service: &str, result: Result<(), Box<dyn std::error::Error>>) {
+    match result {
+        Ok(()) => r2r::log_info!(NODE_ID, "Service {} succeeded.", service),
+        Err(e) => r2r::log_error!(NODE_ID, "Service {} failed with: {}.", service, e),
+    };
+}
+
+// The values of the variables as typed JSON, all variables if no names are given
+pub fn get_variables(state: &State, names: &[String]) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut names = names.to_vec();
+    if names.is_empty() {
+        names = state.state.keys().cloned().collect();
+        names.sort();
+    }
+    let mut values = vec![];
+    for name in &names {
+        match state.state.get(name) {
+            Some(assignment) => values.push(spvalue_to_json(&assignment.val).to_string()),
+            None => return Err(format!("Variable '{}' doesn't exist.", name)),
+        }
+    }
+    Ok((names, values))
+}
+
+// Converts a JSON value to a value of the given type, null is UNKNOWN for every type
+pub fn json_to_spvalue(value_type: &SPValueType, element_type: &SPValueType, value: &Value) -> Result<SPValue, String> {
+    match (value_type, value) {
+        (_, Value::Null) => Ok(SPValue::UNKNOWN),
+        (SPValueType::Bool, Value::Bool(val)) => Ok(val.to_spvalue()),
+        (SPValueType::Int64, Value::Number(val)) if val.is_i64() => Ok(val.as_i64().unwrap().to_spvalue()),
+        (SPValueType::Float64, Value::Number(val)) => Ok(val.as_f64().unwrap().to_spvalue()),
+        (SPValueType::String, Value::String(val)) => Ok(val.to_spvalue()),
+        (SPValueType::Array, Value::Array(values)) => {
+            let mut elements = vec![];
+            for value in values {
+                elements.push(json_to_spvalue(element_type, &SPValueType::UNKNOWN, value)?);
+            }
+            Ok(SPValue::Array(element_type.clone(), elements))
+        }
+        _ => Err(format!("Expected a value of type {:?}, got '{}'.", value_type, value)),
+    }
+}
+
+// Checks the values against the types of the variables, nothing is set if one of them is invalid
+pub fn set_variables(state: &State, names: &[String], values: &[String]) -> Result<State, String> {
+    if names.len() != values.len() {
+        return Err(format!("Got {} names but {} values.", names.len(), values.len()));
+    }
+    let mut new_state = state.clone();
+    for (name, value) in names.iter().zip(values.iter()) {
+        let assignment = match state.state.get(name) {
+            Some(assignment) => assignment,
+            None => return Err(format!("Variable '{}' doesn't exist.", name)),
+        };
+        let value: Value = serde_json::from_str(value)
+            .map_err(|e| format!("Value of '{}' is not valid JSON: {}", name, e))?;
+        // Arrays keep the type of their elements, new arrays default to strings
+        let element_type = match &assignment.val {
+            SPValue::Array(element_type, _) => element_type.clone(),
+            _ => SPValueType::String,
+        };
+        let value = json_to_spvalue(&assignment.var.value_type, &element_type, &value)
+            .map_err(|e| format!("Can't set '{}': {}", name, e))?;
+        new_state = new_state.update(name, value);
+    }
+    Ok(new_state)
+}
+
+// The goal and the plan are only changed with submit_goal and cancel_goal,
+// so that a goal is always parsed before the planner gets it
+pub fn check_settable(name: &str, names: &[String]) -> Result<(), String> {
+    let runner_variables: Vec<String> = ["goal", "replan_trigger", "replanned", "plan", "plan_state", "plan_current_step"]
+        .iter()
+        .map(|variable| format!("{}_{}", name, variable))
+        .collect();
+    match names.iter().find(|n| runner_variables.contains(n)) {
+        Some(n) => Err(format!("Variable '{}' can't be set, use submit_goal or cancel_goal.", n)),
+        None => Ok(()),
+    }
+}
+
+// Parses the goal against the state like the planner does, so that an invalid goal
+// is rejected instead of taking down the runner. The parser panics on errors.
+pub fn parse_goal(goal: &str, state: &State) -> Result<(), String> {
+    if goal.trim().is_empty() {
+        return Err("The goal is empty.".to_string());
+    }
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        Transition::parse("goal", goal, "true", vec![], vec![], state)
+    }))
+    .map(|_| ())
+    .map_err(|e| {
+        let error = match (e.downcast_ref::<String>(), e.downcast_ref::<&str>()) {
+            (Some(error), _) => error.clone(),
+            (None, Some(error)) => error.to_string(),
+            (None, None) => "parse error".to_string(),
+        };
+        format!("Invalid goal '{}': {}", goal, error)
+    })
+}
+
+pub fn submit_goal(name: &str, state: &State, goal: &str) -> State {
+    state
+        .update(&format!("{}_goal", name), goal.to_spvalue())
+        .update(&format!("{}_replan_trigger", name), true.to_spvalue())
+        .update(&format!("{}_replanned", name), false.to_spvalue())
+}
+
+// A cancelled plan ends as failed, the running operation is not interrupted
+pub fn cancel_goal(name: &str, state: &State) -> State {
+    state
+        .update(&format!("{}_goal", name), "".to_spvalue())
+        .update(&format!("{}_plan", name), SPValue::Array(SPValueType::String, vec![]))
+        .update(&format!("{}_plan_state", name), PlanState::Failed.to_string().to_spvalue())
+        .update(&format!("{}_replan_trigger", name), false.to_spvalue())
+}
+
+pub fn plan_of(name: &str, state: &State) -> Vec<String> {
+    match state.get_value(&format!("{}_plan", name)) {
+        SPValue::Array(_, steps) => steps
+            .iter()
+            .map(|step| match step {
+                SPValue::String(step) => step.clone(),
+                _ => step.to_string(),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+pub async fn get_state(command_sender: &mpsc::Sender<Command>) -> Result<State, Box<dyn std::error::Error>> {
+    let (response_tx, response_rx) = oneshot::channel();
+    command_sender.send(Command::GetState(response_tx)).await?;
+    Ok(response_rx.await?)
+}
+
+#[cfg(feature = "ros")]
+async fn set_state(
+    command_sender: &mpsc::Sender<Command>,
+    state: &State,
+    new_state: &State,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let modified_state = state.get_diff_partial_state(new_state);
+    command_sender.send(Command::SetPartialState(modified_state)).await?;
+    Ok(())
+}
+
+#[cfg(feature = "ros")]
+async fn get_variables_server(
+    mut service: impl Stream<Item = ServiceRequest<GetVariables::Service>> + Unpin,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match service.next().await {
+            Some(request) => {
+                let state = get_state(&command_sender).await?;
+                let response = match get_variables(&state, &request.message.names) {
+                    Ok((names, values)) => GetVariables::Response {
+                        success: true,
+                        info: format!("Got {} variables.", names.len()),
+                        names,
+                        values,
+                    },
+                    Err(e) => GetVariables::Response {
+                        success: false,
+                        info: e,
+                        names: vec![],
+                        values: vec![],
+                    },
+                };
+                request.respond(response).expect("Could not send service response.");
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(feature = "ros")]
+async fn set_variables_server(
+    mut service: impl Stream<Item = ServiceRequest<SetVariables::Service>> + Unpin,
+    name: &str,
+    command_sender: mpsc::Sender<Command>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match service.next().await {
+            Some(request) => {
+                let state = get_state(&command_sender).await?;
+                let checked = match run_active.load(Ordering::SeqCst) {
+                    true => Err(format!("Can't set variables, {}", RUN_ACTIVE_INFO)),
+                    false => check_settable(name, &request.message.names),
+                };
+                let new_state = checked
+                    .and_then(|()| set_variables(&state, &request.message.names, &request.message.values));
+                let response = match new_state {
+                    Ok(new_state) => {
+                        set_state(&command_sender, &state, &new_state).await?;
+                        r2r::log_warn!(NODE_ID, "Variables {:?} set from outside.", request.message.names);
+                        SetVariables::Response {
+                            success: true,
+                            info: format!("Set {} variables.", request.message.names.len()),
+                        }
+                    }
+                    Err(e) => SetVariables::Response { success: false, info: e },
+                };
+                request.respond(response).expect("Could not send service response.");
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(feature = "ros")]
+async fn submit_goal_server(
+    mut service: impl Stream<Item = ServiceRequest<SubmitGoal::Service>> + Unpin,
+    name: &str,
+    command_sender: mpsc::Sender<Command>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match service.next().await {
+            Some(request) => {
+                let state = get_state(&command_sender).await?;
+                let parsed = match run_active.load(Ordering::SeqCst) {
+                    true => Err(format!("Can't submit a goal, {}", RUN_ACTIVE_INFO)),
+                    false => parse_goal(&request.message.goal, &state),
+                };
+                let response = match parsed {
+                    Err(e) => SubmitGoal::Response { success: false, info: e },
+                    Ok(()) => {
+                        set_state(&command_sender, &state, &submit_goal(name, &state, &request.message.goal)).await?;
+                        r2r::log_warn!(NODE_ID, "Goal '{}' submitted.", request.message.goal);
+                        SubmitGoal::Response {
+                            success: true,
+                            info: format!("Goal '{}' submitted.", request.message.goal),
+                        }
+                    }
+                };
+                request.respond(response).expect("Could not send service response.");
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(feature = "ros")]
+async fn cancel_goal_server(
+    mut service: impl Stream<Item = ServiceRequest<CancelGoal::Service>> + Unpin,
+    name: &str,
+    command_sender: mpsc::Sender<Command>,
+    run_active: Arc<AtomicBool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match service.next().await {
+            Some(request) => {
+                let response = match run_active.load(Ordering::SeqCst) {
+                    true => CancelGoal::Response {
+                        success: false,
+                        info: format!("Can't cancel the goal, {}", RUN_ACTIVE_INFO),
+                    },
+                    false => {
+                        let state = get_state(&command_sender).await?;
+                        set_state(&command_sender, &state, &cancel_goal(name, &state)).await?;
+                        r2r::log_warn!(NODE_ID, "Goal cancelled.");
+                        CancelGoal::Response {
+                            success: true,
+                            info: "Goal cancelled.".to_string(),
+                        }
+                    }
+                };
+                request.respond(response).expect("Could not send service response.");
+            }
+            None => (),
+        }
+    }
+}
+
+#[cfg(feature = "ros")]
+async fn get_plan_status_server(
+    mut service: impl Stream<Item = ServiceRequest<GetPlanStatus::Service>> + Unpin,
+    name: &str,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match service.next().await {
+            Some(request) => {
+                let state = get_state(&command_sender).await?;
+                let target = format!("{}_services", name);
+                let response = GetPlanStatus::Response {
+                    success: true,
+                    info: "".to_string(),
+                    goal: state.get_or_default_string(&target, &format!("{}_goal", name)),
+                    plan_state: state.get_or_default_string(&target, &format!("{}_plan_state", name)),
+                    plan: plan_of(name, &state),
+                    current_step: state.get_or_default_i64(&target, &format!("{}_plan_current_step", name)),
+                };
+                request.respond(response).expect("Could not send service response.");
+            }
+            None => (),
+        }
+    }
+}
+
+#[test]
+fn test_set_variables() {
+    let state = crate::models::bt_test_endre::state::state();
+
+    let names = vec!["gantry_locked_estimated".to_string(), "gantry_request_timeout".to_string()];
+    let new_state = set_variables(&state, &names, &["true".to_string(), "3000".to_string()]).unwrap();
+    assert_eq!(new_state.get_value("gantry_locked_estimated"), true.to_spvalue());
+    assert_eq!(new_state.get_value("gantry_request_timeout"), 3000.to_spvalue());
+    let (_, values) = get_variables(&new_state, &names).unwrap();
+    assert_eq!(values, vec!["true", "3000"]);
+
+    // Wrong types and unknown variables are rejected
+    assert!(set_variables(&state, &names, &["\"yes\"".to_string(), "3000".to_string()]).is_err());
+    assert!(set_variables(&state, &["no_such_variable".to_string()], &["1".to_string()]).is_err());
+
+    let new_state = set_variables(&state, &["gantry_locked_estimated".to_string()], &["null".to_string()]).unwrap();
+    assert_eq!(new_state.get_value("gantry_locked_estimated"), SPValue::UNKNOWN);
+
+    // The goal is only set with submit_goal
+    assert!(check_settable("bt_test_endre", &names).is_ok());
+    assert!(check_settable("bt_test_endre", &["bt_test_endre_goal".to_string()]).is_err());
+    assert!(check_settable("bt_test_endre", &["bt_test_endre_replan_trigger".to_string()]).is_err());
+}
+
+#[test]
+fn test_parse_goal() {
+    let state = crate::models::bt_test_endre::state::state();
+    assert!(parse_goal("var:robot_mounted_estimated == suction_tool", &state).is_ok());
+    assert!(parse_goal("var:robot_mounted_estimated ==", &state).is_err());
+    assert!(parse_goal(" ", &state).is_err());
+}
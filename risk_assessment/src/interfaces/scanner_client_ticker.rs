@@ -0,0 +1,64 @@
+use crate::*;
+use micro_sp::*;
+use crate::interfaces::msgs::{Emulation, TriggerScanner};
+use tokio::sync::mpsc;
+
+pub struct ScannerClient {
+    pub prefix: String,
+}
+
+impl ScannerClient {
+    pub fn new(prefix: &str) -> ScannerClient {
+        ScannerClient {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn var(&self, name: &str) -> String {
+        format!("{}_{}", self.prefix, name)
+    }
+}
+
+impl DeviceClient for ScannerClient {
+    type Service = TriggerScanner::Service;
+
+    fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn describe(&self, state: &State, target: &str) -> String {
+        format!(
+            "{} of '{}'",
+            self.command(state, target),
+            state.get_or_default_string(target, &self.var("item_command"))
+        )
+    }
+
+    fn request(&self, state: &State, target: &str, emulated_response: Emulation) -> TriggerScanner::Request {
+        TriggerScanner::Request {
+            command: self.command(state, target),
+            item: state.get_or_default_string(target, &self.var("item_command")),
+            emulated_response,
+        }
+    }
+
+    fn outcome(response: &TriggerScanner::Response) -> (bool, String, String) {
+        (response.success, response.failure_cause.clone(), response.info.clone())
+    }
+
+    fn map_response(&self, state: &State, _target: &str, response: &TriggerScanner::Response) -> State {
+        let scanned_item = match response.success {
+            true => response.scanned_item.clone(),
+            false => "UNKNOWN".to_string(),
+        };
+        state.update(&self.var("scanned_item_measured"), scanned_item.to_spvalue())
+    }
+}
+
+pub async fn scanner_client_ticker(
+    transport: impl Transport,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    device_client_ticker(ScannerClient::new("scanner"), transport, ticker_rate, command_sender).await
+}
@@ -0,0 +1,17 @@
+use std::future::Future;
+use tokio::time::{timeout, Duration};
+
+// Failure cause of requests that got no response within the timeout
+pub static TIMEOUT_FAILURE_CAUSE: &'static str = "timeout";
+
+// Waits for the response of a request, None if there was no response within
+// the timeout. A timeout of 0 milliseconds or less waits forever.
+pub async fn request_with_timeout<T, E>(
+    future: impl Future<Output = Result<T, E>>,
+    timeout_ms: i64,
+) -> Option<Result<T, E>> {
+    match timeout_ms {
+        t if t <= 0 => Some(future.await),
+        t => timeout(Duration::from_millis(t as u64), future).await.ok(),
+    }
+}
@@ -0,0 +1,298 @@
+use futures::stream::BoxStream;
+use futures::StreamExt;
+#[cfg(feature = "ros")]
+use r2r::QosProfile;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+// The request and response types of a service, the generated ROS 2 services with the
+// ros feature, the plain ones of msgs without it
+#[cfg(feature = "ros")]
+pub use r2r::WrappedServiceTypeSupport as ServiceType;
+
+#[cfg(not(feature = "ros"))]
+pub trait ServiceType {
+    type Request: Clone;
+    type Response;
+}
+
+pub type ResponseFuture<S> =
+    Pin<Box<dyn Future<Output = Result<<S as ServiceType>::Response, String>> + Send>>;
+
+// A request as the server receives it, answered with respond
+pub struct TransportRequest<S: ServiceType> {
+    pub message: S::Request,
+    responder: Box<dyn FnOnce(S::Response) -> Result<(), String> + Send>,
+}
+
+impl<S: ServiceType> TransportRequest<S> {
+    pub fn respond(self, response: S::Response) -> Result<(), String> {
+        (self.responder)(response)
+    }
+}
+
+pub trait TransportClient<S: ServiceType>: Send + Sync {
+    // Resolves once the server is available
+    fn wait_for_server(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+    fn request(&self, request: &S::Request) -> ResponseFuture<S>;
+}
+
+// How the client tickers reach the devices, and how the emulators receive the
+// requests. R2rTransport goes over ROS 2 services, ChannelTransport stays in the
+// process, so the runner and the emulators can be tested without ROS, also without
+// the ros feature.
+pub trait Transport: Clone + Send + Sync + 'static {
+    fn serve<S>(&self, service_name: &str) -> Result<BoxStream<'static, TransportRequest<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static;
+
+    fn client<S>(&self, service_name: &str) -> Result<Box<dyn TransportClient<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static;
+}
+
+#[cfg(feature = "ros")]
+#[derive(Clone)]
+pub struct R2rTransport {
+    pub arc_node: Arc<Mutex<r2r::Node>>,
+}
+
+#[cfg(feature = "ros")]
+impl R2rTransport {
+    pub fn new(arc_node: Arc<Mutex<r2r::Node>>) -> R2rTransport {
+        R2rTransport { arc_node }
+    }
+}
+
+#[cfg(feature = "ros")]
+struct R2rClient<S: ServiceType + 'static> {
+    client: r2r::Client<S>,
+}
+
+#[cfg(feature = "ros")]
+impl<S> TransportClient<S> for R2rClient<S>
+where
+    S: ServiceType + Send + 'static,
+    S::Request: Send + 'static,
+    S::Response: Send + 'static,
+{
+    fn wait_for_server(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        match r2r::Node::is_available(&self.client) {
+            Ok(future) => Box::pin(async move { future.await.map_err(|e| e.to_string()) }),
+            Err(e) => {
+                let e = e.to_string();
+                Box::pin(async move { Err(e) })
+            }
+        }
+    }
+
+    fn request(&self, request: &S::Request) -> ResponseFuture<S> {
+        match self.client.request(request) {
+            Ok(future) => Box::pin(async move { future.await.map_err(|e| e.to_string()) }),
+            Err(e) => {
+                let e = e.to_string();
+                Box::pin(async move { Err(e) })
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ros")]
+impl Transport for R2rTransport {
+    fn serve<S>(&self, service_name: &str) -> Result<BoxStream<'static, TransportRequest<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static,
+    {
+        let service = self
+            .arc_node
+            .lock()
+            .unwrap()
+            .create_service::<S>(service_name, QosProfile::default())
+            .map_err(|e| e.to_string())?;
+        Ok(service
+            .map(|request| TransportRequest {
+                message: request.message.clone(),
+                responder: Box::new(move |response| {
+                    request.respond(response).map_err(|e| e.to_string())
+                }),
+            })
+            .boxed())
+    }
+
+    fn client<S>(&self, service_name: &str) -> Result<Box<dyn TransportClient<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static,
+    {
+        let client = self
+            .arc_node
+            .lock()
+            .unwrap()
+            .create_client::<S>(service_name, QosProfile::default())
+            .map_err(|e| e.to_string())?;
+        Ok(Box::new(R2rClient { client }))
+    }
+}
+
+type ChannelServices = Arc<Mutex<HashMap<String, Box<dyn Any + Send>>>>;
+
+type ChannelSender<S> = mpsc::Sender<(
+    <S as ServiceType>::Request,
+    oneshot::Sender<<S as ServiceType>::Response>,
+)>;
+
+// The services of the process, by name. Every server holds a channel sender of its
+// request and response type, the clients look it up when they send a request.
+#[derive(Clone, Default)]
+pub struct ChannelTransport {
+    services: ChannelServices,
+}
+
+impl ChannelTransport {
+    pub fn new() -> ChannelTransport {
+        ChannelTransport::default()
+    }
+}
+
+fn channel_sender<S>(
+    services: &ChannelServices,
+    service_name: &str,
+) -> Option<ChannelSender<S>>
+where
+    S: ServiceType + Send + 'static,
+    S::Request: Send + 'static,
+    S::Response: Send + 'static,
+{
+    services
+        .lock()
+        .unwrap()
+        .get(service_name)
+        .and_then(|sender| sender.downcast_ref::<ChannelSender<S>>())
+        .cloned()
+}
+
+struct ChannelClient {
+    services: ChannelServices,
+    service_name: String,
+}
+
+impl<S> TransportClient<S> for ChannelClient
+where
+    S: ServiceType + Send + 'static,
+    S::Request: Send + 'static,
+    S::Response: Send + 'static,
+{
+    fn wait_for_server(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        let (services, service_name) = (self.services.clone(), self.service_name.clone());
+        Box::pin(async move {
+            while channel_sender::<S>(&services, &service_name).is_none() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+    }
+
+    fn request(&self, request: &S::Request) -> ResponseFuture<S> {
+        let sender = channel_sender::<S>(&self.services, &self.service_name);
+        let (service_name, request) = (self.service_name.clone(), request.clone());
+        Box::pin(async move {
+            let sender = sender.ok_or_else(|| format!("No server for '{}'.", service_name))?;
+            let (response_tx, response_rx) = oneshot::channel();
+            sender
+                .send((request, response_tx))
+                .await
+                .map_err(|_| format!("The server of '{}' is gone.", service_name))?;
+            // Like a ROS service, a request that the server drops is never answered
+            match response_rx.await {
+                Ok(response) => Ok(response),
+                Err(_) => futures::future::pending().await,
+            }
+        })
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn serve<S>(&self, service_name: &str) -> Result<BoxStream<'static, TransportRequest<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static,
+    {
+        let mut services = self.services.lock().unwrap();
+        if services.contains_key(service_name) {
+            return Err(format!("Service '{}' is already served.", service_name));
+        }
+        let (sender, receiver): (ChannelSender<S>, _) = mpsc::channel(32);
+        services.insert(service_name.to_string(), Box::new(sender));
+
+        let requests = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|request| (request, receiver))
+        });
+        Ok(requests
+            .map(|(message, response_tx)| TransportRequest {
+                message,
+                responder: Box::new(move |response| {
+                    response_tx
+                        .send(response)
+                        .map_err(|_| "The client is gone.".to_string())
+                }),
+            })
+            .boxed())
+    }
+
+    fn client<S>(&self, service_name: &str) -> Result<Box<dyn TransportClient<S>>, String>
+    where
+        S: ServiceType + Send + 'static,
+        S::Request: Send + 'static,
+        S::Response: Send + 'static,
+    {
+        Ok(Box::new(ChannelClient {
+            services: self.services.clone(),
+            service_name: service_name.to_string(),
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_runner_over_channel_transport() {
+    use crate::*;
+    use micro_sp::*;
+
+    // The minimal model against the gantry emulator, without a ROS node
+    let entry = get_model_entry("minimal").unwrap();
+    let state = (entry.state)().extend(generate_runner_state_variables(entry.name), true);
+    let (model, state, _fmea) = (entry.model)(entry.name, &state);
+    let state = state.extend(generate_operation_state_variables(&model, false), true);
+
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(state_manager(rx, state));
+
+    let transport = ChannelTransport::new();
+    let world = Arc::new(Mutex::new(World::new(0)));
+    spawn_gantry_emulator_server(transport.clone(), world.clone(), 0).await.unwrap();
+    assert!(spawn_gantry_emulator_server(transport.clone(), world.clone(), 0).await.is_err());
+
+    let (transport_clone, tx_clone) = (transport.clone(), tx.clone());
+    tokio::spawn(async move { gantry_client_ticker(transport_clone, 10, tx_clone).await.unwrap() });
+    let (model_clone, tx_clone) = (model.clone(), tx.clone());
+    tokio::spawn(async move { planner_ticker(&model_clone, tx_clone).await.unwrap() });
+    let (model_clone, tx_clone) = (model.clone(), tx.clone());
+    tokio::spawn(async move { operation_runner(&model_clone, tx_clone).await.unwrap() });
+
+    let goal = "var:gantry_position_estimated == b";
+    let outcome = run_goal(&model.name, goal, Some(20000), CLIENT_TICKER_RATE, &tx, &mut |_| true).await.unwrap();
+    assert_eq!(outcome, RunOutcome::Completed);
+    assert_eq!(world.lock().unwrap().gantry_position, "b");
+}
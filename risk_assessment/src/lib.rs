@@ -1,21 +1,62 @@
 pub static EMULATOR_NODE_ID: &'static str = "risk_assessment_emulator";
 pub static NODE_ID: &'static str = "risk_assessment_runner";
+// Defaults of the RunnerConfig
 pub static TEST_TICKER_RATE: u64 = 1000; // milliseconds
 pub static CLIENT_TICKER_RATE: u64 = 100; // milliseconds
 pub static PUBLISHER_TICKER_RATE: u64 = 100; // milliseconds
 pub static NUMBER_OF_TEST_CASES: u64 = 20;
+pub static REPORT_DIRECTORY: &'static str = "reports";
+
+pub mod behavior_tree;
+pub use crate::behavior_tree::node::*;
+pub use crate::behavior_tree::tree::*;
+pub use crate::behavior_tree::xml::*;
+
+pub mod emulation;
+pub use crate::emulation::key::*;
+pub use crate::emulation::profile::*;
 
 pub mod emulators;
+pub use crate::emulators::camera_system_emulator::*;
 pub use crate::emulators::gantry_emulator::*;
 pub use crate::emulators::robot_emulator::*;
+pub use crate::emulators::sampling::*;
+pub use crate::emulators::scanner_emulator::*;
+pub use crate::emulators::seed::*;
+pub use crate::emulators::world::*;
 
 pub mod interfaces;
+pub use crate::interfaces::camera_system_client_ticker::*;
+pub use crate::interfaces::device_client::*;
+pub use crate::interfaces::emulation::*;
 pub use crate::interfaces::gantry_client_ticker::*;
+#[cfg(feature = "ros")]
+pub use crate::interfaces::goal_action::*;
 pub use crate::interfaces::robot_client_ticker::*;
+pub use crate::interfaces::runner_services::*;
+pub use crate::interfaces::scanner_client_ticker::*;
+pub use crate::interfaces::timeout::*;
+pub use crate::interfaces::transport::*;
 
 pub mod models;
 // pub use crate::models::*;
+pub use crate::models::common::*;
+pub use crate::models::failure_branches::*;
+pub use crate::models::registry::*;
+
+pub mod risk;
+pub use crate::risk::divergence::*;
+pub use crate::risk::fmea::*;
+pub use crate::risk::recorder::*;
+pub use crate::risk::report::*;
+pub use crate::risk::stpa::*;
+
+pub mod simulation;
+pub use crate::simulation::campaign::*;
+pub use crate::simulation::monte_carlo::*;
+pub use crate::simulation::scenario::*;
 
 pub mod utils;
+pub use crate::utils::config::*;
 pub use crate::utils::state_publisher::*;
 pub use crate::utils::env_logger::*;
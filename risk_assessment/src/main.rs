@@ -1,7 +1,7 @@
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use tokio::sync::{mpsc, oneshot};
-use tokio::time::{interval, Duration};
 
 use micro_sp::*;
 use risk_assessment::*;
@@ -11,79 +11,165 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Logs from extern crates to stdout
     initialize_env_logger();
 
-    // Enable coverability tracking:
-    let coverability_tracking = false;
+    // Model, tick rates, emulators, etc., see RunnerConfig
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", RUNNER_USAGE);
+        return Ok(());
+    }
+    if args.iter().any(|arg| arg == "--list-models") {
+        for entry in model_registry() {
+            println!("{} ({})", entry.name, entry.devices.join(", "));
+        }
+        return Ok(());
+    }
+    let config = RunnerConfig::from_args(&args)?;
+    let entry = get_model_entry(&config.model)?;
+    let devices = entry.devices();
 
     // Setup the node
     let ctx = r2r::Context::create()?;
     let node = r2r::Node::create(ctx, NODE_ID, "")?;
     let arc_node = Arc::new(Mutex::new(node));
+    r2r::log_info!(NODE_ID, "Using model '{}'.", entry.name);
 
-    let state = models::bt_test_endre::state::state();
+    let state = (entry.state)();
 
     // Add the variables that keep track of the runner state
-    let runner_vars = generate_runner_state_variables("bt_test_endre");
+    let runner_vars = generate_runner_state_variables(entry.name);
     let state = state.extend(runner_vars, true);
 
-    let (model, state) = models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let (model, state, fmea) = (entry.model)(entry.name, &state);
     let name = model.clone().name;
 
-    let op_vars = generate_operation_state_variables(&model, coverability_tracking);
+    let op_vars = generate_operation_state_variables(&model, config.coverability_tracking);
     let state = state.extend(op_vars, true);
 
+    // Optional emulation profile, i.e. --emulation-profile profiles/worn_gripper.toml
+    let state = match &config.emulation_profile {
+        Some(path) => {
+            let profile = EmulationProfile::load(path)?;
+            r2r::log_info!(NODE_ID, "Using emulation profile '{}'.", profile.name);
+            let keys = operation_emulation_keys(&model, &devices, &state);
+            profile.apply(&state, &fmea, &keys)?
+        }
+        None => state,
+    };
+
+    // Same seed, same emulated responses, i.e. EMULATION_SEED=42 replays a run
+    let seed = emulation_seed()?;
+    r2r::log_warn!(NODE_ID, "Emulation seed is {}, set EMULATION_SEED={} to replay this run.", seed, seed);
+
+    // Optional campaign, i.e. --campaign campaigns/overnight.toml, replaces the single test
+    let campaign = match &config.campaign {
+        Some(path) => Some(Campaign::load(path)?),
+        None => None,
+    };
+    // Optional scenario file or directory of scenarios, i.e. --scenario scenarios, replaces the single test
+    let scenarios = match &config.scenario {
+        Some(path) if std::path::Path::new(path).is_dir() => Some(load_scenarios(path)?),
+        Some(path) => Some(vec![Scenario::load(path)?]),
+        None => None,
+    };
+    let initial_state = state.clone();
+    // An invalid campaign goal fails the campaign before it starts
+    if let Some(campaign) = &campaign {
+        if let Err(e) = campaign.check_goals(&state) {
+            r2r::log_error!(NODE_ID, "Campaign failed with: {}", e);
+            std::process::exit(2);
+        }
+    }
+    // Goals and variables from outside are rejected while the campaign or the scenarios run
+    let run_active = Arc::new(AtomicBool::new(campaign.is_some() || scenarios.is_some()));
+
     let (tx, rx) = mpsc::channel(32); // Experiment with buffer size
     tokio::spawn(state_manager(rx, state));
 
-    r2r::log_info!(NODE_ID, "Spawning emulators...");
+    // The client tickers reach the emulators and the devices over ROS 2 services
+    let transport = R2rTransport::new(arc_node.clone());
+
+    // Ground truth of the cell, shared by the emulators
+    let world = Arc::new(Mutex::new(World::new(seed)));
+
+    if config.emulators {
+        r2r::log_info!(NODE_ID, "Spawning emulators...");
+
+        for device in devices.clone() {
+            let transport_clone = transport.clone();
+            let world_clone = world.clone();
+            tokio::task::spawn(async move {
+                match device.as_str() {
+                    "gantry" => spawn_gantry_emulator_server(transport_clone, world_clone, seed).await.unwrap(),
+                    "robot" => spawn_robot_emulator_server(transport_clone, world_clone, seed).await.unwrap(),
+                    "scanner" => spawn_scanner_emulator_server(transport_clone, world_clone, seed).await.unwrap(),
+                    "camera_system" => spawn_camera_system_emulator_server(transport_clone, seed).await.unwrap(),
+                    _ => r2r::log_error!(NODE_ID, "No emulator for device '{}'.", device),
+                }
+            });
+
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        }
+    } else {
+        r2r::log_warn!(NODE_ID, "Emulators are disabled, using the real devices.");
+    }
 
-    let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
-    tokio::task::spawn(async move { spawn_gantry_emulator_server(arc_node_clone).await.unwrap() });
+    r2r::log_info!(NODE_ID, "Spawning interfaces...");
 
-    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    // Also the rate at which the recorder, the monitors and the runs follow the state
+    let client_ticker_rate = config.client_ticker_rate;
+    for device in devices.clone() {
+        let transport_clone = transport.clone();
+        let tx_clone = tx.clone();
+        tokio::task::spawn(async move {
+            match device.as_str() {
+                "gantry" => gantry_client_ticker(transport_clone, client_ticker_rate, tx_clone).await.unwrap(),
+                "robot" => robot_client_ticker(transport_clone, client_ticker_rate, tx_clone).await.unwrap(),
+                "scanner" => scanner_client_ticker(transport_clone, client_ticker_rate, tx_clone).await.unwrap(),
+                "camera_system" => camera_system_client_ticker(transport_clone, client_ticker_rate, tx_clone).await.unwrap(),
+                _ => r2r::log_error!(NODE_ID, "No client ticker for device '{}'.", device),
+            }
+        });
 
-    let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
-    tokio::task::spawn(async move { spawn_robot_emulator_server(arc_node_clone).await.unwrap() });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
 
-    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    // let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+    // let shared_state_clone = shared_state.clone();
+    // // let global_version_clone = global_version.clone();
+    // tokio::task::spawn(async move {
+    //     spawn_robot_client_ticker(arc_node_clone, &shared_state_clone)
+    //         .await
+    //         .unwrap()
+    // });
 
-    r2r::log_info!(NODE_ID, "Spawning interfaces...");
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
+    // Live state for dashboards and loggers, i.e. --state-publisher-mode diff
+    let state_publisher_mode = match &config.state_publisher_mode {
+        Some(mode) => StatePublisherMode::parse(mode)?,
+        None => StatePublisherMode::Full,
+    };
     let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+    let publisher_ticker_rate = config.publisher_ticker_rate;
     let tx_clone = tx.clone();
     tokio::task::spawn(async move {
-        gantry_client_ticker(arc_node_clone, tx_clone)
+        spawn_state_publisher(arc_node_clone, state_publisher_mode, publisher_ticker_rate, tx_clone)
             .await
             .unwrap()
     });
 
     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
+    // Get and set variables, submit and cancel goals from outside
     let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
-    let tx_clone = tx.clone();
+    let name_clone = name.clone();
+    let (tx_clone, run_active_clone) = (tx.clone(), run_active.clone());
     tokio::task::spawn(async move {
-        robot_client_ticker(arc_node_clone, tx_clone)
+        spawn_runner_services(arc_node_clone, &name_clone, tx_clone, run_active_clone)
             .await
             .unwrap()
     });
 
-    // let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
-    // let shared_state_clone = shared_state.clone();
-    // // let global_version_clone = global_version.clone();
-    // tokio::task::spawn(async move {
-    //     spawn_robot_client_ticker(arc_node_clone, &shared_state_clone)
-    //         .await
-    //         .unwrap()
-    // });
-
-    // let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
-    // let shared_state_clone = shared_state.clone();
-    // // let global_version_clone = global_version.clone();
-    // tokio::task::spawn(async move {
-    //     spawn_state_publisher(arc_node_clone, &shared_state_clone)
-    //         .await
-    //         .unwrap()
-    // });
-
     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
 
     r2r::log_info!(NODE_ID, "Spawning operation planner...");
@@ -118,22 +204,136 @@ async fn main() -> Result<(), Box<dyn Error>> {
             .unwrap()
     });
 
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    r2r::log_info!(NODE_ID, "Spawning risk recorder...");
+
+    let (recorder_tx, recorder_rx) = mpsc::channel(32);
+    let (model_clone, devices_clone) = (model.clone(), devices.clone());
+    let tx_clone = tx.clone();
+    tokio::task::spawn(async move {
+        risk_recorder(
+            &model_clone,
+            devices_clone,
+            client_ticker_rate,
+            tx_clone,
+            recorder_rx,
+        )
+        .await
+        .unwrap()
+    });
+
     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
     // std::thread::sleep(std::time::Duration::from_millis(1000));
 
-    r2r::log_info!(NODE_ID, "Spawning operation runner...");
+    r2r::log_info!(NODE_ID, "Spawning goal action server...");
 
+    let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+    let (name_clone, fmea_clone, devices_clone) = (name.clone(), fmea.clone(), devices.clone());
     let tx_clone = tx.clone();
-    tokio::task::spawn(async move { operation_runner(&model, tx_clone).await.unwrap() });
+    let (recorder_tx_clone, run_active_clone) = (recorder_tx.clone(), run_active.clone());
+    tokio::task::spawn(async move {
+        spawn_goal_action_server(
+            arc_node_clone,
+            &name_clone,
+            &fmea_clone,
+            devices_clone,
+            client_ticker_rate,
+            tx_clone,
+            recorder_tx_clone,
+            run_active_clone,
+        )
+        .await
+        .unwrap()
+    });
 
     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-    // std::thread::sleep(std::time::Duration::from_millis(1000));
 
-    r2r::log_info!(NODE_ID, "Spawning test generator...");
+    // Without the emulators there is no ground truth to compare with
+    let divergence_tx = match config.emulators {
+        true => {
+            r2r::log_info!(NODE_ID, "Spawning divergence monitor...");
+
+            let (divergence_tx, divergence_rx) = mpsc::channel(32);
+            let name_clone = name.clone();
+            let tx_clone = tx.clone();
+            tokio::task::spawn(async move {
+                divergence_monitor(&name_clone, world, client_ticker_rate, tx_clone, divergence_rx)
+                    .await
+                    .unwrap()
+            });
+
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            Some(divergence_tx)
+        }
+        false => None,
+    };
 
-    // let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+    r2r::log_info!(NODE_ID, "Spawning operation runner...");
+
+    let model_clone = model.clone();
     let tx_clone = tx.clone();
-    tokio::task::spawn(async move { perform_test(&name, tx_clone).await.unwrap() });
+    tokio::task::spawn(async move { operation_runner(&model_clone, tx_clone).await.unwrap() });
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    // std::thread::sleep(std::time::Duration::from_millis(1000));
+
+    match (campaign, scenarios) {
+        (Some(campaign), _) => {
+            r2r::log_info!(NODE_ID, "Spawning campaign '{}'...", campaign.name);
+
+            let tx_clone = tx.clone();
+            tokio::task::spawn(async move {
+                let code = match perform_campaign(&campaign, &model, &initial_state, &fmea, seed, client_ticker_rate, tx_clone, recorder_tx)
+                    .await
+                {
+                    Ok(code) => code,
+                    Err(e) => {
+                        r2r::log_error!(NODE_ID, "Campaign failed with: {}.", e);
+                        2
+                    }
+                };
+                run_active.store(false, Ordering::SeqCst);
+                std::process::exit(code);
+            });
+        }
+        (None, Some(scenarios)) => {
+            r2r::log_info!(NODE_ID, "Spawning {} scenarios...", scenarios.len());
+
+            let devices_clone = devices.clone();
+            let tx_clone = tx.clone();
+            tokio::task::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
+                let keys = operation_emulation_keys(&model, &devices_clone, &initial_state);
+                let code = match run_scenarios(&scenarios, &model, &initial_state, &fmea, &keys, client_ticker_rate, tx_clone, recorder_tx).await {
+                    Ok((results, path)) => {
+                        r2r::log_warn!(NODE_ID, "Scenarios written to '{}'.", path);
+                        match results.iter().all(|result| result.passed()) {
+                            true => 0,
+                            false => 1,
+                        }
+                    }
+                    Err(e) => {
+                        r2r::log_error!(NODE_ID, "Scenarios failed with: {}.", e);
+                        2
+                    }
+                };
+                run_active.store(false, Ordering::SeqCst);
+                std::process::exit(code);
+            });
+        }
+        (None, None) => {
+            r2r::log_info!(NODE_ID, "Spawning test generator...");
+
+            // let arc_node_clone: Arc<Mutex<r2r::Node>> = arc_node.clone();
+            let test_ticker_rate = config.test_ticker_rate;
+            let tx_clone = tx.clone();
+            tokio::task::spawn(async move {
+                perform_test(&name, &fmea, seed, test_ticker_rate, tx_clone, recorder_tx, divergence_tx)
+                    .await
+                    .unwrap()
+            });
+        }
+    }
 
     tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
     // std::thread::sleep(std::time::Duration::from_millis(1000));
@@ -154,71 +354,144 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Runs the campaign and writes its report, returns the exit code of the process
+async fn perform_campaign(
+    campaign: &Campaign,
+    model: &Model,
+    initial_state: &State,
+    fmea: &FmeaTable,
+    seed: u64,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+) -> Result<i32, Box<dyn Error>> {
+    tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
+    r2r::log_warn!(NODE_ID, "Campaign '{}' started.", campaign.name);
+
+    let devices = get_model_entry(&model.name)?.devices();
+    let keys = operation_emulation_keys(model, &devices, initial_state);
+
+    let mut report = run_campaign(
+        campaign,
+        &model.name,
+        initial_state,
+        fmea,
+        &keys,
+        ticker_rate,
+        command_sender,
+        recorder_sender.clone(),
+    )
+    .await?;
+    report.seed = Some(seed);
+    r2r::log_warn!(NODE_ID, "Campaign written to '{}'.", report.write(REPORT_DIRECTORY)?);
+
+    let (response_tx, response_rx) = oneshot::channel();
+    recorder_sender.send(RecorderCommand::GetLog(response_tx)).await?;
+    let mut log = response_rx.await?;
+    log.seed = Some(seed);
+    for path in FmeaReport::new(fmea, &log).write(REPORT_DIRECTORY)? {
+        r2r::log_warn!(NODE_ID, "Report written to '{}'.", path);
+    }
+
+    r2r::log_warn!(
+        NODE_ID,
+        "Campaign '{}': {} completed, {} failed, {} timed out.",
+        campaign.name,
+        report.count(RunOutcome::Completed),
+        report.count(RunOutcome::Failed),
+        report.count(RunOutcome::TimedOut)
+    );
+    for violation in report.violations() {
+        r2r::log_error!(NODE_ID, "{}", violation);
+    }
+    Ok(report.exit_code())
+}
+
 async fn perform_test(
     name: &str,
+    fmea: &FmeaTable,
+    seed: u64,
+    ticker_rate: u64,
     command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+    divergence_sender: Option<mpsc::Sender<DivergenceCommand>>,
 ) -> Result<(), Box<dyn Error>> {
     tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
     r2r::log_warn!(NODE_ID, "Starting tests...");
     tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
     r2r::log_warn!(NODE_ID, "Tests started.");
-    let mut interval = interval(Duration::from_millis(TEST_TICKER_RATE));
-    let mut test_nr = 0;
-    let mut goals = vec!["var:robot_mounted_estimated == suction_tool"];
-    // let mut goals = vec!("var:robot_mounted_checked == true");
-
-    'test_loop: loop {
-        let (response_tx, response_rx) = oneshot::channel();
-        command_sender.send(Command::GetState(response_tx)).await?; // TODO: maybe we can just ask for values from the guard
-        let state = response_rx.await?;
-
-        let plan_state = state
-            .get_or_default_string(&format!("{}_tester", name), &format!("{}_plan_state", name));
-
-        if goals.len() != 0 {
-            // println!("{:?}", goals);
-            
-            if PlanState::from_str(&plan_state) == PlanState::Failed
-                || PlanState::from_str(&plan_state) == PlanState::Completed
-                || PlanState::from_str(&plan_state) == PlanState::UNKNOWN
-            {
-                test_nr = test_nr + 1;
-                r2r::log_warn!(NODE_ID, "Starting test {}.", test_nr);
-                let new_state = state
-                    // .update("gantry_emulate_execution_time", 2.to_spvalue())
-                    // .update("gantry_emulated_execution_time", 3000.to_spvalue())
-                    // .update("gantry_emulate_failure_rate", 2.to_spvalue())
-                    // .update("gantry_emulated_failure_rate", 30.to_spvalue())
-                    // .update("gantry_emulate_failure_cause", 2.to_spvalue())
-                    // .update(
-                    //     "gantry_emulated_failure_cause",
-                    //     vec!["violation", "collision", "detected_drift"].to_spvalue(),
-                    // )
-                    // .update(
-                    //     "robot_emulated_failure_cause",
-                    //     vec!["move_outside_work_area", "collision_with_operator"].to_spvalue(),
-                    // )
-                    // .update("robot_mounted_estimated", "unknown".to_spvalue())
-                    .update("bt_test_endre_goal", goals.remove(0).to_spvalue())
-                    .update("bt_test_endre_replan_trigger", true.to_spvalue())
-                    .update("bt_test_endre_replanned", false.to_spvalue());
-
-                    let modified_state = state.get_diff_partial_state(&new_state);
-                    command_sender
-                        .send(Command::SetPartialState(modified_state))
-                        .await?;
-                tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-            }
-        } else {
-            break 'test_loop;
-        }
-        interval.tick().await;
-    }
+    let goal = get_model_entry(name)?.test_goal;
+    // let goal = "var:robot_mounted_checked == true";
+
+    // The report is only complete once the plan of the goal is completed or failed
+    let outcome = run_goal(name, goal, None, ticker_rate, &command_sender, &mut |_| true).await?;
+    r2r::log_warn!(NODE_ID, "Test {}.", outcome);
 
+    // Let the recorder see the last executions
     tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
 
     r2r::log_warn!(NODE_ID, "All tests are finished. Generating report...");
 
+    let (response_tx, response_rx) = oneshot::channel();
+    recorder_sender
+        .send(RecorderCommand::GetLog(response_tx))
+        .await?;
+    let mut log = response_rx.await?;
+    log.seed = Some(seed);
+
+    let report = FmeaReport::new(fmea, &log);
+    for path in report.write(REPORT_DIRECTORY)? {
+        r2r::log_warn!(NODE_ID, "Report written to '{}'.", path);
+    }
+    for row in report.rows.iter().take(5) {
+        r2r::log_warn!(
+            NODE_ID,
+            "RPN {}: {} failing with {} (observed {} times in {} executions).",
+            row.rpn,
+            row.operation,
+            row.cause,
+            row.observed_failures,
+            row.executions
+        );
+    }
+
+    // How often the estimates were wrong without the runner knowing it
+    let divergence_sender = match divergence_sender {
+        Some(divergence_sender) => divergence_sender,
+        None => return Ok(()),
+    };
+    let (response_tx, response_rx) = oneshot::channel();
+    divergence_sender
+        .send(DivergenceCommand::GetLog(response_tx))
+        .await?;
+    let mut divergence = response_rx.await?;
+    divergence.seed = Some(seed);
+    r2r::log_warn!(NODE_ID, "Divergence written to '{}'.", divergence.write(REPORT_DIRECTORY)?);
+    let mut variables: Vec<(&String, &DivergenceCount)> = divergence.variables.iter().collect();
+    variables.sort_by(|a, b| a.0.cmp(b.0));
+    for (variable, count) in variables {
+        r2r::log_warn!(
+            NODE_ID,
+            "{} diverged from the ground truth in {:.1}% of the known samples ({} unknown of {}).",
+            variable,
+            count.divergence_rate() * 100.0,
+            count.unknown,
+            count.samples
+        );
+    }
+    let mut detections: Vec<(&String, &DetectionCount)> = divergence.detections.iter().collect();
+    detections.sort_by(|a, b| a.0.cmp(b.0));
+    for (command, count) in detections {
+        r2r::log_warn!(
+            NODE_ID,
+            "{} failed silently {} times, {} were detected (measured detection score {}).",
+            command,
+            count.silent_failures,
+            count.detected,
+            count.detection_score()
+        );
+    }
+
     // Measure operation and plan execution times, and measure total failure rates...
     // Print out plan done or plan failed when done or failed.
 
@@ -0,0 +1,115 @@
+use crate::*;
+
+// Mount the suction tool, recovering from an uncalibrated or unlocked gantry
+// and from an unknown or wrong tool being mounted
+pub fn mount_suction_tool() -> BtNode {
+    BtNode::sequence(
+        "mount_suction_tool",
+        vec![
+            BtNode::fallback(
+                "gantry_calibrated",
+                vec![
+                    BtNode::condition("is_calibrated", "var:gantry_calibrated_estimated == true"),
+                    BtNode::sequence(
+                        "calibrate_gantry",
+                        vec![
+                            BtNode::action("unlock_gantry", "op_gantry_unlock"),
+                            BtNode::action("calibrate_gantry", "op_gantry_calibrate"),
+                        ],
+                    ),
+                ],
+            ),
+            BtNode::fallback(
+                "gantry_locked",
+                vec![
+                    BtNode::condition("is_locked", "var:gantry_locked_estimated == true"),
+                    BtNode::action("lock_gantry", "op_gantry_lock"),
+                ],
+            ),
+            BtNode::fallback(
+                "mounted_tool_known",
+                vec![
+                    BtNode::condition("is_known", "var:robot_mounted_estimated != UNKNOWN"),
+                    BtNode::action("check_mounted_tool", "op_robot_check_for_suction_tool_mounted"),
+                ],
+            ),
+            BtNode::fallback(
+                "suction_tool_mounted",
+                vec![
+                    BtNode::condition("is_mounted", "var:robot_mounted_estimated == suction_tool"),
+                    BtNode::sequence(
+                        "exchange_tool",
+                        vec![
+                            BtNode::fallback(
+                                "no_tool_mounted",
+                                vec![
+                                    BtNode::condition("is_none", "var:robot_mounted_estimated == none"),
+                                    BtNode::sequence(
+                                        "unmount_gripper_tool",
+                                        vec![
+                                            BtNode::action("move_to_gripper_rack", "op_robot_move_to_gripper_tool_rack"),
+                                            BtNode::action("unmount_gripper", "op_robot_unmount_gripper_tool"),
+                                        ],
+                                    ),
+                                ],
+                            ),
+                            BtNode::action("move_to_suction_rack", "op_robot_move_to_suction_tool_rack"),
+                            BtNode::action("mount_suction", "op_robot_mount_suction_tool"),
+                        ],
+                    ),
+                ],
+            ),
+        ],
+    )
+}
+
+#[test]
+fn test_behavior_tree_xml_round_trip() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    let root = mount_suction_tool();
+    let xml = behavior_tree_to_xml("mount_suction_tool", &root, &fmea);
+    let (tree, loaded_fmea) = behavior_tree_from_xml(&xml, &model, &state).unwrap();
+
+    assert_eq!(tree.name, "mount_suction_tool");
+    assert_eq!(tree.root, root);
+    for op in root.operations() {
+        assert_eq!(loaded_fmea.get_operation(&op), fmea.get_operation(&op));
+    }
+
+    // Invalid predicates and reactive nodes are errors, not panics
+    let invalid = xml.replace("var:gantry_calibrated_estimated == true", "var:gantry_calibrated_estimated ==");
+    assert!(behavior_tree_from_xml(&invalid, &model, &state).is_err());
+    let reactive = xml.replace("<Fallback ", "<ReactiveFallback ").replace("</Fallback>", "</ReactiveFallback>");
+    let error = behavior_tree_from_xml(&reactive, &model, &state).err().unwrap();
+    assert!(error.contains("ReactiveFallback"));
+
+    let xml = xml.replace("op_robot_mount_suction_tool", "op_robot_mount_vacuum_tool");
+    let error = behavior_tree_from_xml(&xml, &model, &state).err().unwrap();
+    assert!(error.contains("op_robot_mount_vacuum_tool"));
+}
+
+#[test]
+fn test_behavior_tree_xml_escapes_separators() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    let mut fmea = FmeaTable::new("bt_test_endre");
+    fmea.add(
+        "op_robot_mount_suction_tool",
+        vec![
+            FailureMode::new(GENERIC_FAILURE, 4, 3, 2, "Tool not mounted; robot stops.", "Retry."),
+            FailureMode::new("mis_grip", 6, 2, 3, "Tool slips\\drops.", "Check the tool; remount."),
+        ],
+    );
+    let root = mount_suction_tool();
+    let xml = behavior_tree_to_xml("mount_suction_tool", &root, &fmea);
+    let (_tree, loaded_fmea) = behavior_tree_from_xml(&xml, &model, &state).unwrap();
+    assert_eq!(
+        loaded_fmea.get_operation("op_robot_mount_suction_tool"),
+        fmea.get_operation("op_robot_mount_suction_tool")
+    );
+}
@@ -1,2 +1,4 @@
+pub mod behavior_tree;
 pub mod model;
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod stpa;
\ No newline at end of file
@@ -1,5 +1,5 @@
 use micro_sp::*;
-// use crate::*;
+use crate::*;
 
 // Operations that we need:
 // Gantry: move, calibrate, lock, unlock
@@ -7,29 +7,55 @@ use micro_sp::*;
 // Camera System: update blue boxes
 // Robot: move, mount, unmount, pick, place
 
-pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
+// Failure causes that the gantry emulator can return:
+// generic_failure, violation, collision, detected_drift
+// Failure causes that the robot emulator can return:
+// generic_failure, move_outside_work_area, collision_with_operator, mis_grip, dropped_item
+// Failure causes that the scanner emulator can return:
+// generic_failure, unreadable_marker
+// Failure causes that the camera system emulator can return:
+// generic_failure, occluded
+// Failure causes that every device can return:
+// timeout (no response in time), invalid_emulation (emulator misconfigured)
+// The device reports the cause in {device}_failure_cause, the fail transitions branch on it,
+// see failure_branches.
+
+// Every operation has a deadline, which its start transition passes on to the
+// client ticker of the device as the request timeout, see start_request.
+
+pub fn bt_test_endre(name: &str, state: &State) -> (Model, State, FmeaTable) {
     let state = state.clone();
     let auto_transitions = vec![];
     let auto_operations = vec![];
     let mut operations = vec![];
+    let mut fmea = FmeaTable::new(name);
 
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_lock",
+        "gantry",
+        vec!["var:gantry_locked_estimated <- UNKNOWN"],
+        vec![
+            FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 2, "Gantry lock state unknown, robot motions are blocked.", "Retry, replan if retries are exhausted."), vec![]),
+            FailureBranch::new(FailureMode::new("violation", 6, 2, 3, "Protective stop while locking.", "Reset the gantry and replan."), vec![]),
+        ],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_lock",
+        "gantry",
+        3000,
+        "var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec![
+            &format!("var:gantry_command_command <- lock"),
+        ],
+        &state,
+    );
     operations.push(Operation::new(
         "op_gantry_lock",
-        None,
+        deadline,
         Some(3),
-        Vec::from([
-            Transition::parse(
-            "start_op_gantry_lock",
-            "var:gantry_request_state == initial \
-                && var:gantry_request_trigger == false",
-            "true",
-            vec![
-                &format!("var:gantry_command_command <- lock"),
-                "var:gantry_request_trigger <- true",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        vec![start],
         Vec::from([Transition::parse(
             "complete_op_gantry_lock",
             "true",
@@ -42,38 +68,39 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
             Vec::<&str>::new(),
             &state,
         )]),
-        Vec::from([Transition::parse(
-            "fail_op_gantry_lock",
-            "true",
-            "var:gantry_request_state == failed",
-            vec![
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- UNKNOWN",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        fail_transitions,
         Vec::from([]),
         Vec::from([])
     ));
 
+    fmea.add("op_gantry_lock", failure_modes);
+
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_unlock",
+        "gantry",
+        vec!["var:gantry_locked_estimated <- UNKNOWN"],
+        vec![
+            FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 2, "Gantry lock state unknown, gantry motions are blocked.", "Retry, replan if retries are exhausted."), vec![]),
+            FailureBranch::new(FailureMode::new("violation", 6, 2, 3, "Protective stop while unlocking.", "Reset the gantry and replan."), vec![]),
+        ],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_unlock",
+        "gantry",
+        3000,
+        "var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec![
+            &format!("var:gantry_command_command <- unlock"),
+        ],
+        &state,
+    );
     operations.push(Operation::new(
         "op_gantry_unlock",
-        None,
+        deadline,
         Some(3),
-        Vec::from([Transition::parse(
-            "start_op_gantry_unlock",
-            "var:gantry_request_state == initial \
-                && var:gantry_request_trigger == false",
-            "true",
-            vec![
-                &format!("var:gantry_command_command <- unlock"),
-                "var:gantry_request_trigger <- true",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        vec![start],
         Vec::from([Transition::parse(
             "complete_op_gantry_unlock",
             "true",
@@ -86,39 +113,50 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
             Vec::<&str>::new(),
             &state,
         )]),
-        Vec::from([Transition::parse(
-            "fail_op_gantry_unlock",
-            "true",
-            "var:gantry_request_state == failed",
-            vec![
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- UNKNOWN",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        fail_transitions,
         Vec::from([]),
         Vec::from([])
     ));
 
+    fmea.add("op_gantry_unlock", failure_modes);
+
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_calibrate",
+        "gantry",
+        vec![],
+        vec![
+            FailureBranch::new(
+                FailureMode::new(GENERIC_FAILURE, 4, 3, 3, "Gantry is not calibrated, gantry motions are blocked.", "Retry the calibration."),
+                vec!["var:gantry_calibrated_estimated <- UNKNOWN"],
+            ),
+            FailureBranch::new(
+                FailureMode::new("detected_drift", 5, 4, 4, "Calibration reference drifted, positions are inaccurate.", "Recalibrate, inspect the encoders."),
+                vec!["var:gantry_calibrated_estimated <- false"],
+            ),
+            FailureBranch::new(
+                FailureMode::new("collision", 8, 2, 3, "Gantry hit an obstacle during the calibration sweep.", "Stop the cell, inspect the gantry before resuming."),
+                vec!["var:gantry_calibrated_estimated <- false"],
+            ),
+        ],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_calibrate",
+        "gantry",
+        10000,
+        "var:gantry_locked_estimated == false \
+            && var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec![
+            &format!("var:gantry_command_command <- calibrate"),
+        ],
+        &state,
+    );
     operations.push(Operation::new(
         "op_gantry_calibrate",
-        None,
+        deadline,
         Some(3),
-        Vec::from([Transition::parse(
-            "start_op_gantry_calibrate",
-            "var:gantry_locked_estimated == false \
-                && var:gantry_request_state == initial \
-                && var:gantry_request_trigger == false",
-            "true",
-            vec![
-                &format!("var:gantry_command_command <- calibrate"),
-                "var:gantry_request_trigger <- true",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        vec![start],
         Vec::from([Transition::parse(
             "complete_op_gantry_calibrate",
             "true",
@@ -131,43 +169,53 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
             Vec::<&str>::new(),
             &state,
         )]),
-        Vec::from([Transition::parse(
-            "fail_op_gantry_calibrate",
-            "true",
-            "var:gantry_request_state == failed",
-            vec![
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_calibrated_estimated <- UNKNOWN",
-            ],
-            Vec::<&str>::new(),
-            &state,
-        )]),
+        fail_transitions,
         Vec::from([]),
         Vec::from([])
     ));
 
-    for pos in vec!["home", "pipe_blue_box", "plate_pipe_box"] {
+    fmea.add("op_gantry_calibrate", failure_modes);
+
+    for pos in vec!["home", "pipe_blue_box", "plate_blue_box", "plate_pipe_box"] {
+        // After a collision or a drift the gantry has to be calibrated again
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_gantry_move_to_{}", pos),
+            "gantry",
+            vec!["var:gantry_position_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 2, &format!("Gantry did not reach {pos}, position unknown."), "Retry, replan if retries are exhausted."), vec![]),
+                FailureBranch::new(FailureMode::new("violation", 6, 3, 3, &format!("Protective stop while moving to {pos}."), "Reset the gantry and replan."), vec![]),
+                FailureBranch::new(
+                    FailureMode::new("collision", 9, 2, 3, &format!("Gantry collided on the way to {pos}."), "Stop the cell, inspect the gantry and the tools."),
+                    vec!["var:gantry_calibrated_estimated <- false"],
+                ),
+                FailureBranch::new(
+                    FailureMode::new("detected_drift", 5, 4, 5, &format!("Gantry stopped near {pos} with a position drift."), "Recalibrate the gantry."),
+                    vec!["var:gantry_calibrated_estimated <- false"],
+                ),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_gantry_move_to_{}", pos),
+            "gantry",
+            5000,
+            "var:gantry_request_state == initial \
+                && var:gantry_request_trigger == false \
+                && var:gantry_locked_estimated == false \
+                && var:gantry_calibrated_estimated == true",
+            vec![
+                &format!("var:gantry_command_command <- move"),
+                &format!("var:gantry_position_command <- {pos}"),
+                &format!("var:gantry_speed_command <- 0.5"),
+            ],
+            &state,
+        );
         operations.push(Operation::new(
             &format!("op_gantry_move_to_{}", pos),
-            None,
+            deadline,
             Some(3),
-            Vec::from([Transition::parse(
-                &format!("start_op_gantry_move_to_{}", pos),
-                "var:gantry_request_state == initial \
-                    && var:gantry_request_trigger == false \
-                    && var:gantry_locked_estimated == false \
-                    && var:gantry_calibrated_estimated == true",
-                "true",
-                vec![
-                    &format!("var:gantry_command_command <- move"),
-                    &format!("var:gantry_position_command <- {pos}"),
-                    &format!("var:gantry_speed_command <- 0.5"),
-                    "var:gantry_request_trigger <- true",
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            vec![start],
             Vec::from([Transition::parse(
                 &format!("complete_op_gantry_move_to_{}", pos),
                 "true",
@@ -180,118 +228,129 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
                 Vec::<&str>::new(),
                 &state,
             )]),
+            fail_transitions,
+            Vec::from([]),
+            Vec::from([])
+        ));
+
+        fmea.add(&format!("op_gantry_move_to_{}", pos), failure_modes);
+    }
+
+    for blue_box in vec!["pipe_blue_box", "plate_blue_box"] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_update_position_for_{}", blue_box),
+            "camera_system",
+            vec![&format!("var:{blue_box}_position_updated_estimated <- UNKNOWN")],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 2, &format!("Position of {blue_box} is not updated, the old one is used."), "Retry the update."), vec![]),
+                FailureBranch::new(FailureMode::new("occluded", 4, 4, 4, &format!("{blue_box} is occluded, the position is inaccurate."), "Move the robot out of the field of view and update again."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_update_position_for_{}", blue_box),
+            "camera_system",
+            3000,
+            "var:camera_system_request_state == initial \
+                && var:camera_system_request_trigger == false",
+            vec![
+                &format!("var:camera_system_command_command <- update"),
+                &format!("var:camera_system_update_command <- {blue_box}"),
+            ],
+            &state,
+        );
+        operations.push(Operation::new(
+            &format!("op_update_position_for_{}", blue_box),
+            deadline,
+            Some(3),
+            vec![start],
             Vec::from([Transition::parse(
-                &format!("fail_op_gantry_move_to_{}", pos),
+                &format!("complete_op_update_position_for_{}", blue_box),
                 "true",
-                "var:gantry_request_state == failed",
+                &format!("var:camera_system_request_state == succeeded"),
                 vec![
-                    "var:gantry_request_trigger <- false",
-                    "var:gantry_request_state <- initial",
-                    "var:gantry_position_estimated <- UNKNOWN",
+                    "var:camera_system_request_trigger <- false",
+                    "var:camera_system_request_state <- initial",
+                    &format!("var:{blue_box}_position_updated_estimated <- true"),
                 ],
                 Vec::<&str>::new(),
                 &state,
             )]),
+            fail_transitions,
             Vec::from([]),
             Vec::from([])
         ));
+
+        fmea.add(&format!("op_update_position_for_{}", blue_box), failure_modes);
     }
 
-    // for blue_box in vec!["pipe_blue_box", "plate_blue_box"] {
-    //     operations.push(Operation::new(
-    //         &format!("op_update_position_for_{}", blue_box),
-    //         None,
-    //         Some(3),
-    //         Vec::from([Transition::parse(
-    //             &format!("start_op_update_position_for_{}", blue_box),
-    //             "var:camera_system_request_state == initial \
-    //                 && var:camera_system_request_trigger == false",
-    //             "true",
-    //             vec![
-    //                 &format!("var:camera_system_update_command <- {blue_box}"),
-    //                 "var:camera_system_request_trigger <- true",
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         )),
-    //         Vec::from([Transition::parse(
-    //             &format!("complete_op_update_position_for_{}", blue_box),
-    //             "true",
-    //             &format!("var:camera_system_request_state == succeeded"),
-    //             vec![
-    //                 "var:camera_system_request_trigger <- false",
-    //                 "var:camera_system_request_state <- initial",
-    //                 &format!("var:{blue_box}_position_updated_estimated <- true"),
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         )),
-    //         Vec::from([Transition::parse(
-    //             &format!("fail_op_update_position_for_{}", blue_box),
-    //             "true",
-    //             &format!("var:camera_system_request_state == failed"),
-    //             vec![
-    //                 "var:camera_system_request_trigger <- false",
-    //                 "var:camera_system_request_state <- initial",
-    //                 &format!("var:{blue_box}_position_updated_estimated <- UNKNOWN"),
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         )),
-    //         Transition::empty(),
-    //     ));
-    // }
-
-    // for item in vec!["pipe", "plate"] {
-    //     operations.push(Operation::new(
-    //         &format!("op_scan_{}_blue_box", item),
-    //         None,
-    //         Some(3),
-    //         Vec::from([Transition::parse(
-    //             &format!("start_op_scan_{}_blue_box", item),
-    //             &&format!(
-    //                 "var:scanner_request_state == initial \
-    //                 && var:scanner_request_trigger == false \
-    //                 && var:gantry_position_estimated == {item}_blue_box \
-    //                 && var:robot_position_estimated == {item}_blue_box"
-    //             ),
-    //             "true",
-    //             vec![
-    //                 &format!("var:scanner_item_command <- {item}"),
-    //                 "var:scanner_request_trigger <- true",
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         ),
-    //         Vec::from([Transition::parse(
-    //             &format!("complete_op_scan_{}_blue_box", item),
-    //             "true",
-    //             &format!("var:scanner_request_state == succeeded"),
-    //             vec![
-    //                 "var:scanner_request_trigger <- false",
-    //                 "var:scanner_request_state <- initial",
-    //                 &format!("var:{item}_blue_box_scanned_estimated <- true"),
-    //                 &format!("var:{item}_position_estimated <- {item}_blue_box"),
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         ),
-    //         Vec::from([Transition::parse(
-    //             &format!("fail_op_scan_{}_blue_box", item),
-    //             "true",
-    //             &format!("var:scanner_request_state == failed"),
-    //             vec![
-    //                 "var:scanner_request_trigger <- false",
-    //                 "var:scanner_request_state <- initial",
-    //                 &format!("var:{item}_blue_box_scanned_estimated <- UNKNOWN"),
-    //                 &format!("var:{item}_position_estimated <- UNKNOWN"),
-    //             ],
-    //             Vec::<&str>::new(),
-    //             &state,
-    //         ),
-    //         Transition::empty(),
-    //     ));
-    // }
+    for item in vec!["pipe", "plate"] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_scan_{}_blue_box", item),
+            "scanner",
+            vec![&format!("var:{item}_blue_box_scanned_estimated <- UNKNOWN"), &format!("var:{item}_position_estimated <- UNKNOWN")],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 3, &format!("{item} is not found, its position is unknown."), "Retry the scan."), vec![]),
+                FailureBranch::new(FailureMode::new("unreadable_marker", 4, 3, 5, &format!("Marker on the {item} can't be read."), "Clean the marker, scan from another angle."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_scan_{}_blue_box", item),
+            "scanner",
+            3000,
+            &format!(
+                "var:scanner_request_state == initial \
+                && var:scanner_request_trigger == false \
+                && var:gantry_position_estimated == {item}_blue_box \
+                && var:robot_position_estimated == {item}_blue_box"
+            ),
+            vec![
+                &format!("var:scanner_command_command <- scan"),
+                &format!("var:scanner_item_command <- {item}"),
+            ],
+            &state,
+        );
+        operations.push(Operation::new(
+            &format!("op_scan_{}_blue_box", item),
+            deadline,
+            Some(3),
+            vec![start],
+            Vec::from([
+                Transition::parse(
+                    &format!("complete_op_scan_{}_blue_box", item),
+                    "true",
+                    &format!("var:scanner_request_state == succeeded && var:scanner_scanned_item_measured == {item}"),
+                    vec![
+                        "var:scanner_request_trigger <- false",
+                        "var:scanner_request_state <- initial",
+                        &format!("var:{item}_blue_box_scanned_estimated <- true"),
+                        &format!("var:{item}_position_estimated <- {item}_blue_box"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                ),
+                Transition::parse(
+                    &format!("complete_op_scan_{}_blue_box_2", item),
+                    "true",
+                    &format!("var:scanner_request_state == succeeded && var:scanner_scanned_item_measured != {item}"),
+                    vec![
+                        "var:scanner_request_trigger <- false",
+                        "var:scanner_request_state <- initial",
+                        &format!("var:{item}_blue_box_scanned_estimated <- true"),
+                        &format!("var:{item}_position_estimated <- UNKNOWN"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                )
+            ]),
+            fail_transitions,
+            Vec::from([]),
+            Vec::from([])
+        ));
+
+        fmea.add(&format!("op_scan_{}_blue_box", item), failure_modes);
+    }
 
                     //     && var:gantry_locked_estimated == true \
                     // && var:gantry_calibrated_estimated == true",
@@ -302,30 +361,42 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
         "c",
         "d",
         "pipe_blue_box",
+        "plate_blue_box",
         "plate_pipe_box",
         "gripper_tool_rack",
         "suction_tool_rack",
     ] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_robot_move_to_{}", pos),
+            "robot",
+            vec!["var:robot_position_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 2, &format!("Robot did not reach {pos}, position unknown."), "Retry, replan if retries are exhausted."), vec![]),
+                FailureBranch::new(FailureMode::new("move_outside_work_area", 7, 2, 3, &format!("Robot path to {pos} leaves the work area."), "Check the taught positions and the work area limits."), vec![]),
+                FailureBranch::new(FailureMode::new("collision_with_operator", 10, 1, 4, &format!("Robot hit an operator on the way to {pos}."), "Safety scanner zones, reduced speed in shared areas."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_robot_move_to_{}", pos),
+            "robot",
+            5000,
+            "var:robot_request_state == initial \
+            && var:robot_request_trigger == false \
+            && var:gantry_locked_estimated == true \
+            && var:gantry_calibrated_estimated == true",
+            vec![
+                &format!("var:robot_command_command <- move"),
+                &format!("var:robot_position_command <- {pos}"),
+                &format!("var:robot_speed_command <- 0.5"),
+            ],
+            &state,
+        );
         operations.push(Operation::new(
             &format!("op_robot_move_to_{}", pos),
-            None,
+            deadline,
             Some(3),
-            Vec::from([Transition::parse(
-                &format!("start_op_robot_move_to_{}", pos),
-                "var:robot_request_state == initial \
-                && var:robot_request_trigger == false \
-                && var:gantry_locked_estimated == true \
-                && var:gantry_calibrated_estimated == true",
-                "true",
-                vec![
-                    &format!("var:robot_command_command <- move"),
-                    &format!("var:robot_position_command <- {pos}"),
-                    &format!("var:robot_speed_command <- 0.5"),
-                    "var:robot_request_trigger <- true",
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            vec![start],
             Vec::from([Transition::parse(
                 &format!("complete_op_robot_move_to_{}", pos),
                 "true",
@@ -338,44 +409,44 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
                 Vec::<&str>::new(),
                 &state,
             )]),
-            Vec::from([Transition::parse(
-                &format!("fail_op_robot_move_to_{}", pos),
-                "true",
-                &format!("var:robot_request_state == failed"),
-                vec![
-                    "var:robot_request_trigger <- false",
-                    "var:robot_request_state <- initial",
-                    &format!("var:robot_position_estimated <- UNKNOWN"),
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            fail_transitions,
             Vec::from([]),
             Vec::from([])
         ));
+
+        fmea.add(&format!("op_robot_move_to_{}", pos), failure_modes);
     }
 
     for tool in vec!["gripper_tool", "suction_tool", "none", "unknown"] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_robot_check_for_{tool}_mounted"),
+            "robot",
+            vec!["var:robot_mounted_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 2, 6, "Mounted tool remains unknown.", "Retry the check, ask the operator."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_robot_check_for_{tool}_mounted"),
+            "robot",
+            2000,
+            &format!(
+                "(var:robot_mounted_checked == false || var:robot_mounted_checked == UNKNOWN) \
+                && var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_mounted_estimated == UNKNOWN"
+            ),
+            vec![
+                &format!("var:robot_command_command <- check_mounted_tool"),
+            ],
+            &state,
+        );
         operations.push(Operation::new(
             &format!("op_robot_check_for_{tool}_mounted"),
-            None,
+            deadline,
             Some(3),
-            Vec::from([Transition::parse(
-                &format!("start_op_robot_check_for_{tool}_mounted"),
-                &format!(
-                    "(var:robot_mounted_checked == false || var:robot_mounted_checked == UNKNOWN) \
-                    && var:robot_request_state == initial \
-                    && var:robot_request_trigger == false \
-                    && var:robot_mounted_estimated == UNKNOWN"
-                ),
-                "true",
-                vec![
-                    &format!("var:robot_command_command <- check_mounted_tool"),
-                    "var:robot_request_trigger <- true",
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            vec![start],
             Vec::from([
                 Transition::parse(
                     &format!("complete_op_robot_check_for_{tool}_mounted"),
@@ -406,45 +477,46 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
                     &state,
                 )
             ]),
-            Vec::from([Transition::parse(
-                &format!("fail_op_robot_check_mounted"),
-                "true",
-                &format!("var:robot_request_state == failed"),
-                vec![
-                    "var:robot_request_trigger <- false",
-                    "var:robot_request_state <- initial",
-                    &format!("var:robot_mounted_estimated <- UNKNOWN"),
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            fail_transitions,
             Vec::from([]),
             Vec::from([])
         ));
+
+        fmea.add(&format!("op_robot_check_for_{tool}_mounted"), failure_modes);
     }
 
     for tool in vec!["gripper_tool", "suction_tool"] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_robot_mount_{}", tool),
+            "robot",
+            vec!["var:robot_mounted_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 5, 4, 5, &format!("{tool} is not mounted or only partially mounted."), "Check the mounted tool before using it."), vec![]),
+                FailureBranch::new(FailureMode::new("collision_with_operator", 10, 1, 4, "Robot hit an operator at the tool rack.", "Safety scanner zones around the tool racks."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_robot_mount_{}", tool),
+            "robot",
+            8000,
+            &format!(
+                "var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_position_estimated == {tool}_rack \
+                && var:robot_mounted_estimated == none \
+                && var:gantry_locked_estimated == true",
+            ),
+            vec![
+                &format!("var:robot_command_command <- mount"),
+            ],
+            &state,
+        );
         operations.push(Operation::new(
             &format!("op_robot_mount_{}", tool),
-            None,
+            deadline,
             Some(3),
-            Vec::from([Transition::parse(
-                &format!("start_op_robot_mount_{}", tool),
-                &format!(
-                    "var:robot_request_state == initial \
-                    && var:robot_request_trigger == false \
-                    && var:robot_position_estimated == {tool}_rack \
-                    && var:robot_mounted_estimated == none \
-                    && var:gantry_locked_estimated == true",
-                ),
-                "true",
-                vec![
-                    &format!("var:robot_command_command <- mount"),
-                    "var:robot_request_trigger <- true",
-                ],
-                Vec::<&str>::new(),
-                &state,
-            )]),
+            vec![start],
             Vec::from([Transition::parse(
                 &format!("complete_op_robot_mount_{}", tool),
                 "true",
@@ -457,72 +529,186 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
                 Vec::<&str>::new(),
                 &state,
             )]),
+            fail_transitions,
+            Vec::from([]),
+            Vec::from([])
+        ));
+
+        fmea.add(&format!("op_robot_mount_{}", tool), failure_modes);
+    }
+
+    for tool in vec!["gripper_tool", "suction_tool"] {
+        let (fail_transitions, failure_modes) = failure_branches(
+            &format!("op_robot_unmount_{tool}"),
+            "robot",
+            vec!["var:robot_mounted_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 5, 3, 5, &format!("{tool} is still mounted or left hanging in the rack."), "Check the mounted tool before mounting another one."), vec![]),
+                FailureBranch::new(FailureMode::new("collision_with_operator", 10, 1, 4, "Robot hit an operator at the tool rack.", "Safety scanner zones around the tool racks."), vec![]),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_robot_unmount_{tool}"),
+            "robot",
+            8000,
+            &format!(
+                "var:robot_request_state == initial \
+                && var:robot_request_trigger == false \
+                && var:robot_position_estimated == {tool}_rack \
+                && var:robot_mounted_estimated == {tool} \
+                && var:robot_holding_estimated == none \
+                && var:gantry_locked_estimated == true"
+            ),
+            vec![
+                &format!("var:robot_command_command <- unmount"),
+            ],
+            &state,
+        );
+        operations.push(Operation::new(
+            &format!("op_robot_unmount_{tool}"),
+            deadline,
+            Some(3),
+            vec![start],
             Vec::from([Transition::parse(
-                &format!("fail_op_robot_mount_{}", tool),
+                &format!("complete_op_robot_unmount_{tool}"),
                 "true",
-                &format!("var:robot_request_state == failed"),
+                &format!("var:robot_request_state == succeeded"),
                 vec![
                     "var:robot_request_trigger <- false",
                     "var:robot_request_state <- initial",
-                    &format!("var:robot_mounted_estimated <- UNKNOWN"),
+                    &format!("var:robot_mounted_estimated <- none"),
                 ],
                 Vec::<&str>::new(),
                 &state,
             )]),
+            fail_transitions,
             Vec::from([]),
             Vec::from([])
         ));
+
+        fmea.add(&format!("op_robot_unmount_{tool}"), failure_modes);
     }
 
-    for tool in vec!["gripper_tool", "suction_tool"] {
-        operations.push(Operation::new(
-            &format!("op_robot_unmount_{tool}"),
-            None,
-            Some(3),
-            Vec::from([Transition::parse(
-                &format!("start_op_robot_unmount_{tool}"),
+    // Pipes are handled with the gripper, plates with the suction tool
+    for (item, tool) in vec![("pipe", "gripper_tool"), ("plate", "suction_tool")] {
+        for pos in vec!["pipe_blue_box", "plate_blue_box", "plate_pipe_box", "a", "b", "c", "d"] {
+            let (fail_transitions, failure_modes) = failure_branches(
+                &format!("op_robot_pick_{item}_at_{pos}"),
+                "robot",
+                vec!["var:robot_holding_estimated <- UNKNOWN", &format!("var:{item}_position_estimated <- UNKNOWN")],
+                vec![
+                    FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 3, &format!("{item} is not picked at {pos}, item and tool state unknown."), "Check the tool and rescan the item."), vec![]),
+                    FailureBranch::new(FailureMode::new("mis_grip", 6, 4, 6, &format!("{item} is gripped off-center at {pos} and can slip."), &format!("Check the {tool} and the grip before moving.")), vec![]),
+                    FailureBranch::new(FailureMode::new("collision_with_operator", 10, 1, 4, &format!("Robot hit an operator while picking at {pos}."), "Safety scanner zones, reduced speed in shared areas."), vec![]),
+                ],
+                &state,
+            );
+            let (deadline, start) = start_request(
+                &format!("op_robot_pick_{item}_at_{pos}"),
+                "robot",
+                5000,
                 &format!(
                     "var:robot_request_state == initial \
                     && var:robot_request_trigger == false \
-                    && var:robot_position_estimated == {tool}_rack \
+                    && var:robot_position_estimated == {pos} \
                     && var:robot_mounted_estimated == {tool} \
+                    && var:robot_holding_estimated == none \
+                    && var:{item}_position_estimated == {pos} \
                     && var:gantry_locked_estimated == true"
                 ),
-                "true",
                 vec![
-                    &format!("var:robot_command_command <- unmount"),
-                    "var:robot_request_trigger <- true",
+                    &format!("var:robot_command_command <- pick"),
+                    &format!("var:robot_position_command <- {pos}"),
                 ],
-                Vec::<&str>::new(),
                 &state,
-            )]),
-            Vec::from([Transition::parse(
-                &format!("complete_op_robot_unmount_{tool}"),
-                "true",
-                &format!("var:robot_request_state == succeeded"),
+            );
+            operations.push(Operation::new(
+                &format!("op_robot_pick_{item}_at_{pos}"),
+                deadline,
+                Some(3),
+                vec![start],
+                Vec::from([Transition::parse(
+                    &format!("complete_op_robot_pick_{item}_at_{pos}"),
+                    "true",
+                    &format!("var:robot_request_state == succeeded"),
+                    vec![
+                        "var:robot_request_trigger <- false",
+                        "var:robot_request_state <- initial",
+                        &format!("var:robot_holding_estimated <- {item}"),
+                        &format!("var:{item}_position_estimated <- robot"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                )]),
+                // A failed pick might have left the item in place, moved it or kept it in the tool
+                fail_transitions,
+                Vec::from([]),
+                Vec::from([])
+            ));
+
+            fmea.add(&format!("op_robot_pick_{item}_at_{pos}"), failure_modes);
+
+            let (fail_transitions, failure_modes) = failure_branches(
+                &format!("op_robot_place_{item}_at_{pos}"),
+                "robot",
+                vec![&format!("var:{item}_position_estimated <- UNKNOWN")],
                 vec![
-                    "var:robot_request_trigger <- false",
-                    "var:robot_request_state <- initial",
-                    &format!("var:robot_mounted_estimated <- none"),
+                    FailureBranch::new(
+                        FailureMode::new(GENERIC_FAILURE, 4, 3, 3, &format!("{item} is not placed at {pos}, item and tool state unknown."), "Check the tool and rescan the item."),
+                        vec!["var:robot_holding_estimated <- UNKNOWN"],
+                    ),
+                    FailureBranch::new(
+                        FailureMode::new("dropped_item", 7, 3, 5, &format!("{item} is dropped on the way to {pos}."), "Reduce the speed while holding, rescan the cell."),
+                        vec!["var:robot_holding_estimated <- none"],
+                    ),
+                    FailureBranch::new(FailureMode::new("collision_with_operator", 10, 1, 4, &format!("Robot hit an operator while placing at {pos}."), "Safety scanner zones, reduced speed in shared areas."), vec![]),
                 ],
-                Vec::<&str>::new(),
                 &state,
-            )]),
-            Vec::from([Transition::parse(
-                &format!("fail_op_robot_unmount_{tool}"),
-                "true",
-                &format!("var:robot_request_state == failed"),
+            );
+            let (deadline, start) = start_request(
+                &format!("op_robot_place_{item}_at_{pos}"),
+                "robot",
+                5000,
+                &format!(
+                    "var:robot_request_state == initial \
+                    && var:robot_request_trigger == false \
+                    && var:robot_position_estimated == {pos} \
+                    && var:robot_mounted_estimated == {tool} \
+                    && var:robot_holding_estimated == {item} \
+                    && var:gantry_locked_estimated == true"
+                ),
                 vec![
-                    "var:robot_request_trigger <- false",
-                    "var:robot_request_state <- initial",
-                    &format!("var:robot_mounted_estimated <- UNKNOWN"),
+                    &format!("var:robot_command_command <- place"),
+                    &format!("var:robot_position_command <- {pos}"),
                 ],
-                Vec::<&str>::new(),
                 &state,
-            )]),
-            Vec::from([]),
-            Vec::from([])
-        ));
+            );
+            operations.push(Operation::new(
+                &format!("op_robot_place_{item}_at_{pos}"),
+                deadline,
+                Some(3),
+                vec![start],
+                Vec::from([Transition::parse(
+                    &format!("complete_op_robot_place_{item}_at_{pos}"),
+                    "true",
+                    &format!("var:robot_request_state == succeeded"),
+                    vec![
+                        "var:robot_request_trigger <- false",
+                        "var:robot_request_state <- initial",
+                        "var:robot_holding_estimated <- none",
+                        &format!("var:{item}_position_estimated <- {pos}"),
+                    ],
+                    Vec::<&str>::new(),
+                    &state,
+                )]),
+                fail_transitions,
+                Vec::from([]),
+                Vec::from([])
+            ));
+
+            fmea.add(&format!("op_robot_place_{item}_at_{pos}"), failure_modes);
+        }
     }
 
     // auto_operations.push(Operation::new(
@@ -609,7 +795,7 @@ pub fn bt_test_endre(name: &str, state: &State) -> (Model, State) {
 
     let model = Model::new(name, auto_transitions, auto_operations, operations);
 
-    (model, state)
+    (model, state, fmea)
 }
 
 #[test]
@@ -621,9 +807,14 @@ fn test_model() {
     let runner_vars = generate_runner_state_variables("bt_test_endre");
     let state = state.extend(runner_vars, true);
 
-    let (model, state) = crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let (model, state, fmea) = crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
     // let name = model.clone().name;
 
+    // Every operation should have a risk annotation
+    let (missing, unknown) = fmea.check_coverage(&model);
+    assert!(missing.is_empty(), "Operations without risk annotations: {:?}", missing);
+    assert!(unknown.is_empty(), "Risk annotations for unknown operations: {:?}", unknown);
+
     let op_vars = generate_operation_state_variables(&model, false);
     let state = state.extend(op_vars, true);
 
@@ -662,3 +853,64 @@ fn test_model() {
 
     assert!(plan.found);
 }
+
+#[test]
+fn test_model_pick_and_place() {
+    let state = crate::models::bt_test_endre::state::state();
+    let runner_vars = generate_runner_state_variables("bt_test_endre");
+    let state = state.extend(runner_vars, true);
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    let state = state
+        .update("gantry_locked_estimated", true.to_spvalue())
+        .update("gantry_calibrated_estimated", true.to_spvalue())
+        .update("robot_position_estimated", "pipe_blue_box".to_spvalue())
+        .update("robot_mounted_estimated", "suction_tool".to_spvalue())
+        .update("pipe_position_estimated", "pipe_blue_box".to_spvalue())
+        .update(
+            &format!("{}_goal", model.name),
+            "var:pipe_position_estimated == plate_pipe_box".to_spvalue(),
+        );
+
+    // Pipes can only be picked with the gripper, so the tool has to be exchanged first
+    let plan = bfs_operation_planner(
+        state.clone(),
+        state.extract_goal(&model.name),
+        model.operations.clone(),
+        30,
+    );
+    assert!(plan.found);
+    assert!(plan.plan.contains(&"op_robot_mount_gripper_tool".to_string()));
+    assert_eq!(
+        plan.plan.last(),
+        Some(&"op_robot_place_pipe_at_plate_pipe_box".to_string())
+    );
+}
+
+#[test]
+fn test_model_fail_on_collision() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let operation = model
+        .operations
+        .iter()
+        .find(|o| o.name == "op_gantry_move_to_home")
+        .unwrap();
+
+    let state = state
+        .update("gantry_request_state", "failed".to_spvalue())
+        .update("gantry_calibrated_estimated", true.to_spvalue());
+    for (cause, calibrated) in [("violation", true.to_spvalue()), ("collision", false.to_spvalue()), ("detected_drift", false.to_spvalue())] {
+        let state = state.update("gantry_failure_cause", cause.to_spvalue());
+        let taken: Vec<&Transition> = operation
+            .fail_transitions
+            .iter()
+            .filter(|t| t.clone().eval_running(&state))
+            .collect();
+        assert_eq!(taken.len(), 1, "Exactly one fail transition for {}", cause);
+        let next = taken[0].clone().take_planning(&state);
+        assert_eq!(next.get_value("gantry_calibrated_estimated"), calibrated);
+    }
+}
@@ -1,70 +1,5 @@
 use micro_sp::*;
-
-fn generate_basic_variables(name: &str, state: &State) -> State {
-    let request_trigger = bv!(&&format!("{}_request_trigger", name));
-    let request_state = v!(&&format!("{}_request_state", name));
-    let total_fail_counter = iv!(&&format!("{}_total_fail_counter", name));
-    let subsequent_fail_counter = iv!(&&format!("{}_subsequent_fail_counter", name));
-    let ref_counter = iv!(&&format!("{}_ref_counter", name));
-
-    let state = state.add(assign!(request_trigger, false.to_spvalue()));
-    let state = state.add(assign!(request_state, "initial".to_spvalue()));
-    let state = state.add(assign!(total_fail_counter, 0.to_spvalue()));
-    let state = state.add(assign!(subsequent_fail_counter, 0.to_spvalue()));
-    let state = state.add(assign!(ref_counter, 1.to_spvalue()));
-
-    state
-}
-
-fn generate_emulation_variables(name: &str, state: &State) -> State {
-    // -----------------------------------------------------------------------
-    // # DONT_EMULATE_EXECUTION_TIME: The action will be executed immediatelly
-    // # EMULATE_EXACT_EXECUTION_TIME: The action will always take "emulate_execution_time" amount of time
-    // # EMULATE_RANDOM_EXECUTION_TIME: The action will randomly take between 0 and "emulated_execution_time" amount of time
-    // uint8 DONT_EMULATE_EXECUTION_TIME = 0
-    // uint8 EMULATE_EXACT_EXECUTION_TIME = 1
-    // uint8 EMULATE_RANDOM_EXECUTION_TIME = 2
-    // uint8 emulate_execution_time
-    // int32 emulated_execution_time # milliseconds 
-
-    // # DONT_EMULATE_FAILURE: The action will be execute succesfully every time
-    // # EMULATE_FAILURE_ALWAYS: The action will always fail
-    // # EMULATE_RANDOM_FAILURE_RATE: The action will randomly fail with a "emulated_failure_rate" rate
-    // uint8 DONT_EMULATE_FAILURE = 0
-    // uint8 EMULATE_FAILURE_ALWAYS = 1
-    // uint8 EMULATE_RANDOM_FAILURE_RATE = 2
-    // uint8 emulate_failure_rate
-    // int32 emulated_failure_rate # percentage 0..100  
-
-    // # DONT_EMULATE_FAILURE_CAUSE: If the action fails, it wil fail with a generic "fail" cause
-    // # EMULATE_EXACT_FAILURE_CAUSE: Specify why the exact reason why the action fails (takes the first from the "emulated_failure_cause" list)
-    // # EMULATE_RANDOM_FAILURE_CAUSE: The action will fail and randomly choose a cause from the "emulated_failure_cause" list
-    // uint8 DONT_EMULATE_FAILURE_CAUSE = 0
-    // uint8 EMULATE_EXACT_FAILURE_CAUSE = 1
-    // uint8 EMULATE_RANDOM_FAILURE_CAUSE = 2
-    // uint8 emulate_failure_cause
-    // string[] emulated_failure_cause # For example: ["violation", "timeout", "collision", etc.]
-    // -----------------------------------------------------------------------
-
-    let emulate_execution_time = iv!(&&format!("{}_emulate_execution_time", name));
-    let emulate_failure_rate = iv!(&&format!("{}_emulate_failure_rate", name));
-    let emulate_failure_cause = iv!(&&format!("{}_emulate_failure_cause", name));
-
-    let state = state.add(assign!(emulate_execution_time, 0.to_spvalue()));
-    let state = state.add(assign!(emulate_failure_rate, 0.to_spvalue()));
-    let state = state.add(assign!(emulate_failure_cause, 0.to_spvalue()));
-
-    let emulated_execution_time = iv!(&&format!("{}_emulated_execution_time", name));
-    let emulated_failure_rate = iv!(&&format!("{}_emulated_failure_rate", name));
-    let emulated_failure_cause = av!(&&format!("{}_emulated_failure_cause", name));
-
-    let state = state.add(assign!(emulated_execution_time, 0.to_spvalue()));
-    let state = state.add(assign!(emulated_failure_rate, 0.to_spvalue()));
-    let state = state.add(assign!(emulated_failure_cause, SPValue::Array(SPValueType::String, vec![])));
-
-    state
-
-}
+use crate::*;
 
 pub fn state() -> State {
     let state = State::new();
@@ -77,6 +12,7 @@ pub fn state() -> State {
     // -----------------------------------------------------------------------
 
     let state = generate_basic_variables("gantry", &state);
+    let state = generate_failure_cause_counters("gantry", &state);
 
     let gantry_command_command = v!("gantry_command_command");
     let gantry_speed_command = fv!("gantry_speed_command");
@@ -108,6 +44,7 @@ pub fn state() -> State {
     // -----------------------------------------------------------------------
 
     let state = generate_basic_variables("robot", &state);
+    let state = generate_failure_cause_counters("robot", &state);
 
     let robot_command_command = v!("robot_command_command");
     let robot_speed_command = fv!("robot_speed_command");
@@ -124,12 +61,14 @@ pub fn state() -> State {
     let robot_mounted_checked = bv!("robot_mounted_checked");
     let asdf = bv!("asdf");
     let robot_mounted_one_time_measured = v!("robot_mounted_one_time_measured");
+    let robot_holding_estimated = v!("robot_holding_estimated"); // none, pipe, plate
 
     let state = state.add(assign!(robot_speed_measured, SPValue::UNKNOWN));
     let state = state.add(assign!(robot_position_estimated, SPValue::UNKNOWN));
     let state = state.add(assign!(robot_mounted_estimated, SPValue::UNKNOWN));
     let state = state.add(assign!(robot_mounted_checked, SPValue::Bool(false)));
     let state = state.add(assign!(robot_mounted_one_time_measured, SPValue::UNKNOWN));
+    let state = state.add(assign!(robot_holding_estimated, "none".to_spvalue()));
     let state = state.add(assign!(asdf, SPValue::Bool(false)));
 
     // let robot_mode_measured = v!("robot_mode_measured"); // safety_stop, emergency_stop, operational
@@ -137,6 +76,56 @@ pub fn state() -> State {
     // Optional: emulate gantry failure and execution time
     let state = generate_emulation_variables("robot", &state);
 
-    
+    // -----------------------------------------------------------------------
+    // Scanner:
+    // string command # scan
+    // string item # pipe, plate
+    // -----------------------------------------------------------------------
+
+    let state = generate_basic_variables("scanner", &state);
+    let state = generate_failure_cause_counters("scanner", &state);
+
+    let scanner_command_command = v!("scanner_command_command");
+    let scanner_item_command = v!("scanner_item_command");
+    let scanner_scanned_item_measured = v!("scanner_scanned_item_measured");
+
+    let state = state.add(assign!(scanner_command_command, SPValue::UNKNOWN));
+    let state = state.add(assign!(scanner_item_command, SPValue::UNKNOWN));
+    let state = state.add(assign!(scanner_scanned_item_measured, SPValue::UNKNOWN));
+
+    // Optional: emulate scanner failure and execution time
+    let state = generate_emulation_variables("scanner", &state);
+
+    // -----------------------------------------------------------------------
+    // Camera System:
+    // string command # update
+    // string blue_box # pipe_blue_box, plate_blue_box
+    // -----------------------------------------------------------------------
+
+    let state = generate_basic_variables("camera_system", &state);
+    let state = generate_failure_cause_counters("camera_system", &state);
+
+    let camera_system_command_command = v!("camera_system_command_command");
+    let camera_system_update_command = v!("camera_system_update_command");
+
+    let state = state.add(assign!(camera_system_command_command, SPValue::UNKNOWN));
+    let state = state.add(assign!(camera_system_update_command, SPValue::UNKNOWN));
+
+    // Optional: emulate camera system failure and execution time
+    let state = generate_emulation_variables("camera_system", &state);
+
+    // We estimate (memory variables) where the boxes and the items are
+    let mut state = state;
+    for blue_box in vec!["pipe_blue_box", "plate_blue_box"] {
+        let position_updated_estimated = bv!(&&format!("{blue_box}_position_updated_estimated"));
+        state = state.add(assign!(position_updated_estimated, SPValue::UNKNOWN));
+    }
+    for item in vec!["pipe", "plate"] {
+        let scanned_estimated = bv!(&&format!("{item}_blue_box_scanned_estimated"));
+        let position_estimated = v!(&&format!("{item}_position_estimated"));
+        state = state.add(assign!(scanned_estimated, SPValue::UNKNOWN));
+        state = state.add(assign!(position_estimated, SPValue::UNKNOWN));
+    }
+
     state
 }
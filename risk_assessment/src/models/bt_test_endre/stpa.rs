@@ -0,0 +1,226 @@
+use micro_sp::*;
+use crate::*;
+
+// Operations are linked to the UCAs by their name prefix
+fn operations_starting_with(model: &Model, prefix: &str) -> Vec<String> {
+    model
+        .operations
+        .iter()
+        .filter(|o| o.name.starts_with(prefix))
+        .map(|o| o.name.clone())
+        .collect()
+}
+
+fn control_action(
+    model: &Model,
+    device: &str,
+    command: &str,
+    prefix: &str,
+    ucas: Vec<(UcaType, &str, Vec<&str>, &str)>,
+) -> ControlAction {
+    let operations = operations_starting_with(model, prefix);
+    ControlAction {
+        name: format!("{device}_{command}"),
+        controller: "operation_runner".to_string(),
+        process: device.to_string(),
+        device: device.to_string(),
+        command: command.to_string(),
+        unsafe_control_actions: ucas
+            .iter()
+            .map(|(uca_type, description, hazards, guard)| {
+                UnsafeControlAction::new(
+                    &format!("uca_{device}_{command}_{uca_type}"),
+                    *uca_type,
+                    description,
+                    hazards.clone(),
+                    operations.clone(),
+                    guard,
+                )
+            })
+            .collect(),
+    }
+}
+
+pub fn stpa(model: &Model) -> ControlStructure {
+    let mut cs = ControlStructure::new(&model.name);
+
+    for (id, description) in [
+        ("L1", "Injury to an operator."),
+        ("L2", "Damage to the gantry, the robot, the tools or the items."),
+        ("L3", "Loss of production."),
+    ] {
+        cs.losses.push(Loss {
+            id: id.to_string(),
+            description: description.to_string(),
+        });
+    }
+
+    for (id, description, losses) in [
+        ("H1", "Robot moves while the gantry is not locked.", vec!["L1", "L2"]),
+        ("H2", "Gantry moves while it is not calibrated.", vec!["L2"]),
+        ("H3", "Robot uses a tool that is not the one it believes is mounted.", vec!["L2", "L3"]),
+        ("H4", "Cell stays in an unknown configuration.", vec!["L3"]),
+        ("H5", "Robot handles an item at a wrong or outdated position.", vec!["L2", "L3"]),
+    ] {
+        cs.hazards.push(Hazard {
+            id: id.to_string(),
+            description: description.to_string(),
+            losses: losses.iter().map(|l| l.to_string()).collect(),
+        });
+    }
+
+    cs.controllers.push(Controller {
+        name: "operation_runner".to_string(),
+        description: "micro_sp operation runner executing the plan.".to_string(),
+    });
+    cs.processes.push(ControlledProcess {
+        name: "gantry".to_string(),
+        description: "Gantry carrying the robot between the boxes.".to_string(),
+    });
+    cs.processes.push(ControlledProcess {
+        name: "robot".to_string(),
+        description: "Robot with exchangeable gripper and suction tools.".to_string(),
+    });
+    cs.processes.push(ControlledProcess {
+        name: "scanner".to_string(),
+        description: "Scanner identifying the items in the blue boxes.".to_string(),
+    });
+    cs.processes.push(ControlledProcess {
+        name: "camera_system".to_string(),
+        description: "Camera system locating the blue boxes.".to_string(),
+    });
+
+    cs.feedback.push(FeedbackChannel {
+        name: "gantry_feedback".to_string(),
+        process: "gantry".to_string(),
+        controller: "operation_runner".to_string(),
+        variables: vec![
+            "gantry_request_state".to_string(),
+            "gantry_position_estimated".to_string(),
+            "gantry_calibrated_estimated".to_string(),
+            "gantry_locked_estimated".to_string(),
+        ],
+    });
+    cs.feedback.push(FeedbackChannel {
+        name: "robot_feedback".to_string(),
+        process: "robot".to_string(),
+        controller: "operation_runner".to_string(),
+        variables: vec![
+            "robot_request_state".to_string(),
+            "robot_position_estimated".to_string(),
+            "robot_mounted_estimated".to_string(),
+            "robot_mounted_one_time_measured".to_string(),
+            "robot_holding_estimated".to_string(),
+        ],
+    });
+    cs.feedback.push(FeedbackChannel {
+        name: "scanner_feedback".to_string(),
+        process: "scanner".to_string(),
+        controller: "operation_runner".to_string(),
+        variables: vec![
+            "scanner_request_state".to_string(),
+            "scanner_scanned_item_measured".to_string(),
+        ],
+    });
+    cs.feedback.push(FeedbackChannel {
+        name: "camera_system_feedback".to_string(),
+        process: "camera_system".to_string(),
+        controller: "operation_runner".to_string(),
+        variables: vec![
+            "camera_system_request_state".to_string(),
+            "pipe_blue_box_position_updated_estimated".to_string(),
+            "plate_blue_box_position_updated_estimated".to_string(),
+        ],
+    });
+
+    cs.control_actions.push(control_action(model, "gantry", "move", "op_gantry_move_to_", vec![
+        (UcaType::NotProvided, "Gantry is not moved, the robot can't reach the boxes.", vec!["H4"], "true"),
+        (UcaType::Provided, "Gantry moves while it is locked or not calibrated.", vec!["H2"], "var:gantry_locked_estimated == false && var:gantry_calibrated_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Gantry moves before it is calibrated.", vec!["H2"], "var:gantry_calibrated_estimated == true"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Gantry stops before reaching the position.", vec!["H4"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "gantry", "calibrate", "op_gantry_calibrate", vec![
+        (UcaType::NotProvided, "Gantry is never calibrated and can't be moved.", vec!["H4"], "true"),
+        (UcaType::Provided, "Gantry is calibrated while it is locked.", vec!["H2"], "var:gantry_locked_estimated == false"),
+        (UcaType::WrongTimingOrOrder, "Gantry is calibrated while it is locked.", vec!["H2"], "var:gantry_locked_estimated == false"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Calibration is interrupted, the reference is wrong.", vec!["H2"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "gantry", "lock", "op_gantry_lock", vec![
+        (UcaType::NotProvided, "Gantry is not locked before the robot moves.", vec!["H1"], "true"),
+        (UcaType::Provided, "Gantry is locked while the robot is executing.", vec!["H4"], "var:robot_request_trigger == false"),
+        (UcaType::WrongTimingOrOrder, "Gantry is locked before it reaches the position.", vec!["H4"], "var:gantry_request_state == initial"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Lock is released while the robot is moving.", vec!["H1"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "gantry", "unlock", "op_gantry_unlock", vec![
+        (UcaType::NotProvided, "Gantry is not unlocked and can't be moved.", vec!["H4"], "true"),
+        (UcaType::Provided, "Gantry is unlocked while the robot is executing.", vec!["H1"], "var:robot_request_trigger == false"),
+        (UcaType::WrongTimingOrOrder, "Gantry is unlocked before the robot has finished.", vec!["H1"], "var:robot_request_trigger == false"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Gantry is left unlocked, lock state unknown.", vec!["H4"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "move", "op_robot_move_to_", vec![
+        (UcaType::NotProvided, "Robot is not moved, the task can't be completed.", vec!["H4"], "true"),
+        (UcaType::Provided, "Robot moves while the gantry is not locked.", vec!["H1"], "var:gantry_locked_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Robot moves before the gantry is calibrated and locked.", vec!["H1"], "var:gantry_locked_estimated == true && var:gantry_calibrated_estimated == true"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Robot stops before reaching the position.", vec!["H4"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "mount", "op_robot_mount_", vec![
+        (UcaType::NotProvided, "Tool is not mounted, the item can't be handled.", vec!["H4"], "true"),
+        (UcaType::Provided, "Tool is mounted while another tool is mounted.", vec!["H3"], "var:robot_mounted_estimated == none && var:gantry_locked_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Tool is mounted before the robot is at the rack.", vec!["H3"], "var:gantry_locked_estimated == true"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Tool is only partially mounted.", vec!["H3"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "unmount", "op_robot_unmount_", vec![
+        (UcaType::NotProvided, "Tool is not unmounted, the other tool can't be mounted.", vec!["H4"], "true"),
+        (UcaType::Provided, "Tool is unmounted while the gantry is not locked.", vec!["H1", "H3"], "var:gantry_locked_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Tool is unmounted before the robot is at the rack.", vec!["H3"], "var:gantry_locked_estimated == true"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Tool is left hanging between the robot and the rack.", vec!["H3"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "check_mounted_tool", "op_robot_check_for_", vec![
+        (UcaType::NotProvided, "Mounted tool is not checked, the estimate is used blindly.", vec!["H3"], "true"),
+        (UcaType::Provided, "Mounted tool is checked while the robot is executing.", vec!["H3"], "var:robot_request_trigger == false"),
+        (UcaType::WrongTimingOrOrder, "Mounted tool is checked after the tool is used.", vec!["H3"], "var:robot_mounted_estimated == UNKNOWN"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Check returns before the tool is identified.", vec!["H3"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "pick", "op_robot_pick_", vec![
+        (UcaType::NotProvided, "Item is not picked, the task can't be completed.", vec!["H4"], "true"),
+        (UcaType::Provided, "Item is picked with the wrong tool or while holding another item.", vec!["H3", "H5"], "var:robot_holding_estimated == none && var:gantry_locked_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Item is picked before its position is known.", vec!["H5"], "var:robot_position_estimated != UNKNOWN"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Grip is closed before the item is centered, the item is mis-gripped.", vec!["H5"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "robot", "place", "op_robot_place_", vec![
+        (UcaType::NotProvided, "Item is not placed and stays in the tool.", vec!["H3"], "true"),
+        (UcaType::Provided, "Item is placed while the gantry is not locked.", vec!["H1", "H5"], "var:gantry_locked_estimated == true"),
+        (UcaType::WrongTimingOrOrder, "Item is released before the robot is at the position.", vec!["H5"], "var:robot_position_estimated != UNKNOWN"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Item is released too early and dropped.", vec!["H5"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "scanner", "scan", "op_scan_", vec![
+        (UcaType::NotProvided, "Item is not scanned, its position stays unknown.", vec!["H4"], "true"),
+        (UcaType::Provided, "Item is scanned while the robot is not at the blue box.", vec!["H5"], "var:robot_position_estimated != UNKNOWN"),
+        (UcaType::WrongTimingOrOrder, "Item is scanned before the gantry has reached the blue box.", vec!["H5"], "var:gantry_position_estimated != UNKNOWN"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Scan returns before the item is identified.", vec!["H5"], "true"),
+    ]));
+    cs.control_actions.push(control_action(model, "camera_system", "update", "op_update_position_for_", vec![
+        (UcaType::NotProvided, "Box positions are not updated, outdated positions are used.", vec!["H5"], "true"),
+        (UcaType::Provided, "Box positions are updated while the robot is moving.", vec!["H5"], "var:robot_request_trigger == false"),
+        (UcaType::WrongTimingOrOrder, "Box positions are updated while the robot occludes the boxes.", vec!["H5"], "var:robot_request_trigger == false"),
+        (UcaType::StoppedTooSoonOrAppliedTooLong, "Update returns before all markers are detected.", vec!["H5"], "true"),
+    ]));
+
+    cs
+}
+
+#[test]
+fn test_stpa() {
+    let state = crate::models::bt_test_endre::state::state();
+    let runner_vars = generate_runner_state_variables("bt_test_endre");
+    let state = state.extend(runner_vars, true);
+    let (model, state, _fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+
+    let cs = stpa(&model);
+    let issues = cs.check_structure();
+    assert!(issues.is_empty(), "{:?}", issues);
+
+    let issues = cs.check_model(&model, &state, 4);
+    assert!(issues.is_empty(), "{:?}", issues);
+}
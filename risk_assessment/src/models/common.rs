@@ -0,0 +1,189 @@
+use micro_sp::*;
+
+// Request variables of a device, shared by the states of all models
+pub fn generate_basic_variables(name: &str, state: &State) -> State {
+    let request_trigger = bv!(&&format!("{}_request_trigger", name));
+    let request_state = v!(&&format!("{}_request_state", name));
+    let total_fail_counter = iv!(&&format!("{}_total_fail_counter", name));
+    let subsequent_fail_counter = iv!(&&format!("{}_subsequent_fail_counter", name));
+    let ref_counter = iv!(&&format!("{}_ref_counter", name));
+    let failure_cause = v!(&&format!("{}_failure_cause", name));
+    let info = v!(&&format!("{}_info", name));
+
+    let state = state.add(assign!(request_trigger, false.to_spvalue()));
+    let state = state.add(assign!(request_state, "initial".to_spvalue()));
+    let state = state.add(assign!(total_fail_counter, 0.to_spvalue()));
+    let state = state.add(assign!(subsequent_fail_counter, 0.to_spvalue()));
+    let state = state.add(assign!(ref_counter, 1.to_spvalue()));
+    let state = state.add(assign!(failure_cause, "".to_spvalue()));
+    let state = state.add(assign!(info, "".to_spvalue()));
+
+    // Milliseconds until the client gives up on the request, set by the
+    // operation that issues it from its deadline, 0 if it waits forever
+    let request_timeout = iv!(&&format!("{}_request_timeout", name));
+    let state = state.add(assign!(request_timeout, 0.to_spvalue()));
+
+    state
+}
+
+// Causes that the devices can report, from their emulators, from the ground truth
+// of the world when a command is physically impossible, and from the client tickers
+pub fn failure_causes(name: &str) -> Vec<&'static str> {
+    let mut causes = match name {
+        "gantry" => vec![
+            "violation", "collision", "detected_drift",
+            "not_calibrated", "gantry_locked",
+        ],
+        "robot" => vec![
+            "move_outside_work_area", "collision_with_operator", "mis_grip", "dropped_item",
+            "gantry_not_locked", "tool_already_mounted", "not_at_tool_rack", "no_tool_mounted",
+            "holding_item", "already_holding", "wrong_tool", "no_item_at_position", "not_holding",
+        ],
+        "scanner" => vec!["unreadable_marker"],
+        "camera_system" => vec!["occluded"],
+        _ => vec![],
+    };
+    causes.extend(vec!["generic_failure", "timeout", "invalid_emulation"]);
+    causes
+}
+
+// Commands that the emulators of the devices accept
+pub fn device_commands(name: &str) -> Vec<&'static str> {
+    match name {
+        "gantry" => vec!["move", "calibrate", "lock", "unlock"],
+        "robot" => vec!["move", "pick", "place", "mount", "unmount", "check_mounted_tool"],
+        "scanner" => vec!["scan"],
+        "camera_system" => vec!["update"],
+        _ => vec![],
+    }
+}
+
+// Counts the failures of the device per cause, i.e. gantry_fail_counter_collision
+pub fn generate_failure_cause_counters(name: &str, state: &State) -> State {
+    let mut state = state.clone();
+    for cause in failure_causes(name) {
+        let counter = iv!(&&format!("{}_fail_counter_{}", name, cause));
+        state = state.add(assign!(counter, 0.to_spvalue()));
+    }
+    state
+}
+
+pub fn generate_emulation_variables(name: &str, state: &State) -> State {
+    // -----------------------------------------------------------------------
+    // # DONT_EMULATE_EXECUTION_TIME: The action will be executed immediatelly
+    // # EMULATE_EXACT_EXECUTION_TIME: The action will always take "emulate_execution_time" amount of time
+    // # EMULATE_RANDOM_EXECUTION_TIME: The action will randomly take between 0 and "emulated_execution_time" amount of time
+    // # EMULATE_NORMAL_EXECUTION_TIME, EMULATE_LOG_NORMAL_EXECUTION_TIME: Mean "emulated_execution_time", standard deviation "emulated_execution_time_deviation"
+    // # EMULATE_EXPONENTIAL_EXECUTION_TIME: Mean "emulated_execution_time"
+    // # EMULATE_JITTERED_EXECUTION_TIME: "emulated_execution_time" plus up to "emulated_execution_time_deviation"
+    // # EMULATE_NEVER_RESPOND: The device hangs and never responds
+    // # EMULATE_RESPONSE_AFTER_TIMEOUT: The device responds "emulated_execution_time" after the request timeout
+    // uint8 DONT_EMULATE_EXECUTION_TIME = 0
+    // uint8 EMULATE_EXACT_EXECUTION_TIME = 1
+    // uint8 EMULATE_RANDOM_EXECUTION_TIME = 2
+    // uint8 EMULATE_NORMAL_EXECUTION_TIME = 3
+    // uint8 EMULATE_LOG_NORMAL_EXECUTION_TIME = 4
+    // uint8 EMULATE_EXPONENTIAL_EXECUTION_TIME = 5
+    // uint8 EMULATE_JITTERED_EXECUTION_TIME = 6
+    // uint8 EMULATE_NEVER_RESPOND = 7
+    // uint8 EMULATE_RESPONSE_AFTER_TIMEOUT = 8
+    // uint8 emulate_execution_time
+    // int32 emulated_execution_time # milliseconds
+    // int32 emulated_execution_time_deviation # milliseconds
+
+    // # DONT_EMULATE_FAILURE: The action will be execute succesfully every time
+    // # EMULATE_FAILURE_ALWAYS: The action will always fail
+    // # EMULATE_RANDOM_FAILURE_RATE: The action will randomly fail with a "emulated_failure_rate" rate
+    // uint8 DONT_EMULATE_FAILURE = 0
+    // uint8 EMULATE_FAILURE_ALWAYS = 1
+    // uint8 EMULATE_RANDOM_FAILURE_RATE = 2
+    // uint8 emulate_failure_rate
+    // int32 emulated_failure_rate # percentage 0..100  
+
+    // # DONT_EMULATE_FAILURE_CAUSE: If the action fails, it wil fail with a generic "fail" cause
+    // # EMULATE_EXACT_FAILURE_CAUSE: Specify why the exact reason why the action fails (takes the first from the "emulated_failure_cause" list)
+    // # EMULATE_RANDOM_FAILURE_CAUSE: The action will fail and randomly choose a cause from the "emulated_failure_cause" list
+    // uint8 DONT_EMULATE_FAILURE_CAUSE = 0
+    // uint8 EMULATE_EXACT_FAILURE_CAUSE = 1
+    // uint8 EMULATE_RANDOM_FAILURE_CAUSE = 2
+    // uint8 emulate_failure_cause
+    // string[] emulated_failure_cause # For example: ["violation", "timeout", "collision", etc.]
+    // uint32[] emulated_failure_cause_weights # Relative weights of the causes for EMULATE_RANDOM_FAILURE_CAUSE, uniform if empty
+    // string[] emulated_correlation_after # Previous failure cause of the device
+    // string[] emulated_correlation_cause # Cause that becomes more or less likely after it
+    // uint32[] emulated_correlation_factor # percentage, 100 leaves the cause unchanged
+
+    // # DONT_EMULATE_SILENT_FAILURE: A successful action always has its effect
+    // # EMULATE_SILENT_FAILURE_ALWAYS: The action reports success, but its effect never happens
+    // # EMULATE_RANDOM_SILENT_FAILURE_RATE: The action reports success, but its effect doesn't happen with a "emulated_silent_failure_rate" rate
+    // uint8 DONT_EMULATE_SILENT_FAILURE = 0
+    // uint8 EMULATE_SILENT_FAILURE_ALWAYS = 1
+    // uint8 EMULATE_RANDOM_SILENT_FAILURE_RATE = 2
+    // uint8 emulate_silent_failure
+    // int32 emulated_silent_failure_rate # percentage 0..100
+    // -----------------------------------------------------------------------
+
+    let emulate_execution_time = iv!(&&format!("{}_emulate_execution_time", name));
+    let emulate_failure_rate = iv!(&&format!("{}_emulate_failure_rate", name));
+    let emulate_failure_cause = iv!(&&format!("{}_emulate_failure_cause", name));
+
+    let state = state.add(assign!(emulate_execution_time, 0.to_spvalue()));
+    let state = state.add(assign!(emulate_failure_rate, 0.to_spvalue()));
+    let state = state.add(assign!(emulate_failure_cause, 0.to_spvalue()));
+
+    let emulated_execution_time = iv!(&&format!("{}_emulated_execution_time", name));
+    let emulated_failure_rate = iv!(&&format!("{}_emulated_failure_rate", name));
+    let emulated_failure_cause = av!(&&format!("{}_emulated_failure_cause", name));
+
+    let state = state.add(assign!(emulated_execution_time, 0.to_spvalue()));
+    let emulated_execution_time_deviation = iv!(&&format!("{}_emulated_execution_time_deviation", name));
+    let state = state.add(assign!(emulated_execution_time_deviation, 0.to_spvalue()));
+    let state = state.add(assign!(emulated_failure_rate, 0.to_spvalue()));
+    let state = state.add(assign!(emulated_failure_cause, SPValue::Array(SPValueType::String, vec![])));
+    let emulated_failure_cause_weights = av!(&&format!("{}_emulated_failure_cause_weights", name));
+    let state = state.add(assign!(emulated_failure_cause_weights, SPValue::Array(SPValueType::Int64, vec![])));
+
+    let emulated_correlation_after = av!(&&format!("{}_emulated_correlation_after", name));
+    let emulated_correlation_cause = av!(&&format!("{}_emulated_correlation_cause", name));
+    let emulated_correlation_factor = av!(&&format!("{}_emulated_correlation_factor", name));
+
+    let state = state.add(assign!(emulated_correlation_after, SPValue::Array(SPValueType::String, vec![])));
+    let state = state.add(assign!(emulated_correlation_cause, SPValue::Array(SPValueType::String, vec![])));
+    let state = state.add(assign!(emulated_correlation_factor, SPValue::Array(SPValueType::Int64, vec![])));
+
+    let emulate_silent_failure = iv!(&&format!("{}_emulate_silent_failure", name));
+    let emulated_silent_failure_rate = iv!(&&format!("{}_emulated_silent_failure_rate", name));
+
+    let state = state.add(assign!(emulate_silent_failure, 0.to_spvalue()));
+    let state = state.add(assign!(emulated_silent_failure_rate, 0.to_spvalue()));
+
+    state
+
+}
+
+// The start transition of an operation that sends one request to the device once
+// the guard holds. The deadline of the operation (milliseconds) is passed on to the
+// client ticker of the device as the request timeout, and a request that gets no
+// response in time fails with the cause "timeout". Returns the deadline for
+// Operation::new together with the transition.
+pub fn start_request(
+    operation: &str,
+    device: &str,
+    deadline: i64,
+    guard: &str,
+    actions: Vec<&str>,
+    state: &State,
+) -> (Option<i64>, Transition) {
+    let mut actions: Vec<String> = actions.iter().map(|a| a.to_string()).collect();
+    actions.push(format!("var:{device}_request_trigger <- true"));
+    actions.push(format!("var:{device}_request_timeout <- {deadline}"));
+    let start = Transition::parse(
+        &format!("start_{operation}"),
+        guard,
+        "true",
+        actions.iter().map(|a| a.as_str()).collect(),
+        Vec::<&str>::new(),
+        state,
+    );
+    (Some(deadline), start)
+}
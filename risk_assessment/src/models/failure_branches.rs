@@ -0,0 +1,121 @@
+use crate::*;
+use micro_sp::*;
+
+// One way an operation can fail, keyed on the failure cause that the device reports
+// in {device}_failure_cause. The branch carries the risk classification of the cause,
+// and the actions that it takes on top of the ones shared by all branches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureBranch {
+    pub failure_mode: FailureMode,
+    pub actions: Vec<String>,
+}
+
+impl FailureBranch {
+    pub fn new(failure_mode: FailureMode, actions: Vec<&str>) -> FailureBranch {
+        FailureBranch {
+            failure_mode,
+            actions: actions.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    pub fn cause(&self) -> &str {
+        &self.failure_mode.cause
+    }
+}
+
+// Builds the fail transitions of an operation and the failure modes for its FMEA entry.
+//
+// The GENERIC_FAILURE branch is required, its transition fail_{operation} is taken for
+// every cause that doesn't have a transition of its own. A branch with actions gets the
+// transition fail_{operation}_on_{cause}, a branch without actions only adds its failure
+// mode and fails generically. All transitions reset the request of the device and take
+// the shared actions, so exactly one of them is enabled when the request failed.
+pub fn failure_branches(
+    operation: &str,
+    device: &str,
+    shared_actions: Vec<&str>,
+    branches: Vec<FailureBranch>,
+    state: &State,
+) -> (Vec<Transition>, Vec<FailureMode>) {
+    let mut causes = vec![];
+    for branch in &branches {
+        if causes.contains(&branch.cause()) {
+            panic!("Operation '{}' has two failure branches for '{}'.", operation, branch.cause())
+        }
+        causes.push(branch.cause());
+    }
+    let generic = match branches.iter().find(|b| b.cause() == GENERIC_FAILURE) {
+        Some(generic) => generic,
+        None => panic!("Operation '{}' has no '{}' branch.", operation, GENERIC_FAILURE),
+    };
+
+    let failed = format!("var:{device}_request_state == failed");
+    let actions = |branch: &FailureBranch| -> Vec<String> {
+        let mut actions = vec![
+            format!("var:{device}_request_trigger <- false"),
+            format!("var:{device}_request_state <- initial"),
+        ];
+        actions.extend(shared_actions.iter().map(|a| a.to_string()));
+        actions.extend(branch.actions.iter().cloned());
+        actions
+    };
+
+    let specific: Vec<&FailureBranch> = branches
+        .iter()
+        .filter(|b| b.cause() != GENERIC_FAILURE && !b.actions.is_empty())
+        .collect();
+
+    let mut generic_guard = failed.clone();
+    for branch in &specific {
+        generic_guard = format!("{generic_guard} && var:{device}_failure_cause != {}", branch.cause());
+    }
+    let generic_actions = actions(generic);
+    let mut transitions = vec![Transition::parse(
+        &format!("fail_{operation}"),
+        "true",
+        &generic_guard,
+        generic_actions.iter().map(|a| a.as_str()).collect(),
+        Vec::<&str>::new(),
+        state,
+    )];
+
+    for branch in specific {
+        let branch_actions = actions(branch);
+        transitions.push(Transition::parse(
+            &format!("fail_{operation}_on_{}", branch.cause()),
+            "true",
+            &format!("{failed} && var:{device}_failure_cause == {}", branch.cause()),
+            branch_actions.iter().map(|a| a.as_str()).collect(),
+            Vec::<&str>::new(),
+            state,
+        ));
+    }
+
+    let failure_modes = branches.into_iter().map(|b| b.failure_mode).collect();
+    (transitions, failure_modes)
+}
+
+#[test]
+fn test_failure_branches() {
+    let state = crate::models::bt_test_endre::state::state();
+    let (transitions, failure_modes) = failure_branches(
+        "op_gantry_move_to_home",
+        "gantry",
+        vec!["var:gantry_position_estimated <- UNKNOWN"],
+        vec![
+            FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 2, "Not at home.", "Retry."), vec![]),
+            FailureBranch::new(FailureMode::new("violation", 6, 3, 3, "Protective stop.", "Reset."), vec![]),
+            FailureBranch::new(
+                FailureMode::new("collision", 9, 2, 3, "Collided.", "Inspect."),
+                vec!["var:gantry_calibrated_estimated <- false"],
+            ),
+        ],
+        &state,
+    );
+    assert_eq!(failure_modes.len(), 3);
+    let names: Vec<String> = transitions.iter().map(|t| t.name.clone()).collect();
+    assert_eq!(
+        names,
+        vec!["fail_op_gantry_move_to_home", "fail_op_gantry_move_to_home_on_collision"]
+    );
+}
@@ -1,326 +1,230 @@
 use micro_sp::*;
-// use crate::*;
+use crate::*;
 
-pub fn minimal_model(name: &str, state: &State) -> (Model, State) {
+// A gantry that can be locked, unlocked, calibrated and moved between a, b, c and d.
+// Small enough to try out the runner, the emulators and the reports. The request
+// timeout of every operation is its deadline, see start_request.
+
+pub fn minimal_model(name: &str, state: &State) -> (Model, State, FmeaTable) {
     let state = state.clone();
-    let mut operations = vec![];
-    let mut auto_operations = vec!();
     let mut auto_transitions = vec![];
+    let auto_operations = vec![];
+    let mut operations = vec![];
+    let mut fmea = FmeaTable::new(name);
 
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_lock",
+        "gantry",
+        vec!["var:gantry_locked_estimated <- UNKNOWN"],
+        vec![FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 2, "Gantry lock state unknown.", "Retry."), vec![])],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_lock",
+        "gantry",
+        3000,
+        "var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec!["var:gantry_command_command <- lock"],
+        &state,
+    );
     operations.push(Operation::new(
-        // name
         "op_gantry_lock",
-        // deadline
-        None,
+        deadline,
         Some(3),
-        // precondition
-        t!(
-            // name
-            "start_op_gantry_lock",
-            // planner guard
-            "var:gantry_request_state == initial \
-            && var:gantry_request_trigger == false",
-            // runner guard
-            "true",
-            // planner actions
-            vec!(
-                format!("var:gantry_command_command <- lock").as_str(),
-                "var:gantry_request_trigger <- true"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        // postcondition
-        t!(
-            // name
+        vec![start],
+        Vec::from([Transition::parse(
             "complete_op_gantry_lock",
-            // planner guard
             "true",
-            // runner guard
             "var:gantry_request_state == succeeded",
-            // planner actions
-            vec!(
+            vec![
                 "var:gantry_request_trigger <- false",
                 "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- true"
-            ),
-            //runner actions
+                "var:gantry_locked_estimated <- true",
+            ],
             Vec::<&str>::new(),
-            &state
-        ),
-        // reset transition
-        t!(
-            // name
-            "fail_op_gantry_lock",
-            // planner guard
-            "true",
-            // runner guard
-            "var:gantry_request_state == failed",
-            // planner actions
-            vec!(
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- UNKNOWN"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        Transition::empty(),
+            &state,
+        )]),
+        fail_transitions,
+        Vec::from([]),
+        Vec::from([])
     ));
 
+    fmea.add("op_gantry_lock", failure_modes);
+
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_unlock",
+        "gantry",
+        vec!["var:gantry_locked_estimated <- UNKNOWN"],
+        vec![FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 3, 3, 2, "Gantry lock state unknown.", "Retry."), vec![])],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_unlock",
+        "gantry",
+        3000,
+        "var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec!["var:gantry_command_command <- unlock"],
+        &state,
+    );
     operations.push(Operation::new(
-        // name
         "op_gantry_unlock",
-        // deadline
-        None,
+        deadline,
         Some(3),
-        // precondition
-        t!(
-            // name
-            "start_op_gantry_unlock",
-            // planner guard
-            "var:gantry_request_state == initial \
-            && var:gantry_request_trigger == false",
-            // runner guard
-            "true",
-            // planner actions
-            vec!(
-                format!("var:gantry_command_command <- unlock").as_str(),
-                "var:gantry_request_trigger <- true"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        // postcondition
-        t!(
-            // name
+        vec![start],
+        Vec::from([Transition::parse(
             "complete_op_gantry_unlock",
-            // planner guard
             "true",
-            // runner guard
             "var:gantry_request_state == succeeded",
-            // planner actions
-            vec!(
+            vec![
                 "var:gantry_request_trigger <- false",
                 "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- false"
-            ),
-            //runner actions
+                "var:gantry_locked_estimated <- false",
+            ],
             Vec::<&str>::new(),
-            &state
-        ),
-        // reset transition
-        t!(
-            // name
-            "fail_op_gantry_unlock",
-            // planner guard
-            "true",
-            // runner guard
-            "var:gantry_request_state == failed",
-            // planner actions
-            vec!(
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_locked_estimated <- UNKNOWN"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        Transition::empty(),
+            &state,
+        )]),
+        fail_transitions,
+        Vec::from([]),
+        Vec::from([])
     ));
 
+    fmea.add("op_gantry_unlock", failure_modes);
+
+    let (fail_transitions, failure_modes) = failure_branches(
+        "op_gantry_calibrate",
+        "gantry",
+        vec!["var:gantry_calibrated_estimated <- UNKNOWN"],
+        vec![FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 3, "Gantry is not calibrated.", "Retry."), vec![])],
+        &state,
+    );
+    let (deadline, start) = start_request(
+        "op_gantry_calibrate",
+        "gantry",
+        10000,
+        "var:gantry_request_state == initial \
+            && var:gantry_request_trigger == false",
+        vec!["var:gantry_command_command <- calibrate"],
+        &state,
+    );
     operations.push(Operation::new(
-        // name
         "op_gantry_calibrate",
-        // deadline
-        None,
+        deadline,
         Some(3),
-        // precondition
-        t!(
-            // name
-            "start_op_gantry_calibrate",
-            // planner guard
-            "var:gantry_request_state == initial \
-            && var:gantry_request_trigger == false",
-            // runner guard
-            "true",
-            // planner actions
-            vec!(
-                format!("var:gantry_command_command <- calibrate").as_str(),
-                "var:gantry_request_trigger <- true"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        // postcondition
-        t!(
-            // name
+        vec![start],
+        Vec::from([Transition::parse(
             "complete_op_gantry_calibrate",
-            // planner guard
             "true",
-            // runner guard
             "var:gantry_request_state == succeeded",
-            // planner actions
-            vec!(
-                "var:gantry_request_trigger <- false",
-                "var:gantry_request_state <- initial",
-                "var:gantry_calibrated_estimated <- true"
-            ),
-            //runner actions
-            Vec::<&str>::new(),
-            &state
-        ),
-        // reset transition
-        t!(
-            // name
-            "fail_op_gantry_calibrate",
-            // planner guard
-            "true",
-            // runner guard
-            "var:gantry_request_state == failed",
-            // planner actions
-            vec!(
+            vec![
                 "var:gantry_request_trigger <- false",
                 "var:gantry_request_state <- initial",
-                "var:gantry_calibrated_estimated <- UNKNOWN"
-            ),
-            //runner actions
+                "var:gantry_calibrated_estimated <- true",
+            ],
             Vec::<&str>::new(),
-            &state
-        ),
-        Transition::empty(),
+            &state,
+        )]),
+        fail_transitions,
+        Vec::from([]),
+        Vec::from([])
     ));
 
+    fmea.add("op_gantry_calibrate", failure_modes);
+
     for pos in vec!["a", "b", "c", "d"] {
-        operations.push(Operation::new(
-            // name
+        let (fail_transitions, failure_modes) = failure_branches(
             &format!("op_gantry_move_to_{}", pos),
-            // deadline
-            None,
-            Some(3),
-            // precondition
-            t!(
-                // name
-                &format!("start_op_gantry_move_to_{}", pos).as_str(),
-                // planner guard
-                "var:gantry_request_state == initial \
+            "gantry",
+            vec!["var:gantry_position_estimated <- UNKNOWN"],
+            vec![
+                FailureBranch::new(FailureMode::new(GENERIC_FAILURE, 4, 3, 2, "Gantry position unknown.", "Retry."), vec![]),
+                FailureBranch::new(
+                    FailureMode::new("collision", 9, 2, 3, "Gantry collided while moving.", "Stop and inspect."),
+                    vec!["var:gantry_calibrated_estimated <- false"],
+                ),
+            ],
+            &state,
+        );
+        let (deadline, start) = start_request(
+            &format!("op_gantry_move_to_{}", pos),
+            "gantry",
+            5000,
+            "var:gantry_request_state == initial \
                 && var:gantry_request_trigger == false \
                 && var:gantry_locked_estimated == false \
                 && var:gantry_calibrated_estimated == true",
-                // runner guard
-                "true",
-                // planner actions
-                vec!(
-                    format!("var:gantry_command_command <- move").as_str(),
-                    format!("var:gantry_position_command <- {pos}").as_str(),
-                    format!("var:gantry_speed_command <- 0.5").as_str(),
-                    "var:gantry_request_trigger <- true"
-                ),
-                //runner actions
-                Vec::<&str>::new(),
-                &state
-            ),
-            // postcondition
-            t!(
-                // name
-                &format!("complete_op_gantry_move_to_{}", pos).as_str(),
-                // planner guard
+            vec![
+                "var:gantry_command_command <- move",
+                &format!("var:gantry_position_command <- {pos}"),
+                "var:gantry_speed_command <- 0.5",
+            ],
+            &state,
+        );
+        operations.push(Operation::new(
+            &format!("op_gantry_move_to_{}", pos),
+            deadline,
+            Some(3),
+            vec![start],
+            Vec::from([Transition::parse(
+                &format!("complete_op_gantry_move_to_{}", pos),
                 "true",
-                // runner guard
-                &format!("var:gantry_request_state == succeeded").as_str(),
-                // planner actions
-                vec!(
+                "var:gantry_request_state == succeeded",
+                vec![
                     "var:gantry_request_trigger <- false",
                     "var:gantry_request_state <- initial",
-                    &format!("var:gantry_position_estimated <- {pos}")
-                ),
-                //runner actions
+                    &format!("var:gantry_position_estimated <- {pos}"),
+                ],
                 Vec::<&str>::new(),
-                &state
-            ),
-            // reset transition
-            t!(
-                // name
-                &format!("fail_op_gantry_move_to_{}", pos).as_str(),
-                // planner guard
-                "true",
-                // runner guard
-                "var:gantry_request_state == failed",
-                // planner actions
-                vec!(
-                    "var:gantry_request_trigger <- false",
-                    "var:gantry_request_state <- initial",
-                    "var:gantry_position_estimated <- UNKNOWN"
-                ),
-                //runner actions
-                Vec::<&str>::new(),
-                &state
-            ),
-            Transition::empty(),
+                &state,
+            )]),
+            fail_transitions,
+            Vec::from([]),
+            Vec::from([])
         ));
+
+        fmea.add(&format!("op_gantry_move_to_{}", pos), failure_modes);
     }
 
-    auto_transitions.push(t!(
-        // name
+    auto_transitions.push(Transition::parse(
         "replan_if_plan_has_failed",
-        // planner guard
-        &format!("var:{}_replan_fail_counter == 1", name).as_str(),
-        // ruuner guard = none
+        &format!("var:{}_replan_fail_counter == 1", name),
         "true",
-        // planner actions
         Vec::<&str>::new(),
-        // runner actions - none
-        vec!(
+        vec![
             "var:gantry_request_state <- initial",
             "var:gantry_request_trigger <- false",
-            "var:minimal_model_plan <- UNKNOWN",
-            "var:minimal_model_plan_current_step <- UNKNOWN",
-            "var:minimal_model_replan_trigger <- true" // "var:runner_replan_trigger <- true"
-        ),
-        &state
+            &format!("var:{}_plan <- UNKNOWN", name),
+            &format!("var:{}_plan_current_step <- UNKNOWN", name),
+            &format!("var:{}_replan_trigger <- true", name),
+        ],
+        &state,
     ));
 
     let model = Model::new(name, auto_transitions, auto_operations, operations);
 
-    (model, state)
+    (model, state, fmea)
 }
 
 #[test]
 fn test_model() {
     let state = crate::models::minimal::state::state();
+    let runner_vars = generate_runner_state_variables("minimal");
+    let state = state.extend(runner_vars, true);
 
-    for s in &state.state {
-        println!("{:?}", s.1);
-    }
-
-    let (model, state) = minimal_model("minimal_model", &state);
+    let (model, state, fmea) = minimal_model("minimal", &state);
 
-    println!("+++++++++++++++++++++++");
+    let (missing, unknown) = fmea.check_coverage(&model);
+    assert!(missing.is_empty(), "Operations without risk annotations: {:?}", missing);
+    assert!(unknown.is_empty(), "Risk annotations for unknown operations: {:?}", unknown);
 
-    for s in &state.state {
-        println!("{:?}", s.1);
-    }
-
-    let goal = state.get_value(&format!("{}_goal", model.name));
-    let val = state.get_value("gantry_position_estimated");
-    println!("Current goal: {:?}", goal);
-    println!("Current value: {:?}", val);
+    let op_vars = generate_operation_state_variables(&model, false);
+    let state = state.extend(op_vars, true);
 
     let state = state.update(
         &format!("{}_goal", model.name),
         "var:gantry_position_estimated == b".to_spvalue(),
     );
-    let goal = state.get_value(&format!("{}_goal", model.name));
-    let val = state.get_value("gantry_position_estimated");
-    println!("Current goal: {:?}", goal);
-    println!("Current value: {:?}", val);
-
-    // let extracted_goal = extract_goal_from_state(name, state)
 
     let plan = bfs_operation_planner(
         state.clone(),
@@ -329,10 +233,6 @@ fn test_model() {
         30,
     );
 
-    let val = state.get_value("gantry_position_estimated");
-    println!("Current goal: {:?}", goal);
-    println!("Current value: {:?}", val);
-
     println!("{:?}", plan);
 
     assert!(plan.found);
@@ -1,2 +1,5 @@
-// pub mod minimal;
-pub mod bt_test_endre;
\ No newline at end of file
+pub mod minimal;
+pub mod bt_test_endre;
+pub mod common;
+pub mod failure_branches;
+pub mod registry;
@@ -0,0 +1,65 @@
+use crate::*;
+use micro_sp::*;
+
+// A model that the runner can be started with, see RunnerConfig
+#[derive(Debug, Clone)]
+pub struct ModelEntry {
+    pub name: &'static str,
+    pub state: fn() -> State,
+    pub model: fn(&str, &State) -> (Model, State, FmeaTable),
+    // Devices the model commands, their emulators and client tickers are spawned
+    pub devices: &'static [&'static str],
+    // Goal of the single test run
+    pub test_goal: &'static str,
+}
+
+impl ModelEntry {
+    pub fn devices(&self) -> Vec<String> {
+        self.devices.iter().map(|device| device.to_string()).collect()
+    }
+}
+
+pub fn model_registry() -> Vec<ModelEntry> {
+    vec![
+        ModelEntry {
+            name: "minimal",
+            state: crate::models::minimal::state::state,
+            model: crate::models::minimal::model::minimal_model,
+            devices: &["gantry"],
+            test_goal: "var:gantry_position_estimated == b",
+        },
+        ModelEntry {
+            name: "bt_test_endre",
+            state: crate::models::bt_test_endre::state::state,
+            model: crate::models::bt_test_endre::model::bt_test_endre,
+            devices: &["gantry", "robot", "scanner", "camera_system"],
+            test_goal: "var:robot_mounted_estimated == suction_tool",
+        },
+    ]
+}
+
+pub fn get_model_entry(name: &str) -> Result<ModelEntry, String> {
+    let registry = model_registry();
+    match registry.iter().find(|entry| entry.name == name) {
+        Some(entry) => Ok(entry.clone()),
+        None => Err(format!(
+            "Unknown model '{}', expected one of: {}.",
+            name,
+            registry.iter().map(|entry| entry.name).collect::<Vec<&str>>().join(", ")
+        )),
+    }
+}
+
+#[test]
+fn test_model_registry() {
+    for entry in model_registry() {
+        let state = (entry.state)();
+        let state = state.extend(generate_runner_state_variables(entry.name), true);
+        let (model, _state, fmea) = (entry.model)(entry.name, &state);
+        assert_eq!(model.name, entry.name);
+        let (missing, _) = fmea.check_coverage(&model);
+        assert!(missing.is_empty(), "{}: operations without risk annotations: {:?}", entry.name, missing);
+    }
+    assert!(get_model_entry("minimal").is_ok());
+    assert!(get_model_entry("no_such_model").is_err());
+}
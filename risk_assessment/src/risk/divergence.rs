@@ -0,0 +1,199 @@
+use crate::*;
+use micro_sp::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Duration};
+
+pub enum DivergenceCommand {
+    GetLog(oneshot::Sender<DivergenceLog>),
+}
+
+// How often an estimated variable was unknown or different from the ground truth
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DivergenceCount {
+    pub samples: u64,
+    pub unknown: u64,
+    pub diverged: u64,
+}
+
+impl DivergenceCount {
+    // Unknown estimates are not counted, the runner knows that it doesn't know
+    pub fn divergence_rate(&self) -> f64 {
+        match self.samples - self.unknown {
+            0 => 0.0,
+            known => self.diverged as f64 / known as f64,
+        }
+    }
+}
+
+// How many silent failures of a command were later revealed by a check
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DetectionCount {
+    pub silent_failures: u64,
+    pub detected: u64,
+}
+
+impl DetectionCount {
+    // Measured FMEA detection score, 1 if all silent failures were detected, 10 if none were
+    pub fn detection_score(&self) -> u8 {
+        match self.silent_failures {
+            0 => MIN_SCORE,
+            n => {
+                let missed = 1.0 - self.detected as f64 / n as f64;
+                MIN_SCORE + (missed * (MAX_SCORE - MIN_SCORE) as f64).round() as u8
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DivergenceLog {
+    pub name: String,
+    pub variables: HashMap<String, DivergenceCount>,
+    // Keyed by "{device}_{command}"
+    pub detections: HashMap<String, DetectionCount>,
+    pub seed: Option<u64>,
+}
+
+impl DivergenceLog {
+    pub fn new(name: &str) -> DivergenceLog {
+        DivergenceLog {
+            name: name.to_string(),
+            variables: HashMap::new(),
+            detections: HashMap::new(),
+            seed: None,
+        }
+    }
+
+    pub fn record_silent_failures(&mut self, silent_failures: &[SilentFailure]) {
+        self.detections.clear();
+        for sf in silent_failures {
+            let count = self
+                .detections
+                .entry(format!("{}_{}", sf.device, sf.command))
+                .or_default();
+            count.silent_failures += 1;
+            if sf.detected {
+                count.detected += 1;
+            }
+        }
+    }
+
+    pub fn record(&mut self, variable: &str, estimated: &SPValue, truth: &SPValue) {
+        let count = self.variables.entry(variable.to_string()).or_default();
+        count.samples += 1;
+        if *estimated == SPValue::UNKNOWN {
+            count.unknown += 1;
+        } else if estimated != truth {
+            count.diverged += 1;
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut variables: Vec<(&String, &DivergenceCount)> = self.variables.iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        let mut detections: Vec<(&String, &DetectionCount)> = self.detections.iter().collect();
+        detections.sort_by(|a, b| a.0.cmp(b.0));
+        json!({
+            "name": self.name,
+            "seed": self.seed,
+            "variables": variables
+                .iter()
+                .map(|(variable, count)| json!({
+                    "variable": variable,
+                    "samples": count.samples,
+                    "unknown": count.unknown,
+                    "diverged": count.diverged,
+                    "divergence_rate": count.divergence_rate(),
+                }))
+                .collect::<Vec<serde_json::Value>>(),
+            "detections": detections
+                .iter()
+                .map(|(command, count)| json!({
+                    "command": command,
+                    "silent_failures": count.silent_failures,
+                    "detected": count.detected,
+                    "detection_score": count.detection_score(),
+                }))
+                .collect::<Vec<serde_json::Value>>(),
+        })
+    }
+
+    pub fn write(&self, directory: &str) -> Result<String, Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let path = format!("{}/{}_divergence.json", directory, self.name);
+        std::fs::write(&path, serde_json::to_string_pretty(&self.to_json())?)?;
+        Ok(path)
+    }
+}
+
+// Samples the state and compares the estimated variables with the ground truth of the emulators
+pub async fn divergence_monitor(
+    name: &str,
+    world: Arc<Mutex<World>>,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    mut divergence_receiver: mpsc::Receiver<DivergenceCommand>,
+) -> Result<(), Box<dyn Error>> {
+    let target = "divergence_monitor";
+    let mut log = DivergenceLog::new(name);
+    let mut interval = interval(Duration::from_millis(ticker_rate));
+
+    log_info!(target, "Spawned.");
+
+    loop {
+        tokio::select! {
+            command = divergence_receiver.recv() => match command {
+                Some(DivergenceCommand::GetLog(response_tx)) => {
+                    let _ = response_tx.send(log.clone());
+                }
+                None => return Ok(()),
+            },
+            _ = interval.tick() => {
+                let (response_tx, response_rx) = oneshot::channel();
+                command_sender.send(Command::GetState(response_tx)).await?;
+                let state = response_rx.await?;
+
+                let (truth, silent_failures) = {
+                    let world = world.lock().unwrap();
+                    (world.ground_truth(), world.silent_failures.clone())
+                };
+                log.record_silent_failures(&silent_failures);
+                for (variable, value) in truth {
+                    if state.state.contains_key(&variable) {
+                        log.record(&variable, &state.get_value(&variable), &value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_divergence_log() {
+    let mut log = DivergenceLog::new("test");
+    log.record("gantry_locked_estimated", &SPValue::UNKNOWN, &true.to_spvalue());
+    log.record("gantry_locked_estimated", &true.to_spvalue(), &true.to_spvalue());
+    log.record("gantry_locked_estimated", &false.to_spvalue(), &true.to_spvalue());
+
+    let count = log.variables.get("gantry_locked_estimated").unwrap();
+    assert_eq!(count.samples, 3);
+    assert_eq!(count.unknown, 1);
+    assert_eq!(count.diverged, 1);
+    assert_eq!(count.divergence_rate(), 0.5);
+
+    let mut world = World::new(0);
+    world.robot_mounted = "none".to_string();
+    world.robot_position = "gripper_tool_rack".to_string();
+    world.silent_failure("robot", "mount", "");
+    world.silent_failure("robot", "mount", "");
+    world.robot_command("check_mounted_tool", "").unwrap();
+    world.silent_failure("robot", "mount", "");
+    log.record_silent_failures(&world.silent_failures);
+    let count = log.detections.get("robot_mount").unwrap();
+    assert_eq!((count.silent_failures, count.detected), (3, 2));
+    assert_eq!(count.detection_score(), 4);
+}
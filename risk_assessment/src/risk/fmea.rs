@@ -0,0 +1,185 @@
+use micro_sp::*;
+
+// FMEA scores are given on a 1..10 scale, where 10 is the worst:
+// severity: how bad the consequence is if the failure happens
+// occurrence: how likely the failure is to happen
+// detection: how unlikely it is that the failure is detected (10 = undetectable)
+pub static MIN_SCORE: u8 = 1;
+pub static MAX_SCORE: u8 = 10;
+
+// The cause that the emulators return when no specific cause is emulated
+pub static GENERIC_FAILURE: &'static str = "generic_failure";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailureMode {
+    pub cause: String,
+    pub severity: u8,
+    pub occurrence: u8,
+    pub detection: u8,
+    pub effect: String,
+    pub mitigation: String,
+}
+
+impl FailureMode {
+    pub fn new(
+        cause: &str,
+        severity: u8,
+        occurrence: u8,
+        detection: u8,
+        effect: &str,
+        mitigation: &str,
+    ) -> FailureMode {
+        match FailureMode::try_new(cause, severity, occurrence, detection, effect, mitigation) {
+            Ok(failure_mode) => failure_mode,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Like new, but returns an error for scores outside of MIN_SCORE..MAX_SCORE
+    pub fn try_new(
+        cause: &str,
+        severity: u8,
+        occurrence: u8,
+        detection: u8,
+        effect: &str,
+        mitigation: &str,
+    ) -> Result<FailureMode, String> {
+        for (score, value) in [
+            ("severity", severity),
+            ("occurrence", occurrence),
+            ("detection", detection),
+        ] {
+            if !(MIN_SCORE..=MAX_SCORE).contains(&value) {
+                return Err(format!(
+                    "Failure mode '{}' has {} {} outside of {}..{}.",
+                    cause, score, value, MIN_SCORE, MAX_SCORE
+                ));
+            }
+        }
+        Ok(FailureMode {
+            cause: cause.to_string(),
+            severity,
+            occurrence,
+            detection,
+            effect: effect.to_string(),
+            mitigation: mitigation.to_string(),
+        })
+    }
+
+    // Risk Priority Number, ranges from 1 to 1000
+    pub fn rpn(&self) -> u64 {
+        self.severity as u64 * self.occurrence as u64 * self.detection as u64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationRisk {
+    pub operation: String,
+    pub failure_modes: Vec<FailureMode>,
+}
+
+impl OperationRisk {
+    pub fn new(operation: &str, failure_modes: Vec<FailureMode>) -> OperationRisk {
+        OperationRisk {
+            operation: operation.to_string(),
+            failure_modes,
+        }
+    }
+
+    // If the cause is not explicitly listed, fall back to the generic failure mode
+    pub fn get_failure_mode(&self, cause: &str) -> Option<&FailureMode> {
+        self.failure_modes
+            .iter()
+            .find(|fm| fm.cause == cause)
+            .or(self
+                .failure_modes
+                .iter()
+                .find(|fm| fm.cause == GENERIC_FAILURE))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmeaTable {
+    pub name: String,
+    pub operations: Vec<OperationRisk>,
+}
+
+impl FmeaTable {
+    pub fn new(name: &str) -> FmeaTable {
+        FmeaTable {
+            name: name.to_string(),
+            operations: vec![],
+        }
+    }
+
+    pub fn add(&mut self, operation: &str, failure_modes: Vec<FailureMode>) {
+        match self
+            .operations
+            .iter_mut()
+            .find(|op| op.operation == operation)
+        {
+            Some(op) => op.failure_modes.extend(failure_modes),
+            None => self
+                .operations
+                .push(OperationRisk::new(operation, failure_modes)),
+        }
+    }
+
+    pub fn get_operation(&self, operation: &str) -> Option<&OperationRisk> {
+        self.operations.iter().find(|op| op.operation == operation)
+    }
+
+    pub fn get_failure_mode(&self, operation: &str, cause: &str) -> Option<&FailureMode> {
+        self.get_operation(operation)
+            .and_then(|op| op.get_failure_mode(cause))
+    }
+
+    // Returns the names of the model operations that have no risk annotation,
+    // and the annotated operations that don't exist in the model
+    pub fn check_coverage(&self, model: &Model) -> (Vec<String>, Vec<String>) {
+        let missing = model
+            .operations
+            .iter()
+            .filter(|o| self.get_operation(&o.name).is_none())
+            .map(|o| o.name.clone())
+            .collect();
+        let unknown = self
+            .operations
+            .iter()
+            .filter(|op| !model.operations.iter().any(|o| o.name == op.operation))
+            .map(|op| op.operation.clone())
+            .collect();
+        (missing, unknown)
+    }
+}
+
+#[test]
+fn test_failure_mode_lookup() {
+    let mut fmea = FmeaTable::new("test");
+    fmea.add(
+        "op_gantry_lock",
+        vec![
+            FailureMode::new(GENERIC_FAILURE, 3, 2, 2, "gantry not locked", "retry"),
+            FailureMode::new("violation", 7, 2, 3, "protective stop", "reset the gantry"),
+        ],
+    );
+
+    let fm = fmea.get_failure_mode("op_gantry_lock", "violation").unwrap();
+    assert_eq!(fm.severity, 7);
+    assert_eq!(fm.rpn(), 42);
+    let fm = fmea.get_failure_mode("op_gantry_lock", "collision").unwrap();
+    assert_eq!(fm.cause, GENERIC_FAILURE);
+    assert!(fmea.get_failure_mode("op_gantry_unlock", "violation").is_none());
+}
+
+#[test]
+#[should_panic]
+fn test_failure_mode_score_out_of_range() {
+    FailureMode::new("collision", 11, 1, 1, "", "");
+}
+
+#[test]
+fn test_failure_mode_try_new() {
+    assert!(FailureMode::try_new("collision", 0, 1, 1, "", "").is_err());
+    assert_eq!(FailureMode::try_new("collision", 9, 2, 3, "", "").unwrap().rpn(), 54);
+}
@@ -0,0 +1,5 @@
+pub mod divergence;
+pub mod fmea;
+pub mod recorder;
+pub mod report;
+pub mod stpa;
\ No newline at end of file
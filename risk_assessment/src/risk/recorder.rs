@@ -0,0 +1,111 @@
+use crate::*;
+use micro_sp::*;
+use std::collections::HashMap;
+use std::error::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval, Duration};
+
+pub enum RecorderCommand {
+    GetLog(oneshot::Sender<RiskLog>),
+}
+
+pub async fn get_log(recorder_sender: &mpsc::Sender<RecorderCommand>) -> Result<RiskLog, Box<dyn Error>> {
+    let (response_tx, response_rx) = oneshot::channel();
+    recorder_sender.send(RecorderCommand::GetLog(response_tx)).await?;
+    Ok(response_rx.await?)
+}
+
+// Follows the state and logs which operations were started, and which
+// failures were reported by the devices while these operations were executing.
+// Operations are mapped to the device whose request trigger their start transition sets,
+// and to the emulation key of the command they issue.
+// Failures are logged with the causes counted in {device}_fail_counter_{cause}.
+pub async fn risk_recorder(
+    model: &Model,
+    devices: Vec<String>,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    mut recorder_receiver: mpsc::Receiver<RecorderCommand>,
+) -> Result<(), Box<dyn Error>> {
+    let target = "risk_recorder";
+    let mut log = RiskLog::new(&model.name);
+    let mut interval = interval(Duration::from_millis(ticker_rate));
+    let mut operation_states: HashMap<String, OperationState> = HashMap::new();
+    let mut executing_on_device: HashMap<String, String> = HashMap::new();
+    let mut fail_counters: HashMap<String, i64> = HashMap::new();
+
+    log_info!(target, "Spawned.");
+
+    let (response_tx, response_rx) = oneshot::channel();
+    command_sender.send(Command::GetState(response_tx)).await?;
+    let emulation_keys = operation_emulation_keys(model, &devices, &response_rx.await?);
+    for (operation, key) in &emulation_keys {
+        log.emulation_keys.insert(operation.clone(), key.to_string());
+    }
+
+    loop {
+        tokio::select! {
+            command = recorder_receiver.recv() => match command {
+                Some(RecorderCommand::GetLog(response_tx)) => {
+                    let _ = response_tx.send(log.clone());
+                }
+                None => return Ok(()),
+            },
+            _ = interval.tick() => {
+                let (response_tx, response_rx) = oneshot::channel();
+                command_sender.send(Command::GetState(response_tx)).await?;
+                let state = response_rx.await?;
+
+                for o in &model.operations {
+                    let operation_state =
+                        OperationState::from_str(&state.get_or_default_string(target, &o.name));
+                    let previous = operation_states
+                        .insert(o.name.clone(), operation_state.clone())
+                        .unwrap_or(OperationState::UNKNOWN);
+                    if operation_state == OperationState::Executing
+                        && previous != OperationState::Executing
+                    {
+                        log.record_execution(&o.name);
+                        if let Some(key) = emulation_keys.get(&o.name) {
+                            executing_on_device.insert(key.device.clone(), o.name.clone());
+                        }
+                    }
+                }
+
+                for device in &devices {
+                    // Failures per cause, several can happen between two samples
+                    let mut causes = vec![];
+                    let cause_prefix = format!("{}_fail_counter_", device);
+                    for variable in state.state.keys().filter(|v| v.starts_with(&cause_prefix)) {
+                        let counter = state.get_or_default_i64(target, variable);
+                        let previous = *fail_counters.get(variable).unwrap_or(&counter);
+                        fail_counters.insert(variable.clone(), counter);
+                        for _ in previous..counter {
+                            causes.push(variable[cause_prefix.len()..].to_string());
+                        }
+                    }
+
+                    let counter = state
+                        .get_or_default_i64(target, &format!("{}_total_fail_counter", device));
+                    let previous = *fail_counters.get(device).unwrap_or(&counter);
+                    fail_counters.insert(device.clone(), counter);
+                    if counter > previous {
+                        // Causes without a counter are taken from the last failure cause
+                        let cause = state
+                            .get_or_default_string(target, &format!("{}_failure_cause", device));
+                        while (causes.len() as i64) < counter - previous {
+                            causes.push(cause.clone());
+                        }
+                        let operation = executing_on_device
+                            .get(device)
+                            .cloned()
+                            .unwrap_or(format!("unknown_{}_operation", device));
+                        for cause in &causes {
+                            log.record_failure(&operation, cause);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
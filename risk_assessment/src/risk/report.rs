@@ -0,0 +1,347 @@
+use crate::*;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+
+// What was observed during a test run
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RiskLog {
+    pub name: String,
+    pub executions: HashMap<String, u64>,
+    pub failures: HashMap<(String, String), u64>,
+    // Operation -> emulation key of the command it issues, see EmulationKey
+    pub emulation_keys: HashMap<String, String>,
+    // Emulation seed of the run, replays it when it is set as EMULATION_SEED
+    pub seed: Option<u64>,
+}
+
+impl RiskLog {
+    pub fn new(name: &str) -> RiskLog {
+        RiskLog {
+            name: name.to_string(),
+            executions: HashMap::new(),
+            failures: HashMap::new(),
+            emulation_keys: HashMap::new(),
+            seed: None,
+        }
+    }
+
+    pub fn record_execution(&mut self, operation: &str) {
+        *self.executions.entry(operation.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn record_failure(&mut self, operation: &str, cause: &str) {
+        let cause = if cause.is_empty() { GENERIC_FAILURE } else { cause };
+        *self
+            .failures
+            .entry((operation.to_string(), cause.to_string()))
+            .or_insert(0) += 1;
+    }
+
+    pub fn get_executions(&self, operation: &str) -> u64 {
+        *self.executions.get(operation).unwrap_or(&0)
+    }
+
+    pub fn get_failures(&self, operation: &str, cause: &str) -> u64 {
+        *self
+            .failures
+            .get(&(operation.to_string(), cause.to_string()))
+            .unwrap_or(&0)
+    }
+
+    // What was observed after the earlier log was taken, i.e. during one goal of a run
+    pub fn since(&self, earlier: &RiskLog) -> RiskLog {
+        let mut log = self.clone();
+        for (operation, count) in log.executions.iter_mut() {
+            *count -= earlier.get_executions(operation).min(*count);
+        }
+        for ((operation, cause), count) in log.failures.iter_mut() {
+            *count -= earlier.get_failures(operation, cause).min(*count);
+        }
+        log.executions.retain(|_, count| *count > 0);
+        log.failures.retain(|_, count| *count > 0);
+        log
+    }
+}
+
+// One line in the FMEA worksheet. Failures that were observed with a cause
+// that is not in the FMEA table are scored with the operation's generic
+// failure mode if there is one, or with the worst case scores if there isn't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmeaRow {
+    pub operation: String,
+    pub emulation_key: String,
+    pub cause: String,
+    pub severity: u8,
+    pub occurrence: u8,
+    pub detection: u8,
+    pub rpn: u64,
+    pub executions: u64,
+    pub observed_failures: u64,
+    pub effect: String,
+    pub mitigation: String,
+    pub rated: bool,
+}
+
+impl FmeaRow {
+    fn new(operation: &str, cause: &str, fm: &FailureMode, log: &RiskLog, rated: bool) -> FmeaRow {
+        FmeaRow {
+            operation: operation.to_string(),
+            emulation_key: log.emulation_keys.get(operation).cloned().unwrap_or_default(),
+            cause: cause.to_string(),
+            severity: fm.severity,
+            occurrence: fm.occurrence,
+            detection: fm.detection,
+            rpn: fm.rpn(),
+            executions: log.get_executions(operation),
+            observed_failures: log.get_failures(operation, cause),
+            effect: fm.effect.clone(),
+            mitigation: fm.mitigation.clone(),
+            rated,
+        }
+    }
+
+    pub fn observed_failure_rate(&self) -> f64 {
+        match self.executions {
+            0 => 0.0,
+            n => self.observed_failures as f64 / n as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FmeaReport {
+    pub name: String,
+    pub generated: String,
+    pub seed: Option<u64>,
+    pub rows: Vec<FmeaRow>,
+}
+
+impl FmeaReport {
+    pub fn new(fmea: &FmeaTable, log: &RiskLog) -> FmeaReport {
+        let mut rows = vec![];
+        for op in &fmea.operations {
+            for fm in &op.failure_modes {
+                rows.push(FmeaRow::new(&op.operation, &fm.cause, fm, log, true));
+            }
+        }
+
+        for (operation, cause) in log.failures.keys() {
+            if rows
+                .iter()
+                .any(|row| &row.operation == operation && &row.cause == cause)
+            {
+                continue;
+            }
+            let worst_case = FailureMode::new(
+                cause,
+                MAX_SCORE,
+                MAX_SCORE,
+                MAX_SCORE,
+                "Not rated in the FMEA table.",
+                "Rate this failure mode.",
+            );
+            let fm = fmea
+                .get_failure_mode(operation, cause)
+                .unwrap_or(&worst_case);
+            rows.push(FmeaRow::new(operation, cause, fm, log, false));
+        }
+
+        rows.sort_by(|a, b| {
+            b.rpn
+                .cmp(&a.rpn)
+                .then(b.observed_failures.cmp(&a.observed_failures))
+                .then(a.operation.cmp(&b.operation))
+                .then(a.cause.cmp(&b.cause))
+        });
+
+        FmeaReport {
+            name: fmea.name.clone(),
+            generated: chrono::Local::now()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            seed: log.seed,
+            rows,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = format!("# FMEA report: {}\n\nGenerated: {}\n\n", self.name, self.generated);
+        if let Some(seed) = self.seed {
+            md.push_str(&format!("Emulation seed: {}\n\n", seed));
+        }
+        md.push_str(
+            "| Rank | Operation | Emulation key | Failure cause | S | O | D | RPN | Executions | Observed failures | Effect | Mitigation |\n",
+        );
+        md.push_str("|---|---|---|---|---|---|---|---|---|---|---|---|\n");
+        for (rank, row) in self.rows.iter().enumerate() {
+            md.push_str(&format!(
+                "| {} | {} | {} | {}{} | {} | {} | {} | {} | {} | {} | {} | {} |\n",
+                rank + 1,
+                row.operation,
+                row.emulation_key,
+                row.cause,
+                if row.rated { "" } else { " (unrated)" },
+                row.severity,
+                row.occurrence,
+                row.detection,
+                row.rpn,
+                row.executions,
+                row.observed_failures,
+                row.effect.replace("|", "\\|"),
+                row.mitigation.replace("|", "\\|"),
+            ));
+        }
+        md
+    }
+
+    // The seed goes to a comment line, so that the rows stay plain CSV
+    pub fn to_csv(&self) -> String {
+        let mut csv = match self.seed {
+            Some(seed) => format!("# seed={}\n", seed),
+            None => String::new(),
+        };
+        csv.push_str("rank,operation,emulation_key,cause,severity,occurrence,detection,rpn,executions,observed_failures,observed_failure_rate,rated,effect,mitigation\n");
+        for (rank, row) in self.rows.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{:.3},{},{},{}\n",
+                rank + 1,
+                csv_field(&row.operation),
+                csv_field(&row.emulation_key),
+                csv_field(&row.cause),
+                row.severity,
+                row.occurrence,
+                row.detection,
+                row.rpn,
+                row.executions,
+                row.observed_failures,
+                row.observed_failure_rate(),
+                row.rated,
+                csv_field(&row.effect),
+                csv_field(&row.mitigation),
+            ));
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let rows: Vec<serde_json::Value> = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(rank, row)| {
+                json!({
+                    "rank": rank + 1,
+                    "operation": row.operation,
+                    "emulation_key": row.emulation_key,
+                    "cause": row.cause,
+                    "severity": row.severity,
+                    "occurrence": row.occurrence,
+                    "detection": row.detection,
+                    "rpn": row.rpn,
+                    "executions": row.executions,
+                    "observed_failures": row.observed_failures,
+                    "observed_failure_rate": row.observed_failure_rate(),
+                    "rated": row.rated,
+                    "effect": row.effect,
+                    "mitigation": row.mitigation,
+                })
+            })
+            .collect();
+        json!({
+            "name": self.name,
+            "generated": self.generated,
+            "seed": self.seed,
+            "rows": rows,
+        })
+    }
+
+    // Writes the markdown, csv and json worksheets, returns the written paths
+    pub fn write(&self, directory: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let stem = format!(
+            "{}/{}_fmea_{}",
+            directory,
+            self.name,
+            self.generated.replace(" ", "_").replace(":", "-")
+        );
+        let mut paths = vec![];
+        for (extension, content) in [
+            ("md", self.to_markdown()),
+            ("csv", self.to_csv()),
+            ("json", serde_json::to_string_pretty(&self.to_json())?),
+        ] {
+            let path = format!("{}.{}", stem, extension);
+            std::fs::write(&path, content)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(",") || field.contains("\"") || field.contains("\n") {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[test]
+fn test_report_ranking() {
+    let mut fmea = FmeaTable::new("test");
+    fmea.add(
+        "op_gantry_move_to_home",
+        vec![
+            FailureMode::new(GENERIC_FAILURE, 4, 3, 2, "position unknown", "retry"),
+            FailureMode::new("collision", 9, 2, 3, "gantry collided", "stop, inspect"),
+        ],
+    );
+
+    let mut log = RiskLog::new("test");
+    log.record_execution("op_gantry_move_to_home");
+    log.record_execution("op_gantry_move_to_home");
+    log.record_failure("op_gantry_move_to_home", "collision");
+    log.record_failure("op_gantry_move_to_home", "detected_drift");
+    log.record_failure("op_robot_mount_gripper_tool", "");
+
+    let report = FmeaReport::new(&fmea, &log);
+    assert_eq!(report.rows.len(), 4);
+
+    // Not in the table and no generic failure mode to fall back on, so worst case
+    assert_eq!(report.rows[0].operation, "op_robot_mount_gripper_tool");
+    assert_eq!(report.rows[0].cause, GENERIC_FAILURE);
+    assert_eq!(report.rows[0].rpn, 1000);
+
+    assert_eq!(report.rows[1].cause, "collision");
+    assert_eq!(report.rows[1].rpn, 54);
+    assert_eq!(report.rows[1].observed_failures, 1);
+    assert_eq!(report.rows[1].observed_failure_rate(), 0.5);
+
+    // Scored with the generic failure mode of the operation
+    assert_eq!(report.rows[2].cause, "detected_drift");
+    assert_eq!(report.rows[2].rpn, 24);
+    assert!(!report.rows[2].rated);
+
+    assert_eq!(report.to_csv().lines().count(), 5);
+    assert_eq!(report.to_json()["rows"].as_array().unwrap().len(), 4);
+
+    let report = FmeaReport { seed: Some(42), ..report };
+    assert!(report.to_csv().starts_with("# seed=42\nrank,"));
+}
+
+#[test]
+fn test_log_since() {
+    let mut log = RiskLog::new("test");
+    log.record_execution("op_gantry_move_to_home");
+    log.record_failure("op_gantry_move_to_home", "collision");
+    let earlier = log.clone();
+    log.record_execution("op_gantry_move_to_home");
+    log.record_execution("op_gantry_calibrate");
+
+    let since = log.since(&earlier);
+    assert_eq!(since.get_executions("op_gantry_move_to_home"), 1);
+    assert_eq!(since.get_executions("op_gantry_calibrate"), 1);
+    assert_eq!(since.get_failures("op_gantry_move_to_home", "collision"), 0);
+    assert!(since.failures.is_empty());
+}
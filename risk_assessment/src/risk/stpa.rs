@@ -0,0 +1,334 @@
+use micro_sp::*;
+
+// STPA step 1: losses and the system level hazards that can lead to them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loss {
+    pub id: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hazard {
+    pub id: String,
+    pub description: String,
+    pub losses: Vec<String>,
+}
+
+// STPA step 2: the control structure
+#[derive(Debug, Clone, PartialEq)]
+pub struct Controller {
+    pub name: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlledProcess {
+    pub name: String,
+    pub description: String,
+}
+
+// A control action is a command sent to a device, e.g. "move" in TriggerGantry.
+// The device is also the prefix of the state variables, i.e. gantry_command_command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlAction {
+    pub name: String,
+    pub controller: String,
+    pub process: String,
+    pub device: String,
+    pub command: String,
+    pub unsafe_control_actions: Vec<UnsafeControlAction>,
+}
+
+// Feedback from a controlled process, i.e. the state variables that the controller uses
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedbackChannel {
+    pub name: String,
+    pub process: String,
+    pub controller: String,
+    pub variables: Vec<String>,
+}
+
+// STPA step 3: the four ways a control action can be unsafe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UcaType {
+    NotProvided,
+    Provided,
+    WrongTimingOrOrder,
+    StoppedTooSoonOrAppliedTooLong,
+}
+
+impl UcaType {
+    pub fn all() -> Vec<UcaType> {
+        vec![
+            UcaType::NotProvided,
+            UcaType::Provided,
+            UcaType::WrongTimingOrOrder,
+            UcaType::StoppedTooSoonOrAppliedTooLong,
+        ]
+    }
+}
+
+impl std::fmt::Display for UcaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UcaType::NotProvided => write!(f, "not_provided"),
+            UcaType::Provided => write!(f, "provided"),
+            UcaType::WrongTimingOrOrder => write!(f, "wrong_timing_or_order"),
+            UcaType::StoppedTooSoonOrAppliedTooLong => write!(f, "stopped_too_soon_or_applied_too_long"),
+        }
+    }
+}
+
+// The guard is the predicate that should hold whenever one of the operations
+// is started, so that the UCA can't happen. It is only checked for the
+// Provided and WrongTimingOrOrder types, the other two are about the operation
+// not being executed or not finishing, which guards can't prevent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsafeControlAction {
+    pub id: String,
+    pub uca_type: UcaType,
+    pub description: String,
+    pub hazards: Vec<String>,
+    pub operations: Vec<String>,
+    pub guard: String,
+}
+
+impl UnsafeControlAction {
+    pub fn new(
+        id: &str,
+        uca_type: UcaType,
+        description: &str,
+        hazards: Vec<&str>,
+        operations: Vec<String>,
+        guard: &str,
+    ) -> UnsafeControlAction {
+        UnsafeControlAction {
+            id: id.to_string(),
+            uca_type,
+            description: description.to_string(),
+            hazards: hazards.iter().map(|h| h.to_string()).collect(),
+            operations,
+            guard: guard.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlStructure {
+    pub name: String,
+    pub losses: Vec<Loss>,
+    pub hazards: Vec<Hazard>,
+    pub controllers: Vec<Controller>,
+    pub processes: Vec<ControlledProcess>,
+    pub control_actions: Vec<ControlAction>,
+    pub feedback: Vec<FeedbackChannel>,
+}
+
+impl ControlStructure {
+    pub fn new(name: &str) -> ControlStructure {
+        ControlStructure {
+            name: name.to_string(),
+            losses: vec![],
+            hazards: vec![],
+            controllers: vec![],
+            processes: vec![],
+            control_actions: vec![],
+            feedback: vec![],
+        }
+    }
+
+    // Checks that the control structure is consistent in itself:
+    // all references resolve and every control action covers all four UCA types
+    pub fn check_structure(&self) -> Vec<String> {
+        let mut issues = vec![];
+        for h in &self.hazards {
+            for l in &h.losses {
+                if !self.losses.iter().any(|loss| &loss.id == l) {
+                    issues.push(format!("Hazard '{}' refers to unknown loss '{}'.", h.id, l));
+                }
+            }
+        }
+        for f in &self.feedback {
+            if !self.processes.iter().any(|p| p.name == f.process) {
+                issues.push(format!("Feedback '{}' comes from unknown process '{}'.", f.name, f.process));
+            }
+            if !self.controllers.iter().any(|c| c.name == f.controller) {
+                issues.push(format!("Feedback '{}' goes to unknown controller '{}'.", f.name, f.controller));
+            }
+        }
+        for ca in &self.control_actions {
+            if !self.controllers.iter().any(|c| c.name == ca.controller) {
+                issues.push(format!("Control action '{}' has unknown controller '{}'.", ca.name, ca.controller));
+            }
+            if !self.processes.iter().any(|p| p.name == ca.process) {
+                issues.push(format!("Control action '{}' has unknown process '{}'.", ca.name, ca.process));
+            }
+            for uca_type in UcaType::all() {
+                if !ca.unsafe_control_actions.iter().any(|uca| uca.uca_type == uca_type) {
+                    issues.push(format!("Control action '{}' has no '{}' UCA.", ca.name, uca_type));
+                }
+            }
+            for uca in &ca.unsafe_control_actions {
+                if uca.hazards.is_empty() {
+                    issues.push(format!("UCA '{}' is not linked to a hazard.", uca.id));
+                }
+                for h in &uca.hazards {
+                    if !self.hazards.iter().any(|hazard| &hazard.id == h) {
+                        issues.push(format!("UCA '{}' refers to unknown hazard '{}'.", uca.id, h));
+                    }
+                }
+            }
+        }
+        issues
+    }
+
+    // Checks the control structure against a model:
+    // - the UCA operations exist and issue the command of their control action
+    // - the feedback variables exist in the state
+    // - the UCA guard holds in every reachable state where one of the operations can start
+    pub fn check_model(&self, model: &Model, state: &State, max_depth: usize) -> Vec<String> {
+        let mut issues = vec![];
+        for f in &self.feedback {
+            for var in &f.variables {
+                if !state.state.contains_key(var) {
+                    issues.push(format!("Feedback '{}' uses unknown variable '{}'.", f.name, var));
+                }
+            }
+        }
+
+        let reachable = reachable_states(model, state, max_depth);
+        for ca in &self.control_actions {
+            let command_var = format!("{}_command_command", ca.device);
+            for uca in &ca.unsafe_control_actions {
+                if uca.operations.is_empty() {
+                    issues.push(format!("UCA '{}' is not linked to an operation.", uca.id));
+                }
+                for op_name in &uca.operations {
+                    let operation = match model.operations.iter().find(|o| &o.name == op_name) {
+                        Some(operation) => operation,
+                        None => {
+                            issues.push(format!("UCA '{}' refers to unknown operation '{}'.", uca.id, op_name));
+                            continue;
+                        }
+                    };
+
+                    if !operation.preconditions.iter().any(|t| {
+                        t.clone().take_planning(state).get_value(&command_var)
+                            == ca.command.to_spvalue()
+                    }) {
+                        issues.push(format!(
+                            "Operation '{}' doesn't issue the '{}' command to the {}.",
+                            op_name, ca.command, ca.device
+                        ));
+                    }
+
+                    if uca.uca_type != UcaType::Provided
+                        && uca.uca_type != UcaType::WrongTimingOrOrder
+                    {
+                        continue;
+                    }
+                    let guard = Transition::parse(
+                        &format!("guard_{}", uca.id),
+                        &uca.guard,
+                        "true",
+                        Vec::<&str>::new(),
+                        Vec::<&str>::new(),
+                        state,
+                    );
+                    if let Some(violating) = reachable.iter().find(|s| {
+                        operation.preconditions.iter().any(|t| t.clone().eval_planning(s))
+                            && !guard.clone().eval_planning(s)
+                    }) {
+                        let mut shown: Vec<String> = violating
+                            .state
+                            .iter()
+                            .filter(|(k, _)| uca.guard.contains(&format!("var:{} ", k)))
+                            .map(|(k, v)| format!("{} = {:?}", k, v.val))
+                            .collect();
+                        shown.sort();
+                        issues.push(format!(
+                            "Operation '{}' can start while the guard of UCA '{}' is violated: {}.",
+                            op_name,
+                            uca.id,
+                            shown.join(", ")
+                        ));
+                    }
+                }
+            }
+        }
+        issues
+    }
+}
+
+// Explores the states that the planner can reach by running the operations
+// from start to completion or failure, up to max_depth operations deep
+pub fn reachable_states(model: &Model, state: &State, max_depth: usize) -> Vec<State> {
+    let mut visited = vec![state.clone()];
+    let mut frontier = vec![state.clone()];
+    for _ in 0..max_depth {
+        let mut next_frontier = vec![];
+        for s in &frontier {
+            for o in &model.operations {
+                for pre in o.preconditions.iter().filter(|t| t.clone().eval_planning(s)) {
+                    let started = pre.clone().take_planning(s);
+                    for t in o.postconditions.iter().chain(o.fail_transitions.iter()) {
+                        if t.clone().eval_planning(&started) {
+                            let next = t.clone().take_planning(&started);
+                            if !visited.contains(&next) {
+                                visited.push(next.clone());
+                                next_frontier.push(next);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if next_frontier.is_empty() {
+            break;
+        }
+        frontier = next_frontier;
+    }
+    visited
+}
+
+#[test]
+fn test_check_structure() {
+    let mut cs = ControlStructure::new("test");
+    cs.losses.push(Loss {
+        id: "L1".to_string(),
+        description: "Damage to the gantry.".to_string(),
+    });
+    cs.hazards.push(Hazard {
+        id: "H1".to_string(),
+        description: "Gantry moves uncalibrated.".to_string(),
+        losses: vec!["L1".to_string(), "L2".to_string()],
+    });
+    cs.controllers.push(Controller {
+        name: "operation_runner".to_string(),
+        description: "".to_string(),
+    });
+    cs.processes.push(ControlledProcess {
+        name: "gantry".to_string(),
+        description: "".to_string(),
+    });
+    cs.control_actions.push(ControlAction {
+        name: "gantry_move".to_string(),
+        controller: "operation_runner".to_string(),
+        process: "gantry".to_string(),
+        device: "gantry".to_string(),
+        command: "move".to_string(),
+        unsafe_control_actions: vec![UnsafeControlAction::new(
+            "UCA1",
+            UcaType::Provided,
+            "Gantry moves before calibration.",
+            vec!["H1"],
+            vec!["op_gantry_move_to_home".to_string()],
+            "var:gantry_calibrated_estimated == true",
+        )],
+    });
+
+    let issues = cs.check_structure();
+    assert!(issues.contains(&"Hazard 'H1' refers to unknown loss 'L2'.".to_string()));
+    assert!(issues.contains(&"Control action 'gantry_move' has no 'not_provided' UCA.".to_string()));
+    assert_eq!(issues.len(), 4);
+}
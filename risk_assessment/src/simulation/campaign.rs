@@ -0,0 +1,383 @@
+use crate::*;
+use micro_sp::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration, Instant};
+
+// A campaign runs a list of goals against the emulated cell, each one a number of
+// times, and checks the outcomes against thresholds, so it can run unattended:
+//
+// name = "overnight"
+// repetitions = 20 # per case, defaults to NUMBER_OF_TEST_CASES
+// case_timeout = 120000 # milliseconds, defaults to 60000
+//
+// [thresholds]
+// min_completed_rate = 0.9 # share of the runs that have to complete, defaults to 1.0
+// max_timed_out = 0 # runs that may time out
+// max_observed_rpn = 300 # highest RPN of a failure mode that may be observed
+//
+// [[cases]]
+// goal = "var:robot_mounted_estimated == suction_tool"
+// profile = "profiles/worn_gripper.toml" # emulation profile, defaults to the initial emulation
+// repetitions = 5
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct CampaignThresholds {
+    pub min_completed_rate: Option<f64>,
+    pub max_timed_out: Option<u64>,
+    pub max_observed_rpn: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CampaignCase {
+    pub goal: String,
+    pub profile: Option<String>,
+    pub repetitions: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Campaign {
+    pub name: String,
+    pub repetitions: Option<u64>,
+    pub case_timeout: Option<u64>,
+    #[serde(default)]
+    pub thresholds: CampaignThresholds,
+    pub cases: Vec<CampaignCase>,
+}
+
+impl Campaign {
+    pub fn from_toml(toml: &str) -> Result<Campaign, String> {
+        let campaign: Campaign =
+            toml::from_str(toml).map_err(|e| format!("Invalid campaign: {}", e))?;
+        if campaign.cases.is_empty() {
+            return Err(format!("Campaign '{}' has no cases.", campaign.name));
+        }
+        Ok(campaign)
+    }
+
+    pub fn load(path: &str) -> Result<Campaign, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read campaign '{}': {}.", path, e))?;
+        Campaign::from_toml(&toml)
+    }
+
+    pub fn repetitions(&self, case: &CampaignCase) -> u64 {
+        case.repetitions
+            .unwrap_or(self.repetitions.unwrap_or(NUMBER_OF_TEST_CASES))
+    }
+
+    pub fn case_timeout(&self) -> u64 {
+        self.case_timeout.unwrap_or(60000)
+    }
+
+    // Checks the goals of all cases against the state before the campaign starts,
+    // an invalid goal would panic the planner or wait until the case times out
+    pub fn check_goals(&self, state: &State) -> Result<(), String> {
+        let errors: Vec<String> = self
+            .cases
+            .iter()
+            .enumerate()
+            .filter_map(|(case_nr, case)| {
+                parse_goal(&case.goal, state).err().map(|e| format!("Case {}: {}", case_nr + 1, e))
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(format!("Campaign '{}' has invalid goals. {}", self.name, errors.join(" "))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RunOutcome {
+    Completed,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+impl std::fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunOutcome::Completed => write!(f, "completed"),
+            RunOutcome::Failed => write!(f, "failed"),
+            RunOutcome::TimedOut => write!(f, "timed_out"),
+            RunOutcome::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignRun {
+    pub case: usize,
+    pub repetition: u64,
+    pub goal: String,
+    pub profile: Option<String>,
+    pub outcome: RunOutcome,
+    pub milliseconds: u64,
+    pub failures: u64,
+    // Highest RPN of the failure modes that were observed during the run
+    pub max_observed_rpn: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignReport {
+    pub name: String,
+    pub seed: Option<u64>,
+    pub thresholds: CampaignThresholds,
+    pub runs: Vec<CampaignRun>,
+}
+
+impl CampaignReport {
+    pub fn count(&self, outcome: RunOutcome) -> u64 {
+        self.runs.iter().filter(|run| run.outcome == outcome).count() as u64
+    }
+
+    pub fn completed_rate(&self) -> f64 {
+        match self.runs.len() {
+            0 => 0.0,
+            n => self.count(RunOutcome::Completed) as f64 / n as f64,
+        }
+    }
+
+    // The thresholds that were violated, empty if the campaign passed
+    pub fn violations(&self) -> Vec<String> {
+        let mut violations = vec![];
+        let min_completed_rate = self.thresholds.min_completed_rate.unwrap_or(1.0);
+        if self.completed_rate() < min_completed_rate {
+            violations.push(format!(
+                "Completed {:.1}% of the runs, expected at least {:.1}%.",
+                self.completed_rate() * 100.0,
+                min_completed_rate * 100.0
+            ));
+        }
+        if let Some(max_timed_out) = self.thresholds.max_timed_out {
+            if self.count(RunOutcome::TimedOut) > max_timed_out {
+                violations.push(format!(
+                    "{} runs timed out, expected at most {}.",
+                    self.count(RunOutcome::TimedOut),
+                    max_timed_out
+                ));
+            }
+        }
+        if let Some(max_observed_rpn) = self.thresholds.max_observed_rpn {
+            let observed = self.runs.iter().map(|run| run.max_observed_rpn).max().unwrap_or(0);
+            if observed > max_observed_rpn {
+                violations.push(format!(
+                    "Observed a failure mode with RPN {}, expected at most {}.",
+                    observed, max_observed_rpn
+                ));
+            }
+        }
+        violations
+    }
+
+    // 0 if the campaign passed, 1 if a threshold was violated
+    pub fn exit_code(&self) -> i32 {
+        match self.violations().is_empty() {
+            true => 0,
+            false => 1,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "seed": self.seed,
+            "completed": self.count(RunOutcome::Completed),
+            "failed": self.count(RunOutcome::Failed),
+            "timed_out": self.count(RunOutcome::TimedOut),
+            "completed_rate": self.completed_rate(),
+            "violations": self.violations(),
+            "runs": self.runs.iter().map(|run| json!({
+                "case": run.case,
+                "repetition": run.repetition,
+                "goal": run.goal,
+                "profile": run.profile,
+                "outcome": run.outcome.to_string(),
+                "milliseconds": run.milliseconds,
+                "failures": run.failures,
+                "max_observed_rpn": run.max_observed_rpn,
+            })).collect::<Vec<serde_json::Value>>(),
+        })
+    }
+
+    pub fn write(&self, directory: &str) -> Result<String, Box<dyn Error>> {
+        std::fs::create_dir_all(directory)?;
+        let path = format!(
+            "{}/{}_campaign_{}.json",
+            directory,
+            self.name,
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        );
+        std::fs::write(&path, serde_json::to_string_pretty(&self.to_json())?)?;
+        Ok(path)
+    }
+}
+
+// Submits the goal and waits until its plan is completed or failed. Every sampled
+// state is observed, the goal is cancelled when the observer returns false or when
+// it takes longer than the timeout. Used by the campaigns, the scenarios and the
+// goal action alike.
+pub async fn run_goal(
+    name: &str,
+    goal: &str,
+    timeout: Option<u64>,
+    ticker_rate: u64,
+    command_sender: &mpsc::Sender<Command>,
+    observe: &mut (dyn FnMut(&State) -> bool + Send),
+) -> Result<RunOutcome, Box<dyn Error>> {
+    let target = format!("{}_run_goal", name);
+    let state = get_state(command_sender).await?;
+    let modified_state = state.get_diff_partial_state(&submit_goal(name, &state, goal));
+    command_sender.send(Command::SetPartialState(modified_state)).await?;
+
+    let start = Instant::now();
+    let mut interval = interval(Duration::from_millis(ticker_rate));
+    loop {
+        interval.tick().await;
+        let state = get_state(command_sender).await?;
+        let proceed = observe(&state);
+        // The plan state of the previous goal is kept until the goal is replanned
+        let replanned = state.get_or_default_bool(&target, &format!("{}_replanned", name));
+        let plan_state = PlanState::from_str(
+            &state.get_or_default_string(&target, &format!("{}_plan_state", name)),
+        );
+        if replanned && plan_state == PlanState::Completed {
+            return Ok(RunOutcome::Completed);
+        }
+        if replanned && plan_state == PlanState::Failed {
+            return Ok(RunOutcome::Failed);
+        }
+        let outcome = match (proceed, timeout) {
+            (false, _) => RunOutcome::Cancelled,
+            (true, Some(timeout)) if start.elapsed() > Duration::from_millis(timeout) => RunOutcome::TimedOut,
+            _ => continue,
+        };
+        let modified_state = state.get_diff_partial_state(&cancel_goal(name, &state));
+        command_sender.send(Command::SetPartialState(modified_state)).await?;
+        return Ok(outcome);
+    }
+}
+
+// Runs the cases of the campaign one after the other. The emulation profile of a
+// case is applied to the initial state, see switch_emulation_profile.
+pub async fn run_campaign(
+    campaign: &Campaign,
+    name: &str,
+    initial_state: &State,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+) -> Result<CampaignReport, Box<dyn Error>> {
+    let mut report = CampaignReport {
+        name: campaign.name.clone(),
+        seed: None,
+        thresholds: campaign.thresholds.clone(),
+        runs: vec![],
+    };
+
+    for (case_nr, case) in campaign.cases.iter().enumerate() {
+        let profile = match &case.profile {
+            Some(path) => Some(EmulationProfile::load(path)?),
+            None => None,
+        };
+        let state = get_state(&command_sender).await?;
+        let new_state = switch_emulation_profile(initial_state, &state, profile.as_ref(), fmea, keys)?;
+        let modified_state = state.get_diff_partial_state(&new_state);
+        command_sender.send(Command::SetPartialState(modified_state)).await?;
+
+        for repetition in 1..=campaign.repetitions(case) {
+            log_warn!(
+                NODE_ID,
+                "Campaign '{}', case {} '{}', run {} of {}.",
+                campaign.name,
+                case_nr + 1,
+                case.goal,
+                repetition,
+                campaign.repetitions(case)
+            );
+            let log_at_start = get_log(&recorder_sender).await?;
+            let start = Instant::now();
+            let outcome = run_goal(name, &case.goal, Some(campaign.case_timeout()), ticker_rate, &command_sender, &mut |_| true).await?;
+            let milliseconds = start.elapsed().as_millis() as u64;
+            let log = get_log(&recorder_sender).await?.since(&log_at_start);
+
+            let max_observed_rpn = FmeaReport::new(fmea, &log)
+                .rows
+                .iter()
+                .filter(|row| row.observed_failures > 0)
+                .map(|row| row.rpn)
+                .max()
+                .unwrap_or(0);
+            log_warn!(NODE_ID, "Run {} in {} ms.", outcome, milliseconds);
+            report.runs.push(CampaignRun {
+                case: case_nr + 1,
+                repetition,
+                goal: case.goal.clone(),
+                profile: case.profile.clone(),
+                outcome,
+                milliseconds,
+                failures: log.failures.values().sum(),
+                max_observed_rpn,
+            });
+        }
+    }
+    Ok(report)
+}
+
+#[test]
+fn test_campaign_thresholds() {
+    let campaign = Campaign::from_toml(
+        r#"
+        name = "test"
+        repetitions = 3
+
+        [thresholds]
+        min_completed_rate = 0.5
+        max_timed_out = 0
+
+        [[cases]]
+        goal = "var:robot_mounted_estimated == suction_tool"
+
+        [[cases]]
+        goal = "var:gantry_calibrated_estimated == true"
+        repetitions = 1
+        "#,
+    )
+    .unwrap();
+    assert_eq!(campaign.repetitions(&campaign.cases[0]), 3);
+    assert_eq!(campaign.repetitions(&campaign.cases[1]), 1);
+
+    let state = crate::models::bt_test_endre::state::state();
+    assert!(campaign.check_goals(&state).is_ok());
+    let mut invalid = campaign.clone();
+    invalid.cases[1].goal = "var:gantry_calibrated_estimated ==".to_string();
+    assert!(invalid.check_goals(&state).is_err());
+
+    let run = |outcome: RunOutcome| CampaignRun {
+        case: 1,
+        repetition: 1,
+        goal: campaign.cases[0].goal.clone(),
+        profile: None,
+        outcome,
+        milliseconds: 1000,
+        failures: 0,
+        max_observed_rpn: 0,
+    };
+    let mut report = CampaignReport {
+        name: campaign.name.clone(),
+        seed: None,
+        thresholds: campaign.thresholds.clone(),
+        runs: vec![run(RunOutcome::Completed), run(RunOutcome::Failed)],
+    };
+    assert_eq!(report.exit_code(), 0);
+
+    report.runs.push(run(RunOutcome::TimedOut));
+    assert_eq!(report.violations().len(), 2);
+    assert_eq!(report.exit_code(), 1);
+}
@@ -0,0 +1,3 @@
+pub mod campaign;
+pub mod monte_carlo;
+pub mod scenario;
\ No newline at end of file
@@ -0,0 +1,359 @@
+use crate::*;
+use micro_sp::*;
+use crate::interfaces::msgs::Emulation;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationConfig {
+    pub runs: u64,
+    pub max_plan_depth: usize,
+    pub max_replans: u64,
+    // Percentage 0..100 that an operation fails, same as emulated_failure_rate
+    pub failure_rates: HashMap<String, i64>,
+    pub default_failure_rate: i64,
+    pub seed: Option<u64>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            runs: 1000,
+            max_plan_depth: 30,
+            max_replans: 3,
+            failure_rates: HashMap::new(),
+            default_failure_rate: 0,
+            seed: None,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn get_failure_rate(&self, operation: &str) -> i64 {
+        *self
+            .failure_rates
+            .get(operation)
+            .unwrap_or(&self.default_failure_rate)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedRun {
+    pub goal_reached: bool,
+    pub executed_operations: Vec<String>,
+    // (operation, cause) in the order they happened
+    pub failures: Vec<(String, String)>,
+    pub accumulated_severity: u64,
+    pub replans: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimulationResult {
+    pub runs: u64,
+    pub goal_reached: u64,
+    pub no_plan_found: u64,
+    pub failure_sequences: HashMap<Vec<(String, String)>, u64>,
+    pub accumulated_severity: HashMap<u64, u64>,
+    pub total_replans: u64,
+}
+
+impl SimulationResult {
+    pub fn probability_of_reaching_goal(&self) -> f64 {
+        match self.runs {
+            0 => 0.0,
+            n => self.goal_reached as f64 / n as f64,
+        }
+    }
+
+    pub fn mean_accumulated_severity(&self) -> f64 {
+        match self.runs {
+            0 => 0.0,
+            n => {
+                self.accumulated_severity
+                    .iter()
+                    .map(|(severity, count)| severity * count)
+                    .sum::<u64>() as f64
+                    / n as f64
+            }
+        }
+    }
+
+    pub fn max_accumulated_severity(&self) -> u64 {
+        *self.accumulated_severity.keys().max().unwrap_or(&0)
+    }
+
+    // Most frequent failure sequences first, the empty sequence is a run without failures
+    pub fn ranked_failure_sequences(&self) -> Vec<(Vec<(String, String)>, u64)> {
+        let mut sequences: Vec<(Vec<(String, String)>, u64)> = self
+            .failure_sequences
+            .iter()
+            .map(|(sequence, count)| (sequence.clone(), *count))
+            .collect();
+        sequences.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.len().cmp(&b.0.len())));
+        sequences
+    }
+
+    fn add(&mut self, run: &SimulatedRun) {
+        self.runs += 1;
+        if run.goal_reached {
+            self.goal_reached += 1;
+        }
+        self.total_replans += run.replans;
+        *self
+            .failure_sequences
+            .entry(run.failures.clone())
+            .or_insert(0) += 1;
+        *self
+            .accumulated_severity
+            .entry(run.accumulated_severity)
+            .or_insert(0) += 1;
+    }
+}
+
+// Plans with the bfs_operation_planner and then executes the plan in-process,
+// sampling failures instead of calling the devices. A failing operation takes
+// the fail transition of the sampled cause, it is retried while its precondition still holds and
+// the retries of the operation are not exhausted, otherwise a new plan is made from the current state.
+// Like in the emulators, the correlated causes follow the previous failure of the same device.
+pub fn simulate(
+    model: &Model,
+    fmea: &FmeaTable,
+    devices: &[String],
+    state: &State,
+    goal: &str,
+    config: &SimulationConfig,
+) -> Result<SimulationResult, String> {
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let state = state.update(&format!("{}_goal", model.name), goal.to_spvalue());
+    let keys = operation_emulation_keys(model, devices, &state);
+
+    let mut result = SimulationResult::default();
+    for _ in 0..config.runs {
+        match simulate_run(model, fmea, &keys, &state, config, &mut rng)? {
+            Some(run) => result.add(&run),
+            None => {
+                result.runs += 1;
+                result.no_plan_found += 1;
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Returns None if there was no plan to begin with. Operations without an emulation
+// key keep the previous cause of their own.
+pub fn simulate_run(
+    model: &Model,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+    state: &State,
+    config: &SimulationConfig,
+    rng: &mut StdRng,
+) -> Result<Option<SimulatedRun>, String> {
+    let goal = state.extract_goal(&model.name);
+    let mut state = state.clone();
+    let mut run = SimulatedRun {
+        goal_reached: false,
+        executed_operations: vec![],
+        failures: vec![],
+        accumulated_severity: 0,
+        replans: 0,
+    };
+
+    let mut plan = bfs_operation_planner(
+        state.clone(),
+        goal.clone(),
+        model.operations.clone(),
+        config.max_plan_depth,
+    );
+    if !plan.found {
+        return Ok(None);
+    }
+
+    let mut previous_causes: HashMap<String, Option<String>> = HashMap::new();
+    'replan: loop {
+        for op_name in plan.plan.clone() {
+            let operation = match model.operations.iter().find(|o| o.name == op_name) {
+                Some(operation) => operation,
+                None => break 'replan,
+            };
+
+            let mut retries = 0;
+            let mut completed = false;
+            loop {
+                let start = match operation
+                    .preconditions
+                    .iter()
+                    .find(|t| t.clone().eval_planning(&state))
+                {
+                    Some(start) => start,
+                    None => break,
+                };
+                state = start.clone().take_planning(&state);
+                run.executed_operations.push(operation.name.clone());
+
+                let device = match keys.get(&operation.name) {
+                    Some(key) => key.device.clone(),
+                    None => operation.name.clone(),
+                };
+                let previous_cause = previous_causes.entry(device).or_insert(None);
+                let emulation =
+                    operation_emulation(fmea, &operation.name, config.get_failure_rate(&operation.name));
+                let sampled = sample_failure(&emulation, previous_cause, rng)
+                    .map_err(|e| format!("Invalid emulation of '{}': {}", operation.name, e))?;
+                if let Some(cause) = sampled {
+                    let severity = fmea
+                        .get_failure_mode(&operation.name, &cause)
+                        .map(|fm| fm.severity)
+                        .unwrap_or(0);
+                    run.accumulated_severity += severity as u64;
+                    run.failures.push((operation.name.clone(), cause.clone()));
+                    // The runner picks the branch of the cause by {device}_failure_cause,
+                    // the simulation picks it by name and falls back to the generic one
+                    let branch = format!("fail_{}_on_{}", operation.name, cause);
+                    *previous_cause = Some(cause);
+                    let fail = match operation.fail_transitions.iter().find(|t| t.name == branch) {
+                        Some(fail) => Some(fail),
+                        None => operation
+                            .fail_transitions
+                            .iter()
+                            .find(|t| t.clone().eval_planning(&state)),
+                    };
+                    if let Some(fail) = fail {
+                        state = fail.clone().take_planning(&state);
+                    }
+                    retries += 1;
+                    if retries > operation.retries {
+                        break;
+                    }
+                } else {
+                    *previous_cause = None;
+                    if let Some(complete) = operation
+                        .postconditions
+                        .iter()
+                        .find(|t| t.clone().eval_planning(&state))
+                    {
+                        state = complete.clone().take_planning(&state);
+                    }
+                    completed = true;
+                    break;
+                }
+            }
+
+            // The operation failed for good or it can't be started anymore
+            if !completed {
+                if run.replans >= config.max_replans {
+                    break 'replan;
+                }
+                run.replans += 1;
+                plan = bfs_operation_planner(
+                    state.clone(),
+                    goal.clone(),
+                    model.operations.clone(),
+                    config.max_plan_depth,
+                );
+                if !plan.found {
+                    break 'replan;
+                }
+                continue 'replan;
+            }
+        }
+        break 'replan;
+    }
+
+    run.goal_reached = goal.eval(&state);
+    Ok(Some(run))
+}
+
+// The operation fails like an emulated device would, with the causes of its
+// failure modes weighted by their occurrence rating. Without failure modes,
+// the failure is generic and has no severity.
+fn operation_emulation(fmea: &FmeaTable, operation: &str, failure_rate: i64) -> Emulation {
+    let mut emulation = Emulation {
+        emulate_failure_rate: 2,
+        emulated_failure_rate: failure_rate.clamp(0, 100) as i32,
+        ..Default::default()
+    };
+    if let Some(op) = fmea.get_operation(operation) {
+        if !op.failure_modes.is_empty() {
+            emulation.emulate_failure_cause = 2;
+            emulation.emulated_failure_cause = op.failure_modes.iter().map(|fm| fm.cause.clone()).collect();
+            emulation.emulated_failure_cause_weights =
+                op.failure_modes.iter().map(|fm| fm.occurrence as u32).collect();
+        }
+    }
+    emulation
+}
+
+#[test]
+fn test_simulate_bt_test_endre() {
+    let state = crate::models::bt_test_endre::state::state();
+    let runner_vars = generate_runner_state_variables("bt_test_endre");
+    let state = state.extend(runner_vars, true);
+    let (model, state, fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let devices = get_model_entry("bt_test_endre").unwrap().devices();
+
+    let config = SimulationConfig {
+        runs: 100,
+        seed: Some(0),
+        ..Default::default()
+    };
+    let result = simulate(&model, &fmea, &devices, &state, "var:robot_position_estimated == b", &config).unwrap();
+    assert_eq!(result.probability_of_reaching_goal(), 1.0);
+    assert_eq!(result.max_accumulated_severity(), 0);
+
+    let config = SimulationConfig {
+        runs: 100,
+        default_failure_rate: 100,
+        seed: Some(0),
+        ..Default::default()
+    };
+    let result = simulate(&model, &fmea, &devices, &state, "var:robot_position_estimated == b", &config).unwrap();
+    assert_eq!(result.probability_of_reaching_goal(), 0.0);
+    assert!(result.mean_accumulated_severity() > 0.0);
+
+    let config = SimulationConfig {
+        runs: 1000,
+        default_failure_rate: 10,
+        seed: Some(0),
+        ..Default::default()
+    };
+    let first = simulate(&model, &fmea, &devices, &state, "var:robot_position_estimated == b", &config).unwrap();
+    let second = simulate(&model, &fmea, &devices, &state, "var:robot_position_estimated == b", &config).unwrap();
+    assert_eq!(first, second);
+    assert!(first.probability_of_reaching_goal() > 0.5);
+}
+
+#[test]
+fn test_simulate_collision_invalidates_calibration() {
+    let state = crate::models::bt_test_endre::state::state();
+    let state = state.extend(generate_runner_state_variables("bt_test_endre"), true);
+    let (model, state, mut fmea) =
+        crate::models::bt_test_endre::model::bt_test_endre("bt_test_endre", &state);
+    let state = state
+        .update("gantry_calibrated_estimated", true.to_spvalue())
+        .update("gantry_locked_estimated", false.to_spvalue())
+        .update("bt_test_endre_goal", "var:gantry_position_estimated == home".to_spvalue());
+
+    // The move always collides, the gantry has to be calibrated again before the next move
+    for op in fmea.operations.iter_mut().filter(|op| op.operation == "op_gantry_move_to_home") {
+        op.failure_modes.retain(|fm| fm.cause == "collision");
+    }
+    let config = SimulationConfig {
+        failure_rates: HashMap::from([("op_gantry_move_to_home".to_string(), 100)]),
+        ..Default::default()
+    };
+    let keys = operation_emulation_keys(&model, &get_model_entry("bt_test_endre").unwrap().devices(), &state);
+    let run = simulate_run(&model, &fmea, &keys, &state, &config, &mut StdRng::seed_from_u64(0))
+        .unwrap()
+        .unwrap();
+    assert!(!run.goal_reached);
+    assert_eq!(run.failures[0], ("op_gantry_move_to_home".to_string(), "collision".to_string()));
+    assert_eq!(run.executed_operations[0], "op_gantry_move_to_home");
+    assert!(run.executed_operations.contains(&"op_gantry_calibrate".to_string()));
+}
@@ -0,0 +1,352 @@
+use crate::*;
+use micro_sp::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use tokio::sync::mpsc;
+
+// A scenario describes one experiment, in TOML or in JSON:
+//
+// name = "worn_gripper_tool_change"
+// model = "bt_test_endre"
+// goals = ["var:robot_mounted_estimated == suction_tool"] # executed in order
+// goal_timeout = 60000 # milliseconds, defaults to 60000
+//
+// [initial_state] # variables set before the goals, typed like set_variables
+// gantry_calibrated_estimated = true
+// robot_mounted_estimated = "gripper_tool"
+//
+// [emulation.robot.commands.mount] # devices of an emulation profile, or profile = "profiles/..."
+// failure_probability = 30
+//
+// [expected]
+// must_reach = ["var:robot_mounted_estimated == suction_tool"] # have to hold at some point
+// forbidden = ["var:robot_holding_estimated == UNKNOWN"] # may never hold
+// max_failures = 3 # failed device requests
+//
+// The emulated world is not reset between scenarios.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+pub struct ScenarioExpectations {
+    #[serde(default)]
+    pub must_reach: Vec<String>,
+    #[serde(default)]
+    pub forbidden: Vec<String>,
+    pub max_failures: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub model: String,
+    pub goals: Vec<String>,
+    pub goal_timeout: Option<u64>,
+    #[serde(default)]
+    pub initial_state: BTreeMap<String, serde_json::Value>,
+    pub profile: Option<String>,
+    pub emulation: Option<BTreeMap<String, DeviceProfile>>,
+    #[serde(default)]
+    pub expected: ScenarioExpectations,
+}
+
+impl Scenario {
+    pub fn from_toml(toml: &str) -> Result<Scenario, String> {
+        toml::from_str(toml).map_err(|e| format!("Invalid scenario: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<Scenario, String> {
+        serde_json::from_str(json).map_err(|e| format!("Invalid scenario: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<Scenario, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read scenario '{}': {}.", path, e))?;
+        let scenario = match path.ends_with(".json") {
+            true => Scenario::from_json(&content),
+            false => Scenario::from_toml(&content),
+        }
+        .map_err(|e| format!("{} ({})", e, path))?;
+        if scenario.profile.is_some() && scenario.emulation.is_some() {
+            return Err(format!(
+                "Scenario '{}' has both a profile and an emulation, expected one of them.",
+                scenario.name
+            ));
+        }
+        Ok(scenario)
+    }
+
+    pub fn goal_timeout(&self) -> u64 {
+        self.goal_timeout.unwrap_or(60000)
+    }
+
+    pub fn emulation_profile(&self) -> Result<Option<EmulationProfile>, String> {
+        match (&self.profile, &self.emulation) {
+            (Some(path), _) => Ok(Some(EmulationProfile::load(path)?)),
+            (None, Some(devices)) => Ok(Some(EmulationProfile {
+                name: self.name.clone(),
+                devices: devices.clone(),
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // The state with the overrides of the scenario, the other variables keep their values
+    pub fn initial_state(&self, state: &State) -> Result<State, String> {
+        let names: Vec<String> = self.initial_state.keys().cloned().collect();
+        let values: Vec<String> = self.initial_state.values().map(|value| value.to_string()).collect();
+        set_variables(state, &names, &values)
+            .map_err(|e| format!("Scenario '{}': {}", self.name, e))
+    }
+
+    // The goals and the expected predicates that don't parse against the state,
+    // checked before the run since the parser panics on them
+    pub fn invalid_predicates(&self, state: &State) -> Vec<String> {
+        self.goals
+            .iter()
+            .chain(self.expected.must_reach.iter())
+            .chain(self.expected.forbidden.iter())
+            .filter_map(|predicate| parse_goal(predicate, state).err())
+            .collect()
+    }
+}
+
+// Every .toml and .json file in the directory, sorted by name
+pub fn load_scenarios(directory: &str) -> Result<Vec<Scenario>, String> {
+    let entries = std::fs::read_dir(directory)
+        .map_err(|e| format!("Can't read scenario directory '{}': {}.", directory, e))?;
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .filter(|path| path.ends_with(".toml") || path.ends_with(".json"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|path| Scenario::load(path)).collect()
+}
+
+fn predicate(name: &str, predicate: &str, state: &State) -> Transition {
+    Transition::parse(
+        &format!("{}_{}", name, predicate),
+        predicate,
+        "true",
+        Vec::<&str>::new(),
+        Vec::<&str>::new(),
+        state,
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioResult {
+    pub name: String,
+    pub goals: Vec<(String, RunOutcome)>,
+    pub failures: u64,
+    // Why the scenario failed, empty if it passed
+    pub reasons: Vec<String>,
+}
+
+impl ScenarioResult {
+    pub fn passed(&self) -> bool {
+        self.reasons.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "passed": self.passed(),
+            "goals": self.goals.iter().map(|(goal, outcome)| json!({
+                "goal": goal,
+                "outcome": outcome.to_string(),
+            })).collect::<Vec<serde_json::Value>>(),
+            "failures": self.failures,
+            "reasons": self.reasons,
+        })
+    }
+}
+
+// Sets the initial state and the emulation of the scenario, executes its goals in
+// order and checks the observed states and failures against the expectations.
+// Only the overridden variables and the emulation are set, so the runner variables
+// keep their values. The initial state is the one of the running model, before the
+// runner started, the emulation of the scenario is applied to it.
+pub async fn run_scenario(
+    scenario: &Scenario,
+    model: &Model,
+    initial_state: &State,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+    ticker_rate: u64,
+    command_sender: &mpsc::Sender<Command>,
+    recorder_sender: &mpsc::Sender<RecorderCommand>,
+) -> Result<ScenarioResult, Box<dyn Error>> {
+    let mut result = ScenarioResult {
+        name: scenario.name.clone(),
+        goals: vec![],
+        failures: 0,
+        reasons: vec![],
+    };
+    if scenario.model != model.name {
+        result.reasons.push(format!(
+            "Scenario is for model '{}', the runner has model '{}'.",
+            scenario.model, model.name
+        ));
+        return Ok(result);
+    }
+
+    let state = get_state(command_sender).await?;
+    let new_state = scenario.initial_state(&state)?;
+    result.reasons = scenario.invalid_predicates(&new_state);
+    if !result.passed() {
+        return Ok(result);
+    }
+    let profile = scenario.emulation_profile()?;
+    let new_state = switch_emulation_profile(initial_state, &new_state, profile.as_ref(), fmea, keys)?;
+    let modified_state = state.get_diff_partial_state(&new_state);
+    command_sender.send(Command::SetPartialState(modified_state)).await?;
+
+    let must_reach: Vec<(String, Transition)> = scenario
+        .expected
+        .must_reach
+        .iter()
+        .map(|p| (p.clone(), predicate("must_reach", p, &new_state)))
+        .collect();
+    let forbidden: Vec<(String, Transition)> = scenario
+        .expected
+        .forbidden
+        .iter()
+        .map(|p| (p.clone(), predicate("forbidden", p, &new_state)))
+        .collect();
+    let mut reached = vec![false; must_reach.len()];
+    let mut entered = vec![false; forbidden.len()];
+    let mut observe = |state: &State| {
+        for (i, (_, p)) in must_reach.iter().enumerate() {
+            reached[i] = reached[i] || p.clone().eval_planning(state);
+        }
+        for (i, (_, p)) in forbidden.iter().enumerate() {
+            entered[i] = entered[i] || p.clone().eval_planning(state);
+        }
+        true
+    };
+
+    let log_at_start = get_log(recorder_sender).await?;
+    for goal in &scenario.goals {
+        log_warn!(NODE_ID, "Scenario '{}': executing goal '{}'.", scenario.name, goal);
+        let outcome = run_goal(&model.name, goal, Some(scenario.goal_timeout()), ticker_rate, command_sender, &mut observe).await?;
+        if outcome != RunOutcome::Completed {
+            result.reasons.push(format!("Goal '{}' {}.", goal, outcome));
+        }
+        result.goals.push((goal.clone(), outcome));
+    }
+    result.failures = get_log(recorder_sender).await?.since(&log_at_start).failures.values().sum();
+
+    for (i, (p, _)) in must_reach.iter().enumerate() {
+        if !reached[i] {
+            result.reasons.push(format!("'{}' was never reached.", p));
+        }
+    }
+    for (i, (p, _)) in forbidden.iter().enumerate() {
+        if entered[i] {
+            result.reasons.push(format!("Forbidden '{}' was reached.", p));
+        }
+    }
+    if let Some(max_failures) = scenario.expected.max_failures {
+        if result.failures > max_failures {
+            result.reasons.push(format!(
+                "{} failures, expected at most {}.",
+                result.failures, max_failures
+            ));
+        }
+    }
+    Ok(result)
+}
+
+// Runs the scenarios one after the other and writes a report of all of them,
+// returns the results and the path of the report
+pub async fn run_scenarios(
+    scenarios: &[Scenario],
+    model: &Model,
+    initial_state: &State,
+    fmea: &FmeaTable,
+    keys: &HashMap<String, EmulationKey>,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
+    recorder_sender: mpsc::Sender<RecorderCommand>,
+) -> Result<(Vec<ScenarioResult>, String), Box<dyn Error>> {
+    let mut results = vec![];
+    for scenario in scenarios {
+        let result = run_scenario(scenario, model, initial_state, fmea, keys, ticker_rate, &command_sender, &recorder_sender).await?;
+        match result.passed() {
+            true => log_warn!(NODE_ID, "Scenario '{}' passed.", result.name),
+            false => log_error!(
+                NODE_ID,
+                "Scenario '{}' failed: {}",
+                result.name,
+                result.reasons.join(" ")
+            ),
+        };
+        results.push(result);
+    }
+
+    std::fs::create_dir_all(REPORT_DIRECTORY)?;
+    let path = format!(
+        "{}/{}_scenarios_{}.json",
+        REPORT_DIRECTORY,
+        model.name,
+        chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+    );
+    let report = json!({
+        "model": model.name,
+        "passed": results.iter().filter(|r| r.passed()).count(),
+        "failed": results.iter().filter(|r| !r.passed()).count(),
+        "scenarios": results.iter().map(|r| r.to_json()).collect::<Vec<serde_json::Value>>(),
+    });
+    std::fs::write(&path, serde_json::to_string_pretty(&report)?)?;
+    Ok((results, path))
+}
+
+#[test]
+fn test_scenario_initial_state() {
+    let scenario = Scenario::from_toml(
+        r#"
+        name = "tool_change"
+        model = "bt_test_endre"
+        goals = ["var:robot_mounted_estimated == suction_tool"]
+
+        [initial_state]
+        gantry_calibrated_estimated = true
+        robot_mounted_estimated = "gripper_tool"
+
+        [emulation.robot.commands.mount]
+        failure_probability = 30
+
+        [expected]
+        forbidden = ["var:robot_holding_estimated == UNKNOWN"]
+        max_failures = 3
+        "#,
+    )
+    .unwrap();
+
+    let state = crate::models::bt_test_endre::state::state();
+    let state = state.update("gantry_position_estimated", "a".to_spvalue());
+    let state = scenario.initial_state(&state).unwrap();
+    assert_eq!(state.get_value("gantry_calibrated_estimated"), true.to_spvalue());
+    assert_eq!(state.get_value("robot_mounted_estimated"), "gripper_tool".to_spvalue());
+    assert_eq!(state.get_value("gantry_position_estimated"), "a".to_spvalue());
+    assert_eq!(scenario.emulation_profile().unwrap().unwrap().devices.len(), 1);
+    assert_eq!(scenario.expected.max_failures, Some(3));
+
+    let scenario = Scenario {
+        initial_state: BTreeMap::from([("no_such_variable".to_string(), json!(1))]),
+        ..scenario
+    };
+    assert!(scenario.initial_state(&state).is_err());
+
+    assert!(scenario.invalid_predicates(&state).is_empty());
+    let scenario = Scenario {
+        goals: vec!["var:robot_mounted_estimated == suction_tool".to_string(), "".to_string()],
+        expected: ScenarioExpectations {
+            must_reach: vec!["var:robot_holding_estimated ==".to_string()],
+            ..scenario.expected
+        },
+        ..scenario
+    };
+    assert_eq!(scenario.invalid_predicates(&state).len(), 2);
+}
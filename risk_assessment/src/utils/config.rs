@@ -0,0 +1,192 @@
+use crate::*;
+use serde::Deserialize;
+
+// How the runner is started. The defaults are overridden by a TOML file, then by
+// the environment variables, then by the command line, i.e.
+//
+// risk_assessment --model minimal --client-ticker-rate 50 --no-emulators
+// risk_assessment --config runner.toml --scenario scenarios/tool_change.toml
+//
+// model = "bt_test_endre"
+// test_ticker_rate = 1000 # milliseconds
+// client_ticker_rate = 100 # milliseconds
+// publisher_ticker_rate = 100 # milliseconds
+// emulators = true # false talks to the real devices
+// coverability_tracking = false
+// scenario = "scenarios" # a scenario file or a directory of them, replaces the single test
+// campaign = "campaigns/overnight.toml" # replaces the single test
+// emulation_profile = "profiles/worn_gripper.toml"
+// state_publisher_mode = "diff"
+//
+// The emulation seed is still set with EMULATION_SEED.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunnerConfig {
+    pub model: String,
+    pub test_ticker_rate: u64,
+    pub client_ticker_rate: u64,
+    pub publisher_ticker_rate: u64,
+    pub emulators: bool,
+    pub coverability_tracking: bool,
+    pub scenario: Option<String>,
+    pub campaign: Option<String>,
+    pub emulation_profile: Option<String>,
+    pub state_publisher_mode: Option<String>,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> RunnerConfig {
+        RunnerConfig {
+            model: "bt_test_endre".to_string(),
+            test_ticker_rate: TEST_TICKER_RATE,
+            client_ticker_rate: CLIENT_TICKER_RATE,
+            publisher_ticker_rate: PUBLISHER_TICKER_RATE,
+            emulators: true,
+            coverability_tracking: false,
+            scenario: None,
+            campaign: None,
+            emulation_profile: None,
+            state_publisher_mode: None,
+        }
+    }
+}
+
+pub static RUNNER_USAGE: &'static str = "Usage: risk_assessment [OPTIONS] [--ros-args ...]
+
+Options:
+  --config <PATH>                 TOML file with the options below
+  --model <NAME>                  Model to run, see --list-models
+  --test-ticker-rate <MS>         Tick rate of the test generator
+  --client-ticker-rate <MS>       Tick rate of the device client tickers
+  --publisher-ticker-rate <MS>    Tick rate of the state publisher
+  --no-emulators                  Don't start the emulators, use the real devices
+  --coverability-tracking         Track the coverability of the operations
+  --scenario <PATH>               Scenario file or directory, replaces the single test
+  --campaign <PATH>               Campaign file, replaces the single test
+  --emulation-profile <PATH>      Emulation profile
+  --state-publisher-mode <MODE>   full or diff
+  --list-models                   Print the available models
+  --help                          Print this help";
+
+impl RunnerConfig {
+    pub fn from_toml(toml: &str) -> Result<RunnerConfig, String> {
+        toml::from_str(toml).map_err(|e| format!("Invalid runner config: {}", e))
+    }
+
+    pub fn load(path: &str) -> Result<RunnerConfig, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read runner config '{}': {}.", path, e))?;
+        RunnerConfig::from_toml(&toml)
+    }
+
+    // The variables that were used before the command line options, i.e. CAMPAIGN=...
+    pub fn apply_env(self) -> RunnerConfig {
+        let var = |name: &str| std::env::var(name).ok();
+        RunnerConfig {
+            scenario: var("SCENARIOS").or(self.scenario),
+            campaign: var("CAMPAIGN").or(self.campaign),
+            emulation_profile: var("EMULATION_PROFILE").or(self.emulation_profile),
+            state_publisher_mode: var("STATE_PUBLISHER_MODE").or(self.state_publisher_mode),
+            ..self
+        }
+    }
+
+    // Arguments after --ros-args are left to ROS
+    pub fn apply_args(self, args: &[String]) -> Result<RunnerConfig, String> {
+        let mut config = self;
+        let mut args = args.iter().take_while(|arg| arg.as_str() != "--ros-args");
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for '{}'.", arg))
+            };
+            let rate = |value: String| {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid tick rate '{}' for '{}', expected milliseconds.", value, arg))
+            };
+            match arg.as_str() {
+                "--model" => config.model = value()?,
+                "--test-ticker-rate" => config.test_ticker_rate = rate(value()?)?,
+                "--client-ticker-rate" => config.client_ticker_rate = rate(value()?)?,
+                "--publisher-ticker-rate" => config.publisher_ticker_rate = rate(value()?)?,
+                "--no-emulators" => config.emulators = false,
+                "--coverability-tracking" => config.coverability_tracking = true,
+                "--scenario" => config.scenario = Some(value()?),
+                "--campaign" => config.campaign = Some(value()?),
+                "--emulation-profile" => config.emulation_profile = Some(value()?),
+                "--state-publisher-mode" => config.state_publisher_mode = Some(value()?),
+                // Read before the other options, see from_args
+                "--config" => {
+                    value()?;
+                }
+                _ => return Err(format!("Unknown option '{}'.\n\n{}", arg, RUNNER_USAGE)),
+            }
+        }
+        Ok(config)
+    }
+
+    // The configuration of the runner, args are the command line without the program
+    pub fn from_args(args: &[String]) -> Result<RunnerConfig, String> {
+        let config = match args.iter().position(|arg| arg == "--config") {
+            Some(i) => match args.get(i + 1) {
+                Some(path) => RunnerConfig::load(path)?,
+                None => return Err("Missing value for '--config'.".to_string()),
+            },
+            None => RunnerConfig::default(),
+        };
+        let config = config.apply_env().apply_args(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        get_model_entry(&self.model)?;
+        for (option, rate) in [
+            ("test_ticker_rate", self.test_ticker_rate),
+            ("client_ticker_rate", self.client_ticker_rate),
+            ("publisher_ticker_rate", self.publisher_ticker_rate),
+        ] {
+            if rate == 0 {
+                return Err(format!("{} has to be at least 1 millisecond.", option));
+            }
+        }
+        if self.scenario.is_some() && self.campaign.is_some() {
+            return Err("Both a scenario and a campaign are set, expected one of them.".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_runner_config() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+    let config = RunnerConfig::from_toml(
+        r#"
+        model = "minimal"
+        client_ticker_rate = 50
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.test_ticker_rate, TEST_TICKER_RATE);
+    assert!(config.emulators);
+
+    let config = config
+        .apply_args(&args(&[
+            "--client-ticker-rate", "20", "--no-emulators", "--scenario", "scenarios",
+            "--ros-args", "--no-emulators-for-ros",
+        ]))
+        .unwrap();
+    assert_eq!(config.model, "minimal");
+    assert_eq!(config.client_ticker_rate, 20);
+    assert!(!config.emulators);
+    assert_eq!(config.scenario, Some("scenarios".to_string()));
+    assert!(config.validate().is_ok());
+
+    assert!(RunnerConfig::default().apply_args(&args(&["--model"])).is_err());
+    assert!(RunnerConfig::default().apply_args(&args(&["--client-ticker-rate", "fast"])).is_err());
+    assert!(RunnerConfig::default().apply_args(&args(&["--model", "nope"])).unwrap().validate().is_err());
+    assert!(RunnerConfig::from_toml("tick_rate = 10").is_err());
+}
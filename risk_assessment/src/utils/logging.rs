@@ -0,0 +1,38 @@
+// Log with the logger name first, like r2r::log_info!(NODE_ID, "..."). With the ros
+// feature the messages go to the ROS 2 logger, without it to the log crate, see
+// initialize_env_logger.
+#[cfg(feature = "ros")]
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => { r2r::log_info!($($arg)+) };
+}
+
+#[cfg(feature = "ros")]
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => { r2r::log_warn!($($arg)+) };
+}
+
+#[cfg(feature = "ros")]
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => { r2r::log_error!($($arg)+) };
+}
+
+#[cfg(not(feature = "ros"))]
+#[macro_export]
+macro_rules! log_info {
+    ($logger:expr, $($arg:tt)+) => { log::info!(target: AsRef::<str>::as_ref($logger), $($arg)+) };
+}
+
+#[cfg(not(feature = "ros"))]
+#[macro_export]
+macro_rules! log_warn {
+    ($logger:expr, $($arg:tt)+) => { log::warn!(target: AsRef::<str>::as_ref($logger), $($arg)+) };
+}
+
+#[cfg(not(feature = "ros"))]
+#[macro_export]
+macro_rules! log_error {
+    ($logger:expr, $($arg:tt)+) => { log::error!(target: AsRef::<str>::as_ref($logger), $($arg)+) };
+}
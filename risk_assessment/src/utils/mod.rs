@@ -1,2 +1,4 @@
+pub mod config;
+pub mod logging;
 pub mod state_publisher;
 pub mod env_logger;
\ No newline at end of file
@@ -1,30 +1,101 @@
 use micro_sp::{State, SPValue};
-use r2r::{std_msgs::msg::String as StringMsg, QosProfile};
 use serde_json::Value;
-use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 
-use crate::*;
+// Only the publisher needs ROS 2, the conversions are also used by the services
+#[cfg(feature = "ros")]
+use micro_sp::Command;
+#[cfg(feature = "ros")]
+use r2r::{std_msgs::msg::String as StringMsg, QosProfile};
+#[cfg(feature = "ros")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "ros")]
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatePublisherMode {
+    // The whole state on every tick, on /state
+    Full,
+    // Only the variables that changed since the last message, on /state_diff.
+    // The first message has the whole state, nothing is published if nothing changed.
+    Diff,
+}
+
+impl StatePublisherMode {
+    // i.e. --state-publisher-mode diff
+    pub fn parse(mode: &str) -> Result<StatePublisherMode, String> {
+        match mode {
+            "full" => Ok(StatePublisherMode::Full),
+            "diff" => Ok(StatePublisherMode::Diff),
+            _ => Err(format!("Unknown state publisher mode '{}', expected full or diff.", mode)),
+        }
+    }
+
+    pub fn topic(&self) -> &'static str {
+        match self {
+            StatePublisherMode::Full => "/state",
+            StatePublisherMode::Diff => "/state_diff",
+        }
+    }
+}
 
+// UNKNOWN is published as null, times as their debug string
+pub fn spvalue_to_json(value: &SPValue) -> Value {
+    match value {
+        SPValue::Array(_, values) => Value::Array(values.iter().map(spvalue_to_json).collect()),
+        SPValue::Bool(val) => Value::from(*val),
+        SPValue::Float64(val) => Value::from(val.into_inner()),
+        SPValue::String(val) => Value::from(val.clone()),
+        SPValue::Int64(val) => Value::from(*val),
+        SPValue::Time(val) => Value::from(format!("{:?}", val)),
+        SPValue::UNKNOWN => Value::Null,
+    }
+}
+
+pub fn state_to_json(state: &State) -> Value {
+    let mut map = serde_json::Map::new();
+    state.state.iter().for_each(|(k, v)| {
+        let _ = map.insert(k.to_string(), spvalue_to_json(&v.val));
+    });
+    Value::Object(map)
+}
+
+// The variables of the state that are new or have changed since the previous one
+pub fn state_diff_to_json(previous: &State, state: &State) -> Option<Value> {
+    let mut map = serde_json::Map::new();
+    state.state.iter().for_each(|(k, v)| {
+        let changed = match previous.state.get(k) {
+            Some(p) => p.val != v.val,
+            None => true,
+        };
+        if changed {
+            let _ = map.insert(k.to_string(), spvalue_to_json(&v.val));
+        }
+    });
+    match map.is_empty() {
+        true => None,
+        false => Some(Value::Object(map)),
+    }
+}
+
+#[cfg(feature = "ros")]
 pub async fn spawn_state_publisher(
     arc_node: Arc<Mutex<r2r::Node>>,
-    shared_state: &Arc<(Mutex<State>, Vec<AtomicUsize>)>,//HashMap<String, AtomicUsize>)>,
+    mode: StatePublisherMode,
+    ticker_rate: u64,
+    command_sender: mpsc::Sender<Command>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let publisher = arc_node
         .lock()
         .unwrap()
-        .create_publisher::<StringMsg>(
-            "/state",
-            QosProfile::default(),
-        )?;
+        .create_publisher::<StringMsg>(mode.topic(), QosProfile::default())?;
 
     let timer = arc_node
         .lock()
         .unwrap()
-        .create_wall_timer(std::time::Duration::from_millis(PUBLISHER_TICKER_RATE))?;
+        .create_wall_timer(std::time::Duration::from_millis(ticker_rate))?;
 
-    let shared_state_clone = shared_state.clone();
     tokio::task::spawn(async move {
-        match state_publisher(publisher, timer, &shared_state_clone).await {
+        match state_publisher(publisher, timer, mode, command_sender).await {
             Ok(()) => r2r::log_info!("state_publisher", "Succeeded."),
             Err(e) => r2r::log_error!("state_publisher", "Failed with: '{}'.", e),
         };
@@ -32,37 +103,67 @@ pub async fn spawn_state_publisher(
     Ok(())
 }
 
+#[cfg(feature = "ros")]
 pub async fn state_publisher(
     publisher: r2r::Publisher<StringMsg>,
     mut timer: r2r::Timer,
-    shared_state: &Arc<(Mutex<State>, Vec<AtomicUsize>)>,//HashMap<String, AtomicUsize>)>,
+    mode: StatePublisherMode,
+    command_sender: mpsc::Sender<Command>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    r2r::log_info!("state_publisher", "Publishing on {}.", mode.topic());
+    let mut previous: Option<State> = None;
     loop {
-        let shsl = shared_state.0.lock().unwrap().clone();
-        let mut map = serde_json::Map::new();
-        shsl.state.iter().for_each(|(k, v)| {
-            let _ = map.insert(k.to_string(), match &v.val {
-                SPValue::Array(_, val) => Value::from(format!("{:?}", val)),
-                SPValue::Bool(val) => Value::from(format!("{}", val)),
-                SPValue::Float64(val) => Value::from(format!("{}", val)),
-                SPValue::String(val) => Value::from(format!("{}", val)),
-                SPValue::Int64(val) => Value::from(format!("{}", val)),
-                SPValue::Time(val) => Value::from(format!("{:?}", val)),
-                SPValue::UNKNOWN => Value::from(format!("UNKNOWN")),
-            });
-        });
-
-        let state = serde_json::Value::Object(map).to_string();
-        let state_msg = StringMsg {
-            data: state
-        };
+        let (response_tx, response_rx) = oneshot::channel();
+        command_sender.send(Command::GetState(response_tx)).await?;
+        let state = response_rx.await?;
 
-        match publisher.publish(&state_msg) {
-            Ok(()) => (),
-            Err(e) => {
-                r2r::log_error!("state_publisher", "Failed to send a message with: '{}'", e);
-            }
+        let message = match (mode, &previous) {
+            (StatePublisherMode::Diff, Some(previous)) => state_diff_to_json(previous, &state),
+            _ => Some(state_to_json(&state)),
         };
+        previous = Some(state);
+
+        if let Some(message) = message {
+            let state_msg = StringMsg {
+                data: message.to_string(),
+            };
+            match publisher.publish(&state_msg) {
+                Ok(()) => (),
+                Err(e) => {
+                    r2r::log_error!("state_publisher", "Failed to send a message with: '{}'", e);
+                }
+            };
+        }
         timer.tick().await?;
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_state_to_json() {
+    use micro_sp::*;
+
+    let state = State::new()
+        .add(assign!(bv!("locked"), true.to_spvalue()))
+        .add(assign!(iv!("counter"), 3.to_spvalue()))
+        .add(assign!(v!("position"), SPValue::UNKNOWN))
+        .add(assign!(
+            av!("causes"),
+            SPValue::Array(SPValueType::String, vec!["collision".to_spvalue()])
+        ));
+    assert_eq!(
+        state_to_json(&state),
+        serde_json::json!({
+            "locked": true,
+            "counter": 3,
+            "position": null,
+            "causes": ["collision"]
+        })
+    );
+
+    assert_eq!(state_diff_to_json(&state, &state), None);
+    let new_state = state.update("counter", 4.to_spvalue());
+    assert_eq!(
+        state_diff_to_json(&state, &new_state),
+        Some(serde_json::json!({"counter": 4}))
+    );
+}